@@ -1,11 +1,10 @@
 use gerevs::{
     auth::username_password_authenticator::{UserAuthenticator, UsernamePasswordAuthenticator},
     method_handlers::{TunnelAssociate, TunnelBind, TunnelConnect},
-    Socks5Socket,
+    Socks5Server,
 };
 use std::error::Error;
-use tokio::net::{TcpListener, TcpStream};
-use tracing::{debug, span, warn, Instrument, Level};
+use tokio::net::TcpListener;
 use tracing_subscriber::EnvFilter;
 
 #[tokio::main]
@@ -14,33 +13,21 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .with_env_filter(EnvFilter::from_default_env())
         .init();
 
-    let server = TcpListener::bind("0.0.0.0:8080").await?;
-    loop {
-        let (client, _addr) = server.accept().await?;
-        debug!("Received connection from: {:?}", _addr);
-        let connection = span!(Level::INFO, "connection", %_addr);
-
-        tokio::spawn(
-            async move {
-                let result = handle_connection(client).await;
-                if let Err(err) = result {
-                    warn!("Failed connection: {:?}", err);
-                }
-            }
-            .instrument(connection),
-        );
-    }
-}
-
-async fn handle_connection(client: TcpStream) -> gerevs::Result<()> {
-    let mut socks5_stream = Socks5Socket::new(
-        client,
-        UsernamePasswordAuthenticator::new(SimpleUserAuthenticator),
-        TunnelConnect,
-        TunnelBind,
-        TunnelAssociate,
+    let listener = TcpListener::bind("0.0.0.0:8080").await?;
+    let server = Socks5Server::new(
+        listener,
+        || UsernamePasswordAuthenticator::new(SimpleUserAuthenticator),
+        TunnelConnect::new,
+        TunnelBind::new,
+        TunnelAssociate::new,
     );
-    socks5_stream.run().await
+
+    server
+        .run_until(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await?;
+    Ok(())
 }
 
 struct SimpleUserAuthenticator;
@@ -0,0 +1,200 @@
+//! # Access Log Module
+//!
+//! This module provides `AccessLog`, a `SessionObserver` writing one line per session, like the
+//! access log of a web server. Every line holds the client address, the user the client
+//! authenticated as, the command, the requested destination, the resolved destination, the reply
+//! code, the bytes relayed in each direction and the duration of the session. Lines are written
+//! when the session ends, either in a common-log style text format or as JSON lines, see
+//! `LogFormat`.
+//!
+//! The log can be written to any `io::Write`, `RotatingFile` writes it to a file rotated once it
+//! reaches a size limit.
+//!
+//! ## Example
+//!
+//! ```rust,no_run
+//! use std::sync::Arc;
+//!
+//! use gerevs::{
+//!     access_log::{AccessLog, LogFormat, RotatingFile},
+//!     auth::NoAuthAuthenticator,
+//!     method_handlers::{TunnelAssociate, TunnelBind, TunnelConnect},
+//!     Socks5Server,
+//! };
+//! use tokio::net::TcpListener;
+//!
+//! # async fn run() -> gerevs::Result<()> {
+//! let file = RotatingFile::open("/var/log/gerevs/access.log")?
+//!     .with_max_size(100 * 1024 * 1024)
+//!     .with_max_files(10);
+//! let access_log = AccessLog::new(file, LogFormat::Json);
+//!
+//! let listener = TcpListener::bind("0.0.0.0:1080").await?;
+//! let server = Socks5Server::new(
+//!     listener,
+//!     || NoAuthAuthenticator,
+//!     TunnelConnect::new,
+//!     TunnelBind::new,
+//!     TunnelAssociate::new,
+//! )
+//! .with_observer(Arc::new(access_log));
+//! server.run().await
+//! # }
+//! ```
+
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    net::SocketAddr,
+    sync::{Mutex, MutexGuard},
+    time::{Instant, SystemTime},
+};
+
+use tracing::warn;
+
+use crate::{
+    observer::{Command, RelayStats, Reply, SessionId, SessionObserver, SocksSocketAddr},
+    Socks5Error,
+};
+
+mod format;
+mod rotation;
+
+pub use rotation::RotatingFile;
+
+/// The format of the lines of an `AccessLog`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Space separated fields in the spirit of the Common Log Format, missing fields are `-`:
+    ///
+    /// `client - user [time] "COMMAND destination" reply bytes_up bytes_down duration_ms resolved`
+    ///
+    /// e.g. `10.0.0.7:51234 - alice [17/Oct/2026:02:48:13 +0000] "CONNECT example.com:443" 0 517
+    /// 6120 843 93.184.215.14:443`
+    ///
+    /// Spaces, quotes, backslashes, control and non-ASCII bytes in the user and the destination,
+    /// which the client chooses, are escaped as `\xNN`.
+    #[default]
+    Common,
+    /// One JSON object per line, with the fields of the common format plus the session id and the
+    /// error that ended the session. Missing fields are `null`.
+    Json,
+}
+
+/// What's known of a session, accumulated from its events until it ends.
+struct Entry {
+    started_at: SystemTime,
+    started: Instant,
+    client: Option<SocketAddr>,
+    user: Option<String>,
+    command: Option<Command>,
+    destination: Option<SocksSocketAddr>,
+    resolved: Option<SocketAddr>,
+    reply: Option<Reply>,
+    stats: RelayStats,
+}
+
+impl Entry {
+    fn new(client: Option<SocketAddr>) -> Self {
+        Self {
+            started_at: SystemTime::now(),
+            started: Instant::now(),
+            client,
+            user: None,
+            command: None,
+            destination: None,
+            resolved: None,
+            reply: None,
+            stats: RelayStats::default(),
+        }
+    }
+}
+
+/// The `AccessLog` struct is a `SessionObserver` writing a line to its writer for every session
+/// once it ends. Write errors are logged and otherwise ignored, they never affect the sessions.
+///
+/// Lines are written synchronously by the session that ended, so the writer should be a file or
+/// similarly fast.
+pub struct AccessLog {
+    format: LogFormat,
+    writer: Mutex<Box<dyn Write + Send>>,
+    sessions: Mutex<HashMap<SessionId, Entry>>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+impl AccessLog {
+    /// Creates a new `AccessLog` writing lines of `format` to `writer`.
+    pub fn new<W>(writer: W, format: LogFormat) -> Self
+    where
+        W: Write + Send + 'static,
+    {
+        Self {
+            format,
+            writer: Mutex::new(Box::new(writer)),
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Calls `update` with the entry of `session`, if the session is logged.
+    fn update<F>(&self, session: SessionId, update: F)
+    where
+        F: FnOnce(&mut Entry),
+    {
+        if let Some(entry) = lock(&self.sessions).get_mut(&session) {
+            update(entry);
+        }
+    }
+}
+
+impl SessionObserver for AccessLog {
+    fn connection_accepted(&self, session: SessionId, peer_addr: Option<SocketAddr>) {
+        lock(&self.sessions).insert(session, Entry::new(peer_addr));
+    }
+
+    fn auth_succeeded(&self, session: SessionId, user: Option<&str>) {
+        self.update(session, |entry| entry.user = user.map(str::to_string));
+    }
+
+    fn request_parsed(&self, session: SessionId, command: Command, destination: &SocksSocketAddr) {
+        self.update(session, |entry| {
+            entry.command = Some(command);
+            entry.destination = Some(destination.clone());
+        });
+    }
+
+    fn destination_connected(&self, session: SessionId, destination: SocketAddr) {
+        self.update(session, |entry| entry.resolved = Some(destination));
+    }
+
+    fn reply_sent(&self, session: SessionId, reply: Reply, _bound_addr: &SocksSocketAddr) {
+        self.update(session, |entry| entry.reply = Some(reply));
+    }
+
+    fn relay_finished(&self, session: SessionId, stats: &RelayStats, _error: Option<&Socks5Error>) {
+        self.update(session, |entry| entry.stats = *stats);
+    }
+
+    fn connection_closed(&self, session: SessionId, error: Option<&Socks5Error>) {
+        let Some(mut entry) = lock(&self.sessions).remove(&session) else {
+            return;
+        };
+        entry.stats.duration = entry.started.elapsed();
+
+        let mut line = match self.format {
+            LogFormat::Common => format::common(&entry),
+            LogFormat::Json => format::json(session, &entry, error),
+        };
+        line.push('\n');
+
+        let mut writer = lock(&self.writer);
+        let res: io::Result<()> = writer
+            .write_all(line.as_bytes())
+            .and_then(|()| writer.flush());
+        if let Err(err) = res {
+            warn!("Failed to write access log: {}", err);
+        }
+    }
+}
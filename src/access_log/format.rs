@@ -0,0 +1,193 @@
+use std::{
+    fmt::{Display, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    observer::{Command, SessionId},
+    Socks5Error,
+};
+
+use super::Entry;
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// A UTC date and time, precise to the millisecond.
+struct DateTime {
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: u64,
+    minute: u64,
+    second: u64,
+    millis: u32,
+}
+
+impl DateTime {
+    fn from_system_time(time: SystemTime) -> Self {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let secs = since_epoch.as_secs();
+        let (year, month, day) = civil_from_days((secs / 86400) as i64);
+        let secs_of_day = secs % 86400;
+
+        Self {
+            year,
+            month,
+            day,
+            hour: secs_of_day / 3600,
+            minute: secs_of_day % 3600 / 60,
+            second: secs_of_day % 60,
+            millis: since_epoch.subsec_millis(),
+        }
+    }
+}
+
+/// Converts days since the unix epoch to a (year, month, day) date of the proleptic Gregorian
+/// calendar, see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn command_name(command: Command) -> &'static str {
+    match command {
+        Command::Connect => "CONNECT",
+        Command::Bind => "BIND",
+        Command::UdpAssociate => "UDP_ASSOCIATE",
+    }
+}
+
+/// Formats `value`, or `-` if it's missing.
+fn or_dash<T: Display>(value: Option<T>) -> String {
+    value.map_or_else(|| "-".to_string(), |value| value.to_string())
+}
+
+/// Formats a client-supplied `value`, or `-` if it's missing. Bytes that would shift the following
+/// fields or forge lines, i.e. spaces, quotes, backslashes, control and non-ASCII bytes, are escaped
+/// as `\xNN` like nginx and Apache do.
+fn escaped_or_dash<T: Display>(value: Option<T>) -> String {
+    let Some(value) = value else {
+        return "-".to_string();
+    };
+
+    let mut escaped = String::new();
+    for byte in value.to_string().bytes() {
+        match byte {
+            0x21..=0x7e if byte != b'"' && byte != b'\\' => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\x{:02X}", byte)),
+        }
+    }
+    escaped
+}
+
+/// Formats `entry` in the common format, see `LogFormat::Common`.
+pub(super) fn common(entry: &Entry) -> String {
+    let time = DateTime::from_system_time(entry.started_at);
+    format!(
+        "{} - {} [{:02}/{}/{}:{:02}:{:02}:{:02} +0000] \"{} {}\" {} {} {} {} {}",
+        or_dash(entry.client),
+        escaped_or_dash(entry.user.as_deref()),
+        time.day,
+        MONTHS[time.month as usize - 1],
+        time.year,
+        time.hour,
+        time.minute,
+        time.second,
+        entry.command.map_or("-", command_name),
+        escaped_or_dash(entry.destination.as_ref()),
+        or_dash(entry.reply.map(|reply| reply.to_u8())),
+        entry.stats.bytes_up,
+        entry.stats.bytes_down,
+        entry.stats.duration.as_millis(),
+        or_dash(entry.resolved),
+    )
+}
+
+/// Appends `value` as a JSON string, or `null` if it's missing.
+fn push_json_string<T: Display>(line: &mut String, value: Option<T>) {
+    let Some(value) = value else {
+        line.push_str("null");
+        return;
+    };
+
+    line.push('"');
+    for c in value.to_string().chars() {
+        match c {
+            '"' => line.push_str("\\\""),
+            '\\' => line.push_str("\\\\"),
+            '\n' => line.push_str("\\n"),
+            '\r' => line.push_str("\\r"),
+            '\t' => line.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(line, "\\u{:04x}", c as u32);
+            }
+            c => line.push(c),
+        }
+    }
+    line.push('"');
+}
+
+/// Describes `error` by its cause, the `Display` of `Socks5Error` only tells its variant.
+fn error_message(error: &Socks5Error) -> String {
+    match error {
+        Socks5Error::Socks5Error(reply) => reply.to_string(),
+        Socks5Error::IoError(err) => err.to_string(),
+    }
+}
+
+/// Formats `entry` as a JSON object, see `LogFormat::Json`.
+pub(super) fn json(session: SessionId, entry: &Entry, error: Option<&Socks5Error>) -> String {
+    let time = DateTime::from_system_time(entry.started_at);
+    let mut line = format!(
+        "{{\"time\":\"{}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z\",\"session\":{},\"client\":",
+        time.year, time.month, time.day, time.hour, time.minute, time.second, time.millis, session
+    );
+    push_json_string(&mut line, entry.client);
+    line.push_str(",\"user\":");
+    push_json_string(&mut line, entry.user.as_deref());
+    line.push_str(",\"command\":");
+    push_json_string(&mut line, entry.command.map(command_name));
+    line.push_str(",\"destination\":");
+    push_json_string(&mut line, entry.destination.as_ref());
+    line.push_str(",\"resolved\":");
+    push_json_string(&mut line, entry.resolved);
+    let _ = write!(
+        line,
+        ",\"reply\":{},\"bytes_up\":{},\"bytes_down\":{},\"duration_ms\":{},\"error\":",
+        entry
+            .reply
+            .map_or_else(|| "null".to_string(), |reply| reply.to_u8().to_string()),
+        entry.stats.bytes_up,
+        entry.stats.bytes_down,
+        entry.stats.duration.as_millis(),
+    );
+    push_json_string(&mut line, error.map(error_message));
+    line.push('}');
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_client_supplied_fields() {
+        assert_eq!(escaped_or_dash(None::<&str>), "-");
+        assert_eq!(escaped_or_dash(Some("alice")), "alice");
+        assert_eq!(
+            escaped_or_dash(Some("a b\"c\\d\n\x7fé")),
+            "a\\x20b\\x22c\\x5Cd\\x0A\\x7F\\xC3\\xA9"
+        );
+    }
+}
@@ -0,0 +1,424 @@
+//! # Accounting Module
+//!
+//! This module provides traffic accounting and quotas that can wrap any `Connect`, `Bind` or
+//! `Associate` implementation. The `Accountant` counts the bytes relayed in each direction and
+//! the UDP datagrams relayed, both per session and per credentials of the session (the
+//! `Authenticator::Credentials`). Anonymous sessions (`()` credentials) share one account.
+//!
+//! When the usage of the credentials exceeds their `Quota`, new requests are rejected with
+//! `ConnectionNotAllowedByRuleset` and running sessions are closed on their next read or write.
+//! A session may exceed the quota by the size of one read, or one datagram, before it's closed.
+//!
+//! Usage is only kept in memory, `Accountant::snapshot` and `Accountant::set_usage` allow
+//! persisting it, and `Accountant::reset` starts a new billing period.
+//!
+//! ## Example
+//!
+//! ```rust,no_run
+//! use std::sync::Arc;
+//!
+//! use gerevs::{
+//!     accounting::{AccountingConnect, Accountant, Quota},
+//!     method_handlers::TunnelConnect,
+//! };
+//!
+//! let accountant = Arc::new(Accountant::<String>::new().with_default_quota(Quota {
+//!     bytes_total: Some(10 * 1024 * 1024 * 1024),
+//!     ..Quota::default()
+//! }));
+//! accountant.set_quota("alice".to_string(), None);
+//!
+//! let connect_handler = AccountingConnect::new(TunnelConnect::new(), accountant.clone());
+//!
+//! for (user, usage) in accountant.snapshot() {
+//!     println!("{}: {} bytes down", user, usage.bytes_down);
+//! }
+//! ```
+
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    io::{self, ErrorKind},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+};
+
+use tracing::info;
+
+use crate::{protocol::Reply, Socks5Error};
+
+mod associate;
+mod bind;
+mod connect;
+mod stream;
+
+pub use associate::{AccountedConnection, AccountingAssociate};
+pub use bind::AccountingBind;
+pub use connect::AccountingConnect;
+
+/// Traffic relayed for a session or for credentials. Upload is data sent by the client, download
+/// is data sent to the client. UDP bytes count the payload of the datagrams.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Usage {
+    pub bytes_up: u64,
+    pub bytes_down: u64,
+    pub packets_up: u64,
+    pub packets_down: u64,
+}
+
+impl Usage {
+    fn add(&mut self, other: &Usage) {
+        self.bytes_up += other.bytes_up;
+        self.bytes_down += other.bytes_down;
+        self.packets_up += other.packets_up;
+        self.packets_down += other.packets_down;
+    }
+}
+
+/// Limits on the usage of credentials, `None` fields are unlimited.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    pub bytes_up: Option<u64>,
+    pub bytes_down: Option<u64>,
+    /// Limit on the bytes relayed in both directions together.
+    pub bytes_total: Option<u64>,
+    /// Limit on the UDP datagrams relayed in both directions together.
+    pub packets: Option<u64>,
+}
+
+impl Quota {
+    /// Returns whether `usage` reached any of the limits.
+    pub fn is_exceeded_by(&self, usage: &Usage) -> bool {
+        let reached = |limit: Option<u64>, used: u64| limit.is_some_and(|limit| used >= limit);
+
+        reached(self.bytes_up, usage.bytes_up)
+            || reached(self.bytes_down, usage.bytes_down)
+            || reached(self.bytes_total, usage.bytes_up + usage.bytes_down)
+            || reached(self.packets, usage.packets_up + usage.packets_down)
+    }
+}
+
+struct Account {
+    usage: Usage,
+    quota: Option<Quota>,
+}
+
+/// The credentials and usage of a running session.
+type SessionEntry<K> = (K, Arc<Mutex<Usage>>);
+
+/// The `Accountant` struct holds the usage and quotas of every credentials, keyed by the
+/// credentials type `K`. It's shared by the accounting handlers with an `Arc`.
+pub struct Accountant<K> {
+    default_quota: Option<Quota>,
+    accounts: Mutex<HashMap<K, Arc<Mutex<Account>>>>,
+    sessions: Mutex<HashMap<u64, SessionEntry<K>>>,
+    next_session_id: AtomicU64,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+impl<K> Accountant<K>
+where
+    K: Hash + Eq + Clone,
+{
+    /// Creates a new `Accountant` without quotas.
+    pub fn new() -> Self {
+        Self {
+            default_quota: None,
+            accounts: Mutex::new(HashMap::new()),
+            sessions: Mutex::new(HashMap::new()),
+            next_session_id: AtomicU64::new(0),
+        }
+    }
+
+    /// Sets the quota of credentials that weren't given their own with `set_quota`.
+    ///
+    /// The quota is checked before each read or write, so a session may overshoot it by up to one
+    /// read, or one datagram, before it's closed.
+    pub fn with_default_quota(mut self, quota: Quota) -> Self {
+        self.default_quota = Some(quota);
+        self
+    }
+
+    fn account(&self, credentials: &K) -> Arc<Mutex<Account>> {
+        lock(&self.accounts)
+            .entry(credentials.clone())
+            .or_insert_with(|| {
+                Arc::new(Mutex::new(Account {
+                    usage: Usage::default(),
+                    quota: self.default_quota,
+                }))
+            })
+            .clone()
+    }
+
+    /// Sets the quota of `credentials`, `None` lifts all limits. Running sessions are closed on
+    /// their next read or write if the new quota is exceeded, like with `with_default_quota` the
+    /// quota may be overshot by up to one read, or one datagram.
+    pub fn set_quota(&self, credentials: K, quota: Option<Quota>) {
+        lock(&self.account(&credentials)).quota = quota;
+    }
+
+    /// Returns the usage of `credentials` since the last reset.
+    pub fn usage(&self, credentials: &K) -> Usage {
+        lock(&self.accounts)
+            .get(credentials)
+            .map(|account| lock(account).usage)
+            .unwrap_or_default()
+    }
+
+    /// Replaces the usage of `credentials`, e.g. with a usage persisted from a `snapshot`.
+    pub fn set_usage(&self, credentials: K, usage: Usage) {
+        lock(&self.account(&credentials)).usage = usage;
+    }
+
+    /// Returns the usage of every credentials since the last reset.
+    pub fn snapshot(&self) -> HashMap<K, Usage> {
+        lock(&self.accounts)
+            .iter()
+            .map(|(credentials, account)| (credentials.clone(), lock(account).usage))
+            .collect()
+    }
+
+    /// Returns the usage of every running session, with the credentials of the session.
+    pub fn sessions(&self) -> Vec<(K, Usage)> {
+        lock(&self.sessions)
+            .values()
+            .map(|(credentials, usage)| (credentials.clone(), *lock(usage)))
+            .collect()
+    }
+
+    /// Resets the usage of every credentials, quotas are kept.
+    pub fn reset(&self) {
+        for account in lock(&self.accounts).values() {
+            lock(account).usage = Usage::default();
+        }
+    }
+
+    /// Resets the usage of `credentials`, its quota is kept.
+    pub fn reset_user(&self, credentials: &K) {
+        if let Some(account) = lock(&self.accounts).get(credentials) {
+            lock(account).usage = Usage::default();
+        }
+    }
+
+    /// Starts accounting a session of `credentials`.
+    ///
+    /// - Returns: An error with `ConnectionNotAllowedByRuleset` if the quota is already exceeded.
+    pub(crate) fn start_session(self: &Arc<Self>, credentials: &K) -> crate::Result<Session<K>> {
+        let account = self.account(credentials);
+        check(&account)?;
+
+        let session = Session {
+            accountant: self.clone(),
+            id: self.next_session_id.fetch_add(1, Ordering::Relaxed),
+            account,
+            usage: Arc::new(Mutex::new(Usage::default())),
+        };
+
+        lock(&self.sessions).insert(session.id, (credentials.clone(), session.usage.clone()));
+        Ok(session)
+    }
+}
+
+impl<K> Default for Accountant<K>
+where
+    K: Hash + Eq + Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns an error with `ConnectionNotAllowedByRuleset` if the quota of `account` is exceeded.
+fn check(account: &Mutex<Account>) -> crate::Result<()> {
+    let account = lock(account);
+    match account.quota {
+        Some(quota) if quota.is_exceeded_by(&account.usage) => {
+            info!("Quota exceeded");
+            Err(Socks5Error::Socks5Error(
+                Reply::ConnectionNotAllowedByRuleset,
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The accounting of a running session, it's removed from the `Accountant` when dropped.
+pub(crate) struct Session<K> {
+    accountant: Arc<Accountant<K>>,
+    id: u64,
+    account: Arc<Mutex<Account>>,
+    usage: Arc<Mutex<Usage>>,
+}
+
+impl<K> Session<K> {
+    /// Returns an error if the quota of the session's credentials is exceeded.
+    pub(crate) fn check(&self) -> crate::Result<()> {
+        check(&self.account)
+    }
+
+    /// Like `check`, for use inside relayed streams.
+    pub(crate) fn check_io(&self) -> io::Result<()> {
+        self.check()
+            .map_err(|_| io::Error::new(ErrorKind::PermissionDenied, "Quota exceeded"))
+    }
+
+    pub(crate) fn record(&self, usage: Usage) {
+        lock(&self.usage).add(&usage);
+        lock(&self.account).usage.add(&usage);
+    }
+}
+
+impl<K> Drop for Session<K> {
+    fn drop(&mut self) {
+        let usage = *lock(&self.usage);
+        info!(
+            "Session relayed {} bytes up, {} bytes down, {} datagrams up, {} datagrams down",
+            usage.bytes_up, usage.bytes_down, usage.packets_up, usage.packets_down
+        );
+        lock(&self.accountant.sessions).remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(bytes_up: u64, bytes_down: u64) -> Usage {
+        Usage {
+            bytes_up,
+            bytes_down,
+            ..Usage::default()
+        }
+    }
+
+    fn is_rejected<K>(session: crate::Result<Session<K>>) -> bool {
+        matches!(
+            session,
+            Err(Socks5Error::Socks5Error(
+                Reply::ConnectionNotAllowedByRuleset
+            ))
+        )
+    }
+
+    #[test]
+    fn quota_limits() {
+        let quota = Quota {
+            bytes_up: Some(100),
+            bytes_total: Some(150),
+            packets: Some(2),
+            ..Quota::default()
+        };
+        assert!(!quota.is_exceeded_by(&usage(99, 50)));
+        assert!(quota.is_exceeded_by(&usage(100, 0)));
+        assert!(quota.is_exceeded_by(&usage(50, 100)));
+        assert!(!quota.is_exceeded_by(&Usage {
+            packets_up: 1,
+            ..Usage::default()
+        }));
+        assert!(quota.is_exceeded_by(&Usage {
+            packets_up: 1,
+            packets_down: 1,
+            ..Usage::default()
+        }));
+        assert!(!Quota::default().is_exceeded_by(&usage(u64::MAX, 0)));
+    }
+
+    #[test]
+    fn sessions_are_recorded_per_credentials() {
+        let accountant = Arc::new(Accountant::new());
+        let first = accountant.start_session(&"alice").unwrap();
+        let second = accountant.start_session(&"alice").unwrap();
+        first.record(usage(10, 20));
+        second.record(usage(1, 2));
+
+        assert_eq!(accountant.usage(&"alice"), usage(11, 22));
+        assert_eq!(accountant.usage(&"bob"), Usage::default());
+        let mut sessions = accountant.sessions();
+        sessions.sort_by_key(|(_, usage)| usage.bytes_up);
+        assert_eq!(sessions, [("alice", usage(1, 2)), ("alice", usage(10, 20))]);
+
+        drop(first);
+        assert_eq!(accountant.sessions(), [("alice", usage(1, 2))]);
+        assert_eq!(accountant.usage(&"alice"), usage(11, 22));
+    }
+
+    #[test]
+    fn crossing_the_quota_closes_sessions() {
+        let accountant = Arc::new(Accountant::new().with_default_quota(Quota {
+            bytes_down: Some(100),
+            ..Quota::default()
+        }));
+        let session = accountant.start_session(&"alice").unwrap();
+        session.record(usage(0, 99));
+        assert!(session.check().is_ok());
+
+        // The quota is overshot by the last read
+        session.record(usage(0, 50));
+        assert_eq!(
+            session.check_io().unwrap_err().kind(),
+            ErrorKind::PermissionDenied
+        );
+        assert!(is_rejected(accountant.start_session(&"alice")));
+        assert!(accountant.start_session(&"bob").is_ok());
+
+        // Lifting the quota lets running and new sessions continue
+        accountant.set_quota("alice", None);
+        assert!(session.check().is_ok());
+        assert!(accountant.start_session(&"alice").is_ok());
+    }
+
+    #[test]
+    fn resetting_keeps_quotas() {
+        let quota = Quota {
+            bytes_total: Some(100),
+            ..Quota::default()
+        };
+        let accountant = Arc::new(Accountant::new());
+        accountant.set_quota("alice", Some(quota));
+        accountant.set_quota("bob", Some(quota));
+        accountant.set_usage("alice", usage(100, 0));
+        accountant.set_usage("bob", usage(0, 100));
+        assert!(is_rejected(accountant.start_session(&"alice")));
+
+        accountant.reset_user(&"alice");
+        assert_eq!(accountant.usage(&"alice"), Usage::default());
+        assert_eq!(accountant.usage(&"bob"), usage(0, 100));
+        let session = accountant.start_session(&"alice").unwrap();
+        session.record(usage(100, 0));
+        assert!(session.check().is_err());
+
+        accountant.reset();
+        assert_eq!(
+            accountant.snapshot(),
+            HashMap::from([("alice", Usage::default()), ("bob", Usage::default())])
+        );
+        assert!(session.check().is_ok());
+        assert!(accountant.start_session(&"bob").is_ok());
+
+        // Resetting unknown credentials doesn't create an account
+        accountant.reset_user(&"carol");
+        assert_eq!(accountant.snapshot().len(), 2);
+    }
+
+    #[test]
+    fn set_usage_restores_a_snapshot() {
+        let accountant = Arc::new(Accountant::new());
+        accountant
+            .start_session(&"alice")
+            .unwrap()
+            .record(usage(5, 6));
+        let snapshot = accountant.snapshot();
+
+        let restored = Accountant::new();
+        for (credentials, usage) in snapshot {
+            restored.set_usage(credentials, usage);
+        }
+        assert_eq!(restored.usage(&"alice"), usage(5, 6));
+    }
+}
@@ -0,0 +1,114 @@
+use std::{hash::Hash, io, net::SocketAddr, sync::Arc};
+
+use crate::{
+    context::SessionContext,
+    method_handlers::Associate,
+    protocol::{SocksSocketAddr, UdpMessage},
+};
+
+use super::{Accountant, Session, Usage};
+
+/// The connection of an `AccountingAssociate`, the connection of the wrapped handler together
+/// with the accounting of the session.
+pub struct AccountedConnection<C, K> {
+    inner: C,
+    session: Session<K>,
+}
+
+/// The `AccountingAssociate` struct wraps an `Associate` implementation, rejects requests of
+/// credentials that exceeded their quota and accounts the relayed datagrams. The association is
+/// closed once the quota is exceeded.
+pub struct AccountingAssociate<A, K> {
+    inner: A,
+    accountant: Arc<Accountant<K>>,
+}
+
+impl<A, K> AccountingAssociate<A, K> {
+    /// Creates a new `AccountingAssociate`.
+    ///
+    /// - `inner`: The handler relaying the datagrams.
+    /// - `accountant`: The accountant holding the usage and quotas.
+    pub fn new(inner: A, accountant: Arc<Accountant<K>>) -> Self {
+        Self { inner, accountant }
+    }
+}
+
+impl<A, K> Associate<K> for AccountingAssociate<A, K>
+where
+    A: Associate<K> + Send + Sync,
+    A::Connection: Send,
+    K: Hash + Eq + Clone + Send + Sync,
+{
+    type Connection = AccountedConnection<A::Connection, K>;
+
+    async fn bind(
+        &self,
+        credentials: &K,
+        context: &SessionContext,
+    ) -> crate::Result<(SocketAddr, Self::Connection)> {
+        let session = self.accountant.start_session(credentials)?;
+        let (addr, inner) = self.inner.bind(credentials, context).await?;
+        Ok((addr, AccountedConnection { inner, session }))
+    }
+
+    async fn send_to(
+        &mut self,
+        conn: &mut Self::Connection,
+        buf: &[u8],
+        dst: SocksSocketAddr,
+        credentials: &K,
+    ) -> crate::Result<usize> {
+        conn.session.check()?;
+        let sent = self
+            .inner
+            .send_to(&mut conn.inner, buf, dst, credentials)
+            .await?;
+        conn.session.record(Usage {
+            bytes_up: buf.len() as u64,
+            packets_up: 1,
+            ..Usage::default()
+        });
+        Ok(sent)
+    }
+
+    async fn send_to_client(
+        &mut self,
+        conn: &mut Self::Connection,
+        buf: &[u8],
+        client: SocketAddr,
+        credentials: &K,
+    ) -> crate::Result<usize> {
+        conn.session.check()?;
+        let sent = self
+            .inner
+            .send_to_client(&mut conn.inner, buf, client, credentials)
+            .await?;
+        // `buf` is wrapped in the UDP request header, only the payload is accounted
+        let payload = UdpMessage::parse(buf)
+            .await
+            .map_or(buf.len(), |message| message.data.len());
+        conn.session.record(Usage {
+            bytes_down: payload as u64,
+            packets_down: 1,
+            ..Usage::default()
+        });
+        Ok(sent)
+    }
+
+    async fn recv_from(
+        &mut self,
+        conn: &mut Self::Connection,
+        buf: &mut [u8],
+        credentials: &K,
+    ) -> crate::Result<(usize, SocketAddr)> {
+        // Failing here closes the association once the quota is exceeded
+        conn.session.check()?;
+        self.inner
+            .recv_from(&mut conn.inner, buf, credentials)
+            .await
+    }
+
+    async fn resolve(&self, addr: &SocksSocketAddr) -> io::Result<Vec<SocketAddr>> {
+        self.inner.resolve(addr).await
+    }
+}
@@ -0,0 +1,83 @@
+use std::{hash::Hash, net::SocketAddr, sync::Arc};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{context::SessionContext, method_handlers::Bind, protocol::SocksSocketAddr};
+
+use super::{stream::Counted, Accountant, Session};
+
+/// The `AccountingBind` struct wraps a `Bind` implementation, rejects requests of credentials
+/// that exceeded their quota and accounts the data of the relayed connection.
+pub struct AccountingBind<B, K> {
+    inner: B,
+    accountant: Arc<Accountant<K>>,
+    session: Option<Session<K>>,
+}
+
+impl<B, K> AccountingBind<B, K> {
+    /// Creates a new `AccountingBind`.
+    ///
+    /// - `inner`: The handler accepting and relaying the connection.
+    /// - `accountant`: The accountant holding the usage and quotas.
+    pub fn new(inner: B, accountant: Arc<Accountant<K>>) -> Self {
+        Self {
+            inner,
+            accountant,
+            session: None,
+        }
+    }
+}
+
+impl<B, K> Bind<K> for AccountingBind<B, K>
+where
+    B: Bind<K> + Send,
+    B::Listener: Send,
+    B::Stream: Send,
+    K: Hash + Eq + Clone + Send + Sync + 'static,
+{
+    type Listener = B::Listener;
+    type Stream = B::Stream;
+
+    async fn bind(
+        &mut self,
+        addr: SocksSocketAddr,
+        credentials: &K,
+        context: &SessionContext,
+    ) -> crate::Result<(SocketAddr, Self::Listener)> {
+        self.session = Some(self.accountant.start_session(credentials)?);
+        self.inner.bind(addr, credentials, context).await
+    }
+
+    async fn accept(
+        &mut self,
+        server: Self::Listener,
+        credentials: &K,
+    ) -> crate::Result<(Self::Stream, SocketAddr)> {
+        self.inner.accept(server, credentials).await
+    }
+
+    async fn start_listening<T>(
+        self,
+        server: T,
+        client: Self::Stream,
+        credentials: K,
+    ) -> crate::Result<()>
+    where
+        T: AsyncWrite + AsyncRead + Send + Unpin + 'static,
+    {
+        // `server` is the stream of the SOCKS client, `client` the peer that connected to it
+        match self.session {
+            Some(session) => {
+                let server = Counted::new(server, session);
+                self.inner
+                    .start_listening(server, client, credentials)
+                    .await
+            }
+            None => {
+                self.inner
+                    .start_listening(server, client, credentials)
+                    .await
+            }
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use std::{hash::Hash, net::SocketAddr, sync::Arc};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{context::SessionContext, method_handlers::Connect, protocol::SocksSocketAddr};
+
+use super::{stream::Counted, Accountant, Session};
+
+/// The `AccountingConnect` struct wraps a `Connect` implementation, rejects requests of
+/// credentials that exceeded their quota and accounts the data of the relayed connection.
+pub struct AccountingConnect<C, K> {
+    inner: C,
+    accountant: Arc<Accountant<K>>,
+    session: Option<Session<K>>,
+}
+
+impl<C, K> AccountingConnect<C, K> {
+    /// Creates a new `AccountingConnect`.
+    ///
+    /// - `inner`: The handler establishing and relaying the connection.
+    /// - `accountant`: The accountant holding the usage and quotas.
+    pub fn new(inner: C, accountant: Arc<Accountant<K>>) -> Self {
+        Self {
+            inner,
+            accountant,
+            session: None,
+        }
+    }
+}
+
+impl<C, K> Connect<K> for AccountingConnect<C, K>
+where
+    C: Connect<K> + Send,
+    C::ServerConnection: Send,
+    K: Hash + Eq + Clone + Send + Sync + 'static,
+{
+    type ServerConnection = C::ServerConnection;
+
+    async fn establish_connection(
+        &mut self,
+        destination: SocksSocketAddr,
+        credentials: K,
+        context: &SessionContext,
+    ) -> crate::Result<Self::ServerConnection> {
+        self.session = Some(self.accountant.start_session(&credentials)?);
+        self.inner
+            .establish_connection(destination, credentials, context)
+            .await
+    }
+
+    fn reply_address(&self, connection: &Self::ServerConnection) -> Option<SocketAddr> {
+        self.inner.reply_address(connection)
+    }
+
+    async fn start_listening<T>(
+        self,
+        client: T,
+        connection: Self::ServerConnection,
+    ) -> crate::Result<()>
+    where
+        T: AsyncWrite + AsyncRead + Send + Unpin + 'static,
+    {
+        match self.session {
+            Some(session) => {
+                let client = Counted::new(client, session);
+                self.inner.start_listening(client, connection).await
+            }
+            None => self.inner.start_listening(client, connection).await,
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use super::{Session, Usage};
+
+/// Wraps the client stream of a relay, recording the data read from and written to the client
+/// and failing once the quota is exceeded.
+pub(crate) struct Counted<T, K> {
+    inner: T,
+    session: Session<K>,
+}
+
+impl<T, K> Counted<T, K> {
+    pub(crate) fn new(inner: T, session: Session<K>) -> Self {
+        Self { inner, session }
+    }
+}
+
+impl<T, K> AsyncRead for Counted<T, K>
+where
+    T: AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.session.check_io()?;
+
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        this.session.record(Usage {
+            bytes_up: (buf.filled().len() - filled) as u64,
+            ..Usage::default()
+        });
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T, K> AsyncWrite for Counted<T, K>
+where
+    T: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.session.check_io()?;
+
+        let written = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        this.session.record(Usage {
+            bytes_down: written as u64,
+            ..Usage::default()
+        });
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::ErrorKind, sync::Arc};
+
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    use crate::accounting::{Accountant, Quota};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn fails_once_the_quota_is_crossed() {
+        let accountant = Arc::new(Accountant::new().with_default_quota(Quota {
+            bytes_total: Some(100),
+            ..Quota::default()
+        }));
+        let (client, mut peer) = duplex(1024);
+        let mut counted = Counted::new(client, accountant.start_session(&()).unwrap());
+
+        peer.write_all(&[0; 60]).await.unwrap();
+        let mut buf = [0; 1024];
+        assert_eq!(counted.read(&mut buf).await.unwrap(), 60);
+        // The write crossing the quota still goes through
+        counted.write_all(&[0; 60]).await.unwrap();
+        assert_eq!(
+            accountant.usage(&()),
+            Usage {
+                bytes_up: 60,
+                bytes_down: 60,
+                ..Usage::default()
+            }
+        );
+
+        let err = counted.write_all(&[0; 1]).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        let err = counted.read(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+
+        accountant.reset();
+        counted.write_all(&[0; 1]).await.unwrap();
+    }
+}
@@ -0,0 +1,316 @@
+//! # Access Control Module
+//!
+//! This module provides a rule based access control layer that can wrap any `Connect`, `Bind` or
+//! `Associate` implementation and answer `ConnectionNotAllowedByRuleset` for denied destinations.
+//!
+//! Rules are evaluated in order and the first matching rule decides, when no rule matches the
+//! default action of the `RuleSet` applies. A rule can match on the destination host (CIDR,
+//! exact domain, domain suffix or glob), the destination port and the authenticated user.
+//!
+//! Domain destinations are evaluated before and after DNS resolution: every address the domain
+//! resolves to is evaluated together with the domain name, and the request is only allowed if all
+//! of them are allowed. The wrappers resolve domains with the `SystemResolver` unless another
+//! resolver is set with `with_resolver`. Domains are only resolved when a rule matches on CIDRs,
+//! and then the wrapped handler is given the vetted addresses instead of the domain name, so a DNS
+//! answer changing between the evaluation and the connection can't reach a denied network.
+//!
+//! ## Rule File Format
+//!
+//! One rule per line, `#` starts a comment:
+//!
+//! ```text
+//! # action  host                  [ports=...]        [user=...]
+//! deny      10.0.0.0/8
+//! allow     .example.com          ports=80,443
+//! allow     *.internal.corp       ports=8000-8080    user=alice
+//! deny      any
+//! default   deny
+//! ```
+//!
+//! The `default` line sets the action applied when no rule matches, it defaults to `deny`.
+//!
+//! ## Example
+//!
+//! ```rust,no_run
+//! use std::sync::Arc;
+//!
+//! use gerevs::{
+//!     acl::{AclConnect, RuleSet},
+//!     method_handlers::TunnelConnect,
+//!     resolver::DnsResolver,
+//! };
+//!
+//! # fn run() -> std::io::Result<()> {
+//! let rules = Arc::new(RuleSet::from_file("acl.rules")?);
+//! let resolver = DnsResolver::new(vec!["1.1.1.1:53".parse().unwrap()]);
+//! let connect_handler = AclConnect::new(
+//!     TunnelConnect::new().with_resolver(resolver.clone()),
+//!     rules.clone(),
+//! )
+//! .with_resolver(resolver);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    io::{self, ErrorKind},
+    net::SocketAddr,
+    path::Path,
+    str::FromStr,
+};
+
+use tracing::{debug, info};
+
+use crate::{
+    protocol::{Addr, Reply, SocksSocketAddr},
+    resolver::Resolver,
+    Socks5Error,
+};
+
+mod associate;
+mod bind;
+mod cidr;
+mod connect;
+mod rule;
+
+pub use associate::AclAssociate;
+pub use bind::AclBind;
+pub use cidr::Cidr;
+pub use connect::AclConnect;
+pub use rule::{Action, DomainPattern, HostPattern, PortRange, Rule};
+
+use rule::Destination;
+
+/// The `AclCredentials` trait exposes the user name of the credentials produced by an
+/// `Authenticator`, so rules can be restricted to specific users.
+pub trait AclCredentials {
+    /// Returns the name of the authenticated user, `None` for anonymous sessions.
+    fn user(&self) -> Option<&str>;
+}
+
+impl AclCredentials for () {
+    fn user(&self) -> Option<&str> {
+        None
+    }
+}
+
+impl AclCredentials for String {
+    fn user(&self) -> Option<&str> {
+        Some(self)
+    }
+}
+
+impl<C> AclCredentials for Option<C>
+where
+    C: AclCredentials,
+{
+    fn user(&self) -> Option<&str> {
+        self.as_ref().and_then(AclCredentials::user)
+    }
+}
+
+/// An ordered list of rules, the first matching rule decides.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+    default_action: Action,
+}
+
+impl RuleSet {
+    /// Creates an empty `RuleSet`.
+    ///
+    /// - `default_action`: The action applied when no rule matches.
+    pub fn new(default_action: Action) -> Self {
+        Self {
+            rules: Vec::new(),
+            default_action,
+        }
+    }
+
+    /// Appends a rule, it's evaluated after all previously added rules.
+    pub fn with_rule(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Returns the rules in evaluation order.
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Reads a `RuleSet` from a file in the rule file format described in the module documentation.
+    pub fn from_file<P>(path: P) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        std::fs::read_to_string(path)?.parse()
+    }
+
+    /// Evaluates a destination whose domain name, if any, was already resolved.
+    ///
+    /// - `dst`: The destination as requested by the client.
+    /// - `resolved`: The addresses a domain destination resolved to, may be empty.
+    /// - `credentials`: The credentials of the session.
+    /// - Returns: `Action::Allow` only if the destination is allowed with every resolved address.
+    pub fn evaluate<C>(
+        &self,
+        dst: &SocksSocketAddr,
+        resolved: &[SocketAddr],
+        credentials: &C,
+    ) -> Action
+    where
+        C: AclCredentials,
+    {
+        let user = credentials.user();
+        let destinations: Vec<Destination> = match &dst.addr {
+            Addr::Ipv4(ip) => vec![Destination {
+                domain: None,
+                ip: Some((*ip).into()),
+                port: dst.port,
+            }],
+            Addr::Ipv6(ip) => vec![Destination {
+                domain: None,
+                ip: Some((*ip).into()),
+                port: dst.port,
+            }],
+            Addr::Domain(domain) if resolved.is_empty() => vec![Destination {
+                domain: Some(domain),
+                ip: None,
+                port: dst.port,
+            }],
+            Addr::Domain(domain) => resolved
+                .iter()
+                .map(|addr| Destination {
+                    domain: Some(domain),
+                    ip: Some(addr.ip()),
+                    port: dst.port,
+                })
+                .collect(),
+        };
+
+        let allowed = destinations
+            .into_iter()
+            .all(|destination| self.evaluate_destination(destination, user) == Action::Allow);
+        if allowed {
+            Action::Allow
+        } else {
+            Action::Deny
+        }
+    }
+
+    fn evaluate_destination(&self, destination: Destination, user: Option<&str>) -> Action {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(destination, user))
+            .map_or(self.default_action, |rule| rule.action)
+    }
+
+    /// Whether evaluating a domain destination requires resolving it.
+    fn needs_resolution(&self) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| matches!(rule.host, HostPattern::Cidr(_)))
+    }
+
+    /// Resolves `dst` if needed and evaluates it.
+    ///
+    /// - `resolver`: The resolver used to resolve a domain destination.
+    /// - Returns: A future that resolves to `crate::Result<Vec<SocketAddr>>`, the vetted addresses
+    ///   of a domain destination that had to be resolved, empty otherwise. The domain must not be
+    ///   resolved again, only these addresses should be reached. An error with
+    ///   `ConnectionNotAllowedByRuleset` if the destination is denied.
+    pub async fn check<C, R>(
+        &self,
+        dst: &SocksSocketAddr,
+        credentials: &C,
+        resolver: &R,
+    ) -> crate::Result<Vec<SocketAddr>>
+    where
+        C: AclCredentials,
+        R: Resolver,
+    {
+        let resolved = match dst.addr {
+            Addr::Domain(_) if self.needs_resolution() => {
+                let resolved = dst.resolve(resolver).await?;
+                // Without addresses the CIDR rules couldn't be evaluated
+                if resolved.is_empty() {
+                    return Err(Socks5Error::Socks5Error(Reply::HostUnreachable));
+                }
+                resolved
+            }
+            _ => Vec::new(),
+        };
+
+        match self.evaluate(dst, &resolved, credentials) {
+            Action::Allow => {
+                debug!("Destination {} allowed", dst);
+                Ok(resolved)
+            }
+            Action::Deny => {
+                info!("Destination {} denied by ruleset", dst);
+                Err(Socks5Error::Socks5Error(
+                    Reply::ConnectionNotAllowedByRuleset,
+                ))
+            }
+        }
+    }
+}
+
+impl FromStr for RuleSet {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rule_set = RuleSet::new(Action::Deny);
+
+        for (number, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut tokens = line.split_whitespace();
+            let Some(first) = tokens.next() else {
+                continue;
+            };
+
+            let with_line = |err: io::Error| {
+                io::Error::new(err.kind(), format!("Line {}: {}", number + 1, err))
+            };
+
+            if first == "default" {
+                let action = tokens.next().ok_or_else(|| {
+                    with_line(io::Error::new(
+                        ErrorKind::InvalidInput,
+                        "Missing default action",
+                    ))
+                })?;
+                rule_set.default_action = action.parse().map_err(with_line)?;
+                continue;
+            }
+
+            let action = first.parse().map_err(with_line)?;
+            let host = tokens
+                .next()
+                .ok_or_else(|| with_line(io::Error::new(ErrorKind::InvalidInput, "Missing host")))?
+                .parse()
+                .map_err(with_line)?;
+            let mut rule = Rule::new(action, host);
+
+            for option in tokens {
+                match option.split_once('=') {
+                    Some(("ports", ports)) => {
+                        for port in ports.split(',') {
+                            rule.ports.push(port.parse().map_err(with_line)?);
+                        }
+                    }
+                    Some(("user", user)) => rule.user = Some(user.to_string()),
+                    _ => {
+                        return Err(with_line(io::Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("Unknown option: {}", option),
+                        )))
+                    }
+                }
+            }
+            rule_set.rules.push(rule);
+        }
+
+        Ok(rule_set)
+    }
+}
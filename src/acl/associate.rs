@@ -0,0 +1,103 @@
+use std::{io, net::SocketAddr, sync::Arc};
+
+use crate::{
+    context::SessionContext,
+    method_handlers::Associate,
+    protocol::SocksSocketAddr,
+    resolver::{Resolver, SystemResolver},
+};
+
+use super::{AclCredentials, RuleSet};
+
+/// The `AclAssociate` struct wraps an `Associate` implementation and only relays datagrams from
+/// the client to destinations allowed by the `RuleSet`, datagrams to denied destinations are dropped.
+/// A domain resolved to evaluate the rules is passed on as its first vetted address.
+pub struct AclAssociate<A, R = SystemResolver> {
+    inner: A,
+    rules: Arc<RuleSet>,
+    resolver: R,
+}
+
+impl<A> AclAssociate<A> {
+    /// Creates a new `AclAssociate`.
+    ///
+    /// - `inner`: The handler allowed datagrams are passed on to.
+    /// - `rules`: The rules datagram destinations are evaluated against.
+    pub fn new(inner: A, rules: Arc<RuleSet>) -> Self {
+        Self {
+            inner,
+            rules,
+            resolver: SystemResolver,
+        }
+    }
+}
+
+impl<A, R> AclAssociate<A, R> {
+    /// Sets the resolver used to resolve the destination domain names before evaluating
+    /// them, it should be the resolver of the wrapped handler.
+    pub fn with_resolver<R2>(self, resolver: R2) -> AclAssociate<A, R2>
+    where
+        R2: Resolver,
+    {
+        AclAssociate {
+            inner: self.inner,
+            rules: self.rules,
+            resolver,
+        }
+    }
+}
+
+impl<A, C, R> Associate<C> for AclAssociate<A, R>
+where
+    A: Associate<C> + Send + Sync,
+    A::Connection: Send,
+    C: AclCredentials + Sync + Send,
+    R: Resolver,
+{
+    type Connection = A::Connection;
+
+    async fn bind(
+        &self,
+        credentials: &C,
+        context: &SessionContext,
+    ) -> crate::Result<(SocketAddr, Self::Connection)> {
+        self.inner.bind(credentials, context).await
+    }
+
+    async fn send_to(
+        &mut self,
+        conn: &mut Self::Connection,
+        buf: &[u8],
+        dst: SocksSocketAddr,
+        credentials: &C,
+    ) -> crate::Result<usize> {
+        let resolved = self.rules.check(&dst, credentials, &self.resolver).await?;
+        let dst = resolved.first().map_or(dst, |&resolved| resolved.into());
+        self.inner.send_to(conn, buf, dst, credentials).await
+    }
+
+    async fn send_to_client(
+        &mut self,
+        conn: &mut Self::Connection,
+        buf: &[u8],
+        client: SocketAddr,
+        credentials: &C,
+    ) -> crate::Result<usize> {
+        self.inner
+            .send_to_client(conn, buf, client, credentials)
+            .await
+    }
+
+    async fn recv_from(
+        &mut self,
+        conn: &mut Self::Connection,
+        buf: &mut [u8],
+        credentials: &C,
+    ) -> crate::Result<(usize, SocketAddr)> {
+        self.inner.recv_from(conn, buf, credentials).await
+    }
+
+    async fn resolve(&self, addr: &SocksSocketAddr) -> io::Result<Vec<SocketAddr>> {
+        self.inner.resolve(addr).await
+    }
+}
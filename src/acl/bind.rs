@@ -0,0 +1,99 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{
+    context::SessionContext,
+    method_handlers::Bind,
+    protocol::SocksSocketAddr,
+    resolver::{Resolver, SystemResolver},
+};
+
+use super::{AclCredentials, RuleSet};
+
+/// The `AclBind` struct wraps a `Bind` implementation and only passes on BIND requests whose
+/// address is allowed by the `RuleSet`. The address of the accepted peer is evaluated as well. A
+/// domain resolved to evaluate the rules is passed on as its first vetted address.
+pub struct AclBind<B, R = SystemResolver> {
+    inner: B,
+    rules: Arc<RuleSet>,
+    resolver: R,
+}
+
+impl<B> AclBind<B> {
+    /// Creates a new `AclBind`.
+    ///
+    /// - `inner`: The handler allowed requests are passed on to.
+    /// - `rules`: The rules requests are evaluated against.
+    pub fn new(inner: B, rules: Arc<RuleSet>) -> Self {
+        Self {
+            inner,
+            rules,
+            resolver: SystemResolver,
+        }
+    }
+}
+
+impl<B, R> AclBind<B, R> {
+    /// Sets the resolver used to resolve the requested domain names before evaluating
+    /// them, it should be the resolver of the wrapped handler.
+    pub fn with_resolver<R2>(self, resolver: R2) -> AclBind<B, R2>
+    where
+        R2: Resolver,
+    {
+        AclBind {
+            inner: self.inner,
+            rules: self.rules,
+            resolver,
+        }
+    }
+}
+
+impl<B, C, R> Bind<C> for AclBind<B, R>
+where
+    B: Bind<C> + Send,
+    B::Listener: Send,
+    B::Stream: Send,
+    C: AclCredentials + Send + Sync,
+    R: Resolver,
+{
+    type Listener = B::Listener;
+    type Stream = B::Stream;
+
+    async fn bind(
+        &mut self,
+        addr: SocksSocketAddr,
+        credentials: &C,
+        context: &SessionContext,
+    ) -> crate::Result<(SocketAddr, Self::Listener)> {
+        let resolved = self.rules.check(&addr, credentials, &self.resolver).await?;
+        let addr = resolved.first().map_or(addr, |&resolved| resolved.into());
+        self.inner.bind(addr, credentials, context).await
+    }
+
+    async fn accept(
+        &mut self,
+        server: Self::Listener,
+        credentials: &C,
+    ) -> crate::Result<(Self::Stream, SocketAddr)> {
+        let (stream, peer_addr) = self.inner.accept(server, credentials).await?;
+        self.rules
+            .check(&peer_addr.into(), credentials, &self.resolver)
+            .await?;
+        Ok((stream, peer_addr))
+    }
+
+    async fn start_listening<T>(
+        self,
+        server: T,
+        client: Self::Stream,
+        credentials: C,
+    ) -> crate::Result<()>
+    where
+        T: AsyncWrite + AsyncRead + Send + Unpin + 'static,
+    {
+        self.inner
+            .start_listening(server, client, credentials)
+            .await
+    }
+}
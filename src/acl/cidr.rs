@@ -0,0 +1,202 @@
+use std::{
+    fmt::{self, Display},
+    io::{self, ErrorKind},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+/// A `Cidr` is an IP network written as `address/prefix`, e.g. `10.0.0.0/8` or `fc00::/7`.
+///
+/// IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`) are treated as the IPv4 address they map, so
+/// `127.0.0.0/8` also contains `::ffff:127.0.0.1`. Likewise a network of mapped addresses is the
+/// IPv4 network it maps, `::ffff:10.0.0.0/104` is `10.0.0.0/8`. IPv6 networks shorter than `/96`
+/// don't contain IPv4 addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Creates a new `Cidr`, the host bits of `addr` are cleared.
+    ///
+    /// - `addr`: The network address.
+    /// - `prefix`: The prefix length, at most 32 for IPv4 and 128 for IPv6.
+    /// - Returns: `None` if `prefix` is too long for the address family.
+    pub fn new(addr: IpAddr, prefix: u8) -> Option<Self> {
+        let max_prefix = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix > max_prefix {
+            return None;
+        }
+
+        let (addr, prefix) = match addr {
+            IpAddr::V6(ip) if prefix >= 96 => match ip.to_ipv4_mapped() {
+                Some(ip) => (IpAddr::V4(ip), prefix - 96),
+                None => (addr, prefix),
+            },
+            _ => (addr, prefix),
+        };
+        let addr = match addr {
+            IpAddr::V4(ip) => {
+                let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+                IpAddr::V4(Ipv4Addr::from(u32::from(ip) & mask))
+            }
+            IpAddr::V6(ip) => {
+                let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+                IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask))
+            }
+        };
+        Some(Self { addr, prefix })
+    }
+
+    /// Returns the prefix length of the network.
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    /// Returns whether `ip` is inside this network.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        match (self.addr, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                prefix_matches(&network.octets(), &ip.octets(), self.prefix)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                prefix_matches(&network.octets(), &ip.octets(), self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_matches(network: &[u8], ip: &[u8], prefix: u8) -> bool {
+    let full_bytes = prefix as usize / 8;
+    if network[..full_bytes] != ip[..full_bytes] {
+        return false;
+    }
+
+    let remaining_bits = prefix % 8;
+    if remaining_bits == 0 {
+        return true;
+    }
+    let mask = u8::MAX << (8 - remaining_bits);
+    network[full_bytes] & mask == ip[full_bytes] & mask
+}
+
+impl From<IpAddr> for Cidr {
+    fn from(addr: IpAddr) -> Self {
+        let prefix = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        Cidr::new(addr, prefix).expect("Full prefix is valid")
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = io::Error;
+
+    /// Parses `address/prefix`, a plain address is parsed as a single host network.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || io::Error::new(ErrorKind::InvalidInput, format!("Invalid CIDR: {}", s));
+
+        let Some((addr, prefix)) = s.split_once('/') else {
+            return s.parse::<IpAddr>().map(Cidr::from).map_err(|_| invalid());
+        };
+
+        let addr = addr.parse::<IpAddr>().map_err(|_| invalid())?;
+        let prefix = prefix.parse::<u8>().map_err(|_| invalid())?;
+        Cidr::new(addr, prefix).ok_or_else(invalid)
+    }
+}
+
+impl Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(s: &str) -> Cidr {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_networks_and_hosts() {
+        assert_eq!(cidr("10.0.0.0/8").prefix(), 8);
+        assert_eq!(cidr("10.1.2.3").prefix(), 32);
+        assert_eq!(cidr("::1").prefix(), 128);
+
+        for invalid in [
+            "10.0.0.0/33",
+            "::/129",
+            "10.0.0.0/",
+            "/8",
+            "10.0.0/8",
+            "example.com",
+        ] {
+            assert!(invalid.parse::<Cidr>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn clears_host_bits() {
+        assert_eq!(cidr("10.1.2.3/8").to_string(), "10.0.0.0/8");
+        assert_eq!(cidr("10.1.2.3/8"), cidr("10.0.0.0/8"));
+        assert_eq!(cidr("2001:db8::1/32").to_string(), "2001:db8::/32");
+        assert_eq!(cidr("1.2.3.4/0").to_string(), "0.0.0.0/0");
+    }
+
+    #[test]
+    fn contains_ipv4() {
+        let network = cidr("192.168.0.0/16");
+        assert!(network.contains(ip("192.168.0.1")));
+        assert!(network.contains(ip("192.168.255.255")));
+        assert!(!network.contains(ip("192.169.0.0")));
+        assert!(!network.contains(ip("::1")));
+
+        let network = cidr("172.16.0.0/12");
+        assert!(network.contains(ip("172.31.255.255")));
+        assert!(!network.contains(ip("172.32.0.0")));
+
+        assert!(cidr("0.0.0.0/0").contains(ip("8.8.8.8")));
+        assert!(cidr("8.8.8.8").contains(ip("8.8.8.8")));
+        assert!(!cidr("8.8.8.8").contains(ip("8.8.8.9")));
+    }
+
+    #[test]
+    fn contains_ipv6() {
+        let network = cidr("fc00::/7");
+        assert!(network.contains(ip("fd12:3456::1")));
+        assert!(!network.contains(ip("fe80::1")));
+        assert!(!network.contains(ip("10.0.0.1")));
+        assert!(cidr("::/0").contains(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn mapped_addresses_are_ipv4() {
+        assert!(cidr("127.0.0.0/8").contains(ip("::ffff:127.0.0.1")));
+        assert!(!cidr("127.0.0.0/8").contains(ip("::ffff:128.0.0.1")));
+
+        // Networks written in mapped form
+        assert_eq!(cidr("::ffff:10.0.0.0/104"), cidr("10.0.0.0/8"));
+        assert!(cidr("::ffff:10.0.0.0/104").contains(ip("10.1.2.3")));
+        assert!(cidr("::ffff:10.0.0.0/104").contains(ip("::ffff:10.1.2.3")));
+        assert!(cidr("::ffff:0:0/96").contains(ip("8.8.8.8")));
+        assert_eq!(cidr("::ffff:1.2.3.4"), cidr("1.2.3.4"));
+
+        // Only mapped networks are IPv4
+        assert!(!cidr("::/0").contains(ip("8.8.8.8")));
+        assert!(!cidr("64:ff9b::/96").contains(ip("8.8.8.8")));
+    }
+}
@@ -0,0 +1,110 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tracing::debug;
+
+use crate::{
+    context::SessionContext,
+    method_handlers::Connect,
+    protocol::SocksSocketAddr,
+    resolver::{Resolver, SystemResolver},
+};
+
+use super::{AclCredentials, RuleSet};
+
+/// The `AclConnect` struct wraps a `Connect` implementation and only passes on CONNECT requests
+/// whose destination is allowed by the `RuleSet`. A domain resolved to evaluate the rules is passed
+/// on as its vetted addresses, which are tried in order until a connection is established.
+pub struct AclConnect<C, R = SystemResolver> {
+    inner: C,
+    rules: Arc<RuleSet>,
+    resolver: R,
+}
+
+impl<C> AclConnect<C> {
+    /// Creates a new `AclConnect`.
+    ///
+    /// - `inner`: The handler allowed requests are passed on to.
+    /// - `rules`: The rules requests are evaluated against.
+    pub fn new(inner: C, rules: Arc<RuleSet>) -> Self {
+        Self {
+            inner,
+            rules,
+            resolver: SystemResolver,
+        }
+    }
+}
+
+impl<C, R> AclConnect<C, R> {
+    /// Sets the resolver used to resolve the destination domain names before evaluating
+    /// them, it should be the resolver of the wrapped handler.
+    pub fn with_resolver<R2>(self, resolver: R2) -> AclConnect<C, R2>
+    where
+        R2: Resolver,
+    {
+        AclConnect {
+            inner: self.inner,
+            rules: self.rules,
+            resolver,
+        }
+    }
+}
+
+impl<C, Cr, R> Connect<Cr> for AclConnect<C, R>
+where
+    C: Connect<Cr> + Send,
+    C::ServerConnection: Send,
+    Cr: AclCredentials + Clone + Send + Sync,
+    R: Resolver,
+{
+    type ServerConnection = C::ServerConnection;
+
+    async fn establish_connection(
+        &mut self,
+        destination: SocksSocketAddr,
+        credentials: Cr,
+        context: &SessionContext,
+    ) -> crate::Result<Self::ServerConnection> {
+        let resolved = self
+            .rules
+            .check(&destination, &credentials, &self.resolver)
+            .await?;
+        if resolved.is_empty() {
+            return self
+                .inner
+                .establish_connection(destination, credentials, context)
+                .await;
+        }
+
+        let mut last_err = None;
+        for addr in resolved {
+            match self
+                .inner
+                .establish_connection(addr.into(), credentials.clone(), context)
+                .await
+            {
+                Ok(connection) => return Ok(connection),
+                Err(err) => {
+                    debug!("Failed connecting to {} ({}): {:?}", addr, destination, err);
+                    last_err = Some(err);
+                }
+            }
+        }
+        Err(last_err.expect("Vetted addresses aren't empty"))
+    }
+
+    fn reply_address(&self, connection: &Self::ServerConnection) -> Option<SocketAddr> {
+        self.inner.reply_address(connection)
+    }
+
+    async fn start_listening<T>(
+        self,
+        client: T,
+        connection: Self::ServerConnection,
+    ) -> crate::Result<()>
+    where
+        T: AsyncWrite + AsyncRead + Send + Unpin + 'static,
+    {
+        self.inner.start_listening(client, connection).await
+    }
+}
@@ -0,0 +1,554 @@
+use std::{
+    io::{self, ErrorKind},
+    net::IpAddr,
+    ops::RangeInclusive,
+    str::FromStr,
+};
+
+use super::Cidr;
+
+/// The decision of an access control rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// The request is passed on to the wrapped handler.
+    Allow,
+    /// The request is answered with `ConnectionNotAllowedByRuleset`.
+    Deny,
+}
+
+impl FromStr for Action {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(Action::Allow),
+            "deny" => Ok(Action::Deny),
+            _ => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid action: {}, expected allow or deny", s),
+            )),
+        }
+    }
+}
+
+/// A pattern matched against destination domain names, case insensitively.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DomainPattern {
+    /// Matches exactly this domain.
+    Exact(String),
+    /// Matches this domain and all of its subdomains, written as `.example.com`.
+    Suffix(String),
+    /// Matches a glob where `*` matches any number of characters and `?` a single one.
+    Glob(String),
+}
+
+impl DomainPattern {
+    /// Returns whether `domain` matches the pattern.
+    pub fn matches(&self, domain: &str) -> bool {
+        let domain = normalize_domain(domain);
+        match self {
+            DomainPattern::Exact(exact) => domain == *exact,
+            DomainPattern::Suffix(suffix) => {
+                domain == *suffix
+                    || domain
+                        .strip_suffix(suffix.as_str())
+                        .is_some_and(|subdomain| subdomain.ends_with('.'))
+            }
+            DomainPattern::Glob(glob) => glob_matches(glob.as_bytes(), domain.as_bytes()),
+        }
+    }
+}
+
+impl FromStr for DomainPattern {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let pattern = normalize_domain(s);
+        if pattern.is_empty() || pattern == "." {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "Domain pattern cannot be empty",
+            ));
+        }
+
+        Ok(if let Some(suffix) = pattern.strip_prefix('.') {
+            DomainPattern::Suffix(suffix.to_string())
+        } else if pattern.contains(['*', '?']) {
+            DomainPattern::Glob(pattern)
+        } else {
+            DomainPattern::Exact(pattern)
+        })
+    }
+}
+
+fn normalize_domain(domain: &str) -> String {
+    domain.trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn glob_matches(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` in the pattern, and the text position it's currently matched up to
+    let mut backtrack = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => {
+                let Some((star, matched)) = backtrack else {
+                    return false;
+                };
+                p = star + 1;
+                t = matched + 1;
+                backtrack = Some((star, matched + 1));
+            }
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// A pattern matched against the destination host of a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostPattern {
+    /// Matches every destination.
+    Any,
+    /// Matches destinations whose IP address, given or resolved, is inside the network.
+    Cidr(Cidr),
+    /// Matches destinations requested by domain name.
+    Domain(DomainPattern),
+}
+
+impl FromStr for HostPattern {
+    type Err = io::Error;
+
+    /// Parses `any` or `*`, a CIDR or IP address, and otherwise a `DomainPattern`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "any" || s == "*" {
+            return Ok(HostPattern::Any);
+        }
+        if s.contains('/') || s.parse::<IpAddr>().is_ok() {
+            return s.parse().map(HostPattern::Cidr);
+        }
+        s.parse().map(HostPattern::Domain)
+    }
+}
+
+/// An inclusive range of ports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortRange(pub RangeInclusive<u16>);
+
+impl PortRange {
+    /// Returns whether `port` is inside the range.
+    pub fn contains(&self, port: u16) -> bool {
+        self.0.contains(&port)
+    }
+}
+
+impl From<u16> for PortRange {
+    fn from(port: u16) -> Self {
+        PortRange(port..=port)
+    }
+}
+
+impl From<RangeInclusive<u16>> for PortRange {
+    fn from(range: RangeInclusive<u16>) -> Self {
+        PortRange(range)
+    }
+}
+
+impl FromStr for PortRange {
+    type Err = io::Error;
+
+    /// Parses a single port (`443`) or an inclusive range (`8000-8080`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || io::Error::new(ErrorKind::InvalidInput, format!("Invalid port: {}", s));
+        let parse = |port: &str| port.trim().parse::<u16>().map_err(|_| invalid());
+
+        match s.split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (parse(start)?, parse(end)?);
+                if start > end {
+                    return Err(invalid());
+                }
+                Ok(PortRange(start..=end))
+            }
+            None => parse(s).map(PortRange::from),
+        }
+    }
+}
+
+/// A single destination the rules are evaluated against, the requested domain name (if any)
+/// together with one of the addresses it resolved to.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Destination<'a> {
+    pub domain: Option<&'a str>,
+    pub ip: Option<IpAddr>,
+    pub port: u16,
+}
+
+/// An access control rule, it matches when every one of its conditions matches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    /// The decision taken when the rule matches.
+    pub action: Action,
+    /// The destination host the rule applies to.
+    pub host: HostPattern,
+    /// The destination ports the rule applies to, empty for all ports.
+    pub ports: Vec<PortRange>,
+    /// The user the rule applies to, `None` for all users.
+    pub user: Option<String>,
+}
+
+impl Rule {
+    /// Creates a new `Rule` for all ports and users.
+    pub fn new(action: Action, host: HostPattern) -> Self {
+        Self {
+            action,
+            host,
+            ports: Vec::new(),
+            user: None,
+        }
+    }
+
+    /// Restricts the rule to the given destination ports.
+    pub fn with_ports<I>(mut self, ports: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<PortRange>,
+    {
+        self.ports.extend(ports.into_iter().map(Into::into));
+        self
+    }
+
+    /// Restricts the rule to sessions authenticated as `user`, see `AclCredentials`.
+    pub fn with_user(mut self, user: impl Into<String>) -> Self {
+        self.user = Some(user.into());
+        self
+    }
+
+    pub(crate) fn matches(&self, destination: Destination, user: Option<&str>) -> bool {
+        if let Some(rule_user) = &self.user {
+            if user != Some(rule_user.as_str()) {
+                return false;
+            }
+        }
+
+        if !self.ports.is_empty() && !self.ports.iter().any(|r| r.contains(destination.port)) {
+            return false;
+        }
+
+        match &self.host {
+            HostPattern::Any => true,
+            HostPattern::Cidr(cidr) => destination.ip.is_some_and(|ip| cidr.contains(ip)),
+            HostPattern::Domain(pattern) => destination
+                .domain
+                .is_some_and(|domain| pattern.matches(domain)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+    use crate::{
+        acl::RuleSet,
+        protocol::{Addr, Reply, SocksSocketAddr},
+        resolver::Resolver,
+        Socks5Error,
+    };
+
+    /// Resolves every domain to the same addresses.
+    struct StaticResolver(Vec<SocketAddr>);
+
+    impl Resolver for StaticResolver {
+        async fn resolve(&self, _: &str, _: u16) -> io::Result<Vec<SocketAddr>> {
+            Ok(self.0.clone())
+        }
+    }
+
+    fn domain(pattern: &str) -> DomainPattern {
+        pattern.parse().unwrap()
+    }
+
+    fn dst(host: &str, port: u16) -> SocksSocketAddr {
+        let addr = match host.parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) => Addr::Ipv4(ip),
+            Ok(IpAddr::V6(ip)) => Addr::Ipv6(ip),
+            Err(_) => Addr::Domain(host.to_string()),
+        };
+        SocksSocketAddr { addr, port }
+    }
+
+    fn resolved(addr: &str) -> Vec<SocketAddr> {
+        vec![addr.parse().unwrap()]
+    }
+
+    #[test]
+    fn parses_domain_patterns() {
+        assert_eq!(
+            domain("Example.COM."),
+            DomainPattern::Exact("example.com".into())
+        );
+        assert_eq!(
+            domain(".example.com"),
+            DomainPattern::Suffix("example.com".into())
+        );
+        assert_eq!(
+            domain("*.example.com"),
+            DomainPattern::Glob("*.example.com".into())
+        );
+        assert_eq!(
+            domain("api?.example.com"),
+            DomainPattern::Glob("api?.example.com".into())
+        );
+        assert!("".parse::<DomainPattern>().is_err());
+        assert!(".".parse::<DomainPattern>().is_err());
+    }
+
+    #[test]
+    fn matches_exact_and_suffix_domains() {
+        let exact = domain("example.com");
+        assert!(exact.matches("example.com"));
+        assert!(exact.matches("EXAMPLE.com."));
+        assert!(!exact.matches("www.example.com"));
+
+        let suffix = domain(".example.com");
+        assert!(suffix.matches("example.com"));
+        assert!(suffix.matches("a.b.Example.com."));
+        assert!(!suffix.matches("badexample.com"));
+        assert!(!suffix.matches("example.com.evil"));
+    }
+
+    #[test]
+    fn matches_globs() {
+        let glob = domain("*.example.com");
+        assert!(glob.matches("www.example.com"));
+        assert!(glob.matches("a.b.example.com"));
+        assert!(glob.matches("WWW.Example.Com."));
+        assert!(!glob.matches("example.com"));
+        assert!(!glob.matches("www.example.com.evil"));
+
+        let glob = domain("api?.*.corp");
+        assert!(glob.matches("api1.eu.corp"));
+        assert!(!glob.matches("api.eu.corp"));
+        assert!(!glob.matches("api12.eu.corp"));
+
+        assert!(domain("*a*b").matches("xaxxb"));
+        assert!(!domain("*a*b").matches("xaxxbx"));
+        assert!(domain("**").matches("anything"));
+    }
+
+    #[test]
+    fn parses_host_patterns() {
+        assert_eq!("any".parse::<HostPattern>().unwrap(), HostPattern::Any);
+        assert_eq!("*".parse::<HostPattern>().unwrap(), HostPattern::Any);
+        assert!(matches!(
+            "10.0.0.0/8".parse::<HostPattern>().unwrap(),
+            HostPattern::Cidr(_)
+        ));
+        assert!(matches!(
+            "::1".parse::<HostPattern>().unwrap(),
+            HostPattern::Cidr(_)
+        ));
+        assert!(matches!(
+            "example.com".parse::<HostPattern>().unwrap(),
+            HostPattern::Domain(_)
+        ));
+        assert!("10.0.0.0/40".parse::<HostPattern>().is_err());
+    }
+
+    #[test]
+    fn parses_port_ranges() {
+        assert_eq!("443".parse::<PortRange>().unwrap(), PortRange(443..=443));
+        assert_eq!(
+            "8000-8080".parse::<PortRange>().unwrap(),
+            PortRange(8000..=8080)
+        );
+        assert_eq!(
+            "0-65535".parse::<PortRange>().unwrap(),
+            PortRange(0..=65535)
+        );
+
+        let range = PortRange::from(8000..=8080);
+        assert!(range.contains(8000));
+        assert!(range.contains(8080));
+        assert!(!range.contains(7999));
+        assert!(!range.contains(8081));
+
+        for invalid in ["", "http", "65536", "8080-8000", "1-", "-1", "1-2-3"] {
+            assert!(invalid.parse::<PortRange>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn parses_rule_files() {
+        let rules: RuleSet = "
+            # Comment
+            deny 10.0.0.0/8 # trailing comment
+            allow .example.com ports=80,443
+            allow *.internal.corp ports=8000-8080 user=alice
+            default allow
+        "
+        .parse()
+        .unwrap();
+
+        assert_eq!(rules.rules().len(), 3);
+        assert_eq!(
+            rules.rules()[1],
+            Rule::new(Action::Allow, HostPattern::Domain(domain(".example.com")))
+                .with_ports([80, 443])
+        );
+        assert_eq!(
+            rules.rules()[2],
+            Rule::new(
+                Action::Allow,
+                HostPattern::Domain(domain("*.internal.corp"))
+            )
+            .with_ports([8000..=8080])
+            .with_user("alice")
+        );
+        assert_eq!(rules.evaluate(&dst("8.8.8.8", 53), &[], &()), Action::Allow);
+
+        // The default action is deny
+        let rules: RuleSet = "allow example.com".parse().unwrap();
+        assert_eq!(rules.evaluate(&dst("8.8.8.8", 53), &[], &()), Action::Deny);
+    }
+
+    #[test]
+    fn rejects_malformed_rule_files() {
+        for (rules, line) in [
+            ("permit any", "Line 1"),
+            ("allow any\ndeny", "Line 2"),
+            ("default", "Line 1"),
+            ("default maybe", "Line 1"),
+            ("allow any ports=http", "Line 1"),
+            ("allow any ports=90-80", "Line 1"),
+            ("allow any color=red", "Line 1"),
+            ("\n\nallow 10.0.0.0/33", "Line 3"),
+        ] {
+            let err = rules.parse::<RuleSet>().unwrap_err();
+            assert!(err.to_string().starts_with(line), "{}: {}", rules, err);
+        }
+    }
+
+    #[test]
+    fn first_matching_rule_decides() {
+        let rules: RuleSet = "
+            allow 10.1.0.0/16
+            deny 10.0.0.0/8
+            deny any ports=25
+            allow any
+        "
+        .parse()
+        .unwrap();
+
+        assert_eq!(
+            rules.evaluate(&dst("10.1.2.3", 80), &[], &()),
+            Action::Allow
+        );
+        assert_eq!(rules.evaluate(&dst("10.2.0.1", 80), &[], &()), Action::Deny);
+        assert_eq!(rules.evaluate(&dst("8.8.8.8", 25), &[], &()), Action::Deny);
+        assert_eq!(rules.evaluate(&dst("8.8.8.8", 80), &[], &()), Action::Allow);
+        assert_eq!(
+            rules.evaluate(&dst("::ffff:10.2.0.1", 80), &[], &()),
+            Action::Deny
+        );
+    }
+
+    #[test]
+    fn evaluates_resolved_domains() {
+        let rules: RuleSet = "
+            deny 10.0.0.0/8
+            allow .example.com
+        "
+        .parse()
+        .unwrap();
+
+        let example = dst("www.example.com", 443);
+        assert_eq!(
+            rules.evaluate(&example, &resolved("93.184.215.14:443"), &()),
+            Action::Allow
+        );
+        assert_eq!(
+            rules.evaluate(&example, &resolved("10.0.0.1:443"), &()),
+            Action::Deny
+        );
+
+        // Every resolved address must be allowed
+        let addrs = [
+            "93.184.215.14:443".parse().unwrap(),
+            "10.0.0.1:443".parse().unwrap(),
+        ];
+        assert_eq!(rules.evaluate(&example, &addrs, &()), Action::Deny);
+    }
+
+    #[test]
+    fn matches_users() {
+        let rules: RuleSet = "
+            allow any user=alice
+            default deny
+        "
+        .parse()
+        .unwrap();
+
+        let destination = dst("example.com", 80);
+        assert_eq!(
+            rules.evaluate(&destination, &[], &"alice".to_string()),
+            Action::Allow
+        );
+        assert_eq!(
+            rules.evaluate(&destination, &[], &"bob".to_string()),
+            Action::Deny
+        );
+        assert_eq!(rules.evaluate(&destination, &[], &()), Action::Deny);
+    }
+
+    #[tokio::test]
+    async fn check_returns_the_vetted_addresses() {
+        let rules: RuleSet = "
+            deny 10.0.0.0/8
+            allow any
+        "
+        .parse()
+        .unwrap();
+        let example = dst("www.example.com", 443);
+
+        let public = StaticResolver(resolved("93.184.215.14:443"));
+        assert_eq!(
+            rules.check(&example, &(), &public).await.unwrap(),
+            resolved("93.184.215.14:443")
+        );
+
+        let private = StaticResolver(resolved("10.0.0.1:443"));
+        assert!(matches!(
+            rules.check(&example, &(), &private).await,
+            Err(Socks5Error::Socks5Error(
+                Reply::ConnectionNotAllowedByRuleset
+            ))
+        ));
+
+        // The CIDR rules can't be evaluated without addresses
+        let empty = StaticResolver(Vec::new());
+        assert!(matches!(
+            rules.check(&example, &(), &empty).await,
+            Err(Socks5Error::Socks5Error(Reply::HostUnreachable))
+        ));
+
+        // Without CIDR rules domains aren't resolved
+        let rules: RuleSet = "allow .example.com".parse().unwrap();
+        assert!(rules
+            .check(&example, &(), &private)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
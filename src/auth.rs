@@ -2,12 +2,27 @@ use std::{future::Future, io};
 
 use tokio::io::{AsyncRead, AsyncWrite};
 
+use crate::context::SessionContext;
+
 pub use crate::protocol::AuthMethod;
 
+mod composite_authenticator;
+pub mod gssapi;
+#[cfg(feature = "htpasswd")]
+mod htpasswd;
 mod no_auth_authenticator;
+mod session_stream;
+mod source_ip_authenticator;
 pub mod username_password_authenticator;
 
+pub use composite_authenticator::{CompositeAuthenticator, MapCredentials};
+#[cfg(feature = "htpasswd")]
+pub use htpasswd::HtpasswdFile;
 pub use no_auth_authenticator::NoAuthAuthenticator;
+pub use session_stream::{AsyncStream, SessionStream};
+pub use source_ip_authenticator::{Allowlist, NoFallback, SourceIpAuthenticator};
+
+use username_password_authenticator::User;
 
 /// # Authenticator Trait
 ///
@@ -29,8 +44,9 @@ where
     /// provided by the client. It returns the selected `AuthMethod`.
     ///
     /// - `methods`: A slice of `AuthMethod` indicating the methods supported by the client.
+    /// - `context`: The context of the session, see `SessionContext`.
     /// - Returns the selected `AuthMethod`.
-    fn select_method(&self, methods: &[AuthMethod]) -> AuthMethod;
+    fn select_method(&self, methods: &[AuthMethod], context: &SessionContext) -> AuthMethod;
 
     /// This method performs the authentication process over the provided connection using the selected
     /// authentication method. It returns a future that resolves to an `io::Result` containing an optional
@@ -38,6 +54,8 @@ where
     ///
     /// - `conn`: A mutable reference to the connection `T`.
     /// - `selected_method`: The `AuthMethod` that was selected during the method selection phase.
+    /// - `context`: The context of the session, values attached to its extensions are available
+    ///   to the method handlers.
     /// - Returns a future that resolves to `io::Result<Option<Self::Credentials>>>`:
     ///   - `Ok(Some(credentials))`: Authentication was successful, and credentials are provided.
     ///   - `Ok(None)`: Authentication failed.
@@ -46,5 +64,55 @@ where
         &mut self,
         conn: &mut T,
         selected_method: AuthMethod,
+        context: &mut SessionContext,
     ) -> impl Future<Output = io::Result<Option<Self::Credentials>>> + Send;
+
+    /// This method authenticates a SOCKS4 or SOCKS4a client by the user id field of its request,
+    /// as SOCKS4 has no authentication sub-negotiation. By default all SOCKS4 clients are rejected.
+    ///
+    /// - `user_id`: The user id sent by the client, may be empty.
+    /// - `context`: The context of the session, as for `authenticate`.
+    /// - Returns a future that resolves to `io::Result<Option<Self::Credentials>>>`, `Ok(None)` if
+    ///   the user id is rejected.
+    fn authenticate_socks4(
+        &mut self,
+        user_id: &str,
+        context: &mut SessionContext,
+    ) -> impl Future<Output = io::Result<Option<Self::Credentials>>> + Send {
+        let _ = (user_id, context);
+        async { Ok(None) }
+    }
+
+    /// This method authenticates an HTTP proxy client by the basic `Proxy-Authorization` header of
+    /// its request. By default all HTTP clients are rejected.
+    ///
+    /// - `user`: The user sent by the client, `None` if the request had no credentials.
+    /// - `context`: The context of the session, as for `authenticate`.
+    /// - Returns a future that resolves to `io::Result<Option<Self::Credentials>>>`, `Ok(None)` if
+    ///   the client is rejected, it's answered with `407 Proxy Authentication Required`.
+    fn authenticate_http(
+        &mut self,
+        user: Option<User>,
+        context: &mut SessionContext,
+    ) -> impl Future<Output = io::Result<Option<Self::Credentials>>> + Send {
+        let _ = (user, context);
+        async { Ok(None) }
+    }
+
+    /// Returns the name the client authenticated as, once the authentication succeeded. It's
+    /// reported to the `SessionObserver` to identify the user of the session, e.g. in access logs.
+    /// By default no name is reported.
+    fn user_name(&self) -> Option<&str> {
+        None
+    }
+
+    /// This method wraps the connection of a SOCKS5 client once it authenticated, for methods
+    /// protecting the rest of the session, e.g. GSSAPI: the request, the reply and the relayed data
+    /// are exchanged through the returned stream. By default the connection is used as is.
+    ///
+    /// - `conn`: The connection the client authenticated on.
+    /// - Returns: The stream the session continues on, see `SessionStream`.
+    fn encapsulate(&mut self, conn: T) -> io::Result<SessionStream<T>> {
+        Ok(SessionStream::plain(conn))
+    }
 }
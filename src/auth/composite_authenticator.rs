@@ -0,0 +1,527 @@
+use std::{io, marker::PhantomData};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tracing::debug;
+
+use crate::{context::SessionContext, protocol::AuthMethod};
+
+use super::{username_password_authenticator::User, Authenticator, SessionStream};
+
+/// Which of the two authenticators of a `CompositeAuthenticator`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    First,
+    Second,
+}
+
+/// The `CompositeAuthenticator` struct negotiates among several authenticators: the method of a
+/// SOCKS5 client is selected by the first authenticator that selects one of the methods the client
+/// offered, and that authenticator authenticates the client. SOCKS4 and HTTP clients, which don't
+/// negotiate a method, are authenticated by the first authenticator accepting them.
+///
+/// A `CompositeAuthenticator` holds two authenticators, more are chained with `or`, in order of
+/// preference. The authenticators must produce the same credentials type, use `MapCredentials` to
+/// convert them, e.g. into an enum of the credentials of every authenticator.
+///
+/// ## Example
+///
+/// Username and password authentication is preferred, clients that don't support it are only
+/// admitted from the trusted network:
+///
+/// ```rust,no_run
+/// # use std::{io, sync::Arc};
+/// use gerevs::{
+///     acl::Cidr,
+///     auth::{
+///         username_password_authenticator::{User, UserAuthenticator, UsernamePasswordAuthenticator},
+///         Allowlist, CompositeAuthenticator, MapCredentials, SourceIpAuthenticator,
+///     },
+///     method_handlers::{TunnelAssociate, TunnelBind, TunnelConnect},
+///     Socks5Server,
+/// };
+/// use tokio::net::TcpListener;
+///
+/// enum Identity {
+///     User(String),
+///     Network,
+/// }
+///
+/// struct Users;
+///
+/// impl UserAuthenticator for Users {
+///     type Credentials = String;
+///
+///     async fn authenticate_user(&mut self, user: User) -> io::Result<Option<String>> {
+///         Ok((user.password == "password").then_some(user.username))
+///     }
+/// }
+///
+/// # async fn run() -> gerevs::Result<()> {
+/// let trusted = Arc::new(Allowlist::new().with_network("10.0.0.0/8".parse::<Cidr>()?, ()));
+///
+/// let listener = TcpListener::bind("0.0.0.0:1080").await?;
+/// let server = Socks5Server::new(
+///     listener,
+///     move || {
+///         CompositeAuthenticator::new(
+///             MapCredentials::new(UsernamePasswordAuthenticator::new(Users), Identity::User),
+///             MapCredentials::new(SourceIpAuthenticator::new(trusted.clone()), |()| {
+///                 Identity::Network
+///             }),
+///         )
+///     },
+///     TunnelConnect::new,
+///     TunnelBind::new,
+///     TunnelAssociate::new,
+/// );
+/// server.run().await
+/// # }
+/// ```
+pub struct CompositeAuthenticator<A, B> {
+    first: A,
+    second: B,
+    /// The authenticator that authenticated the client.
+    authenticated: Option<Side>,
+}
+
+impl<A, B> CompositeAuthenticator<A, B> {
+    /// Creates a new `CompositeAuthenticator` preferring `first` over `second`.
+    pub fn new(first: A, second: B) -> Self {
+        Self {
+            first,
+            second,
+            authenticated: None,
+        }
+    }
+
+    /// Appends `next` to the authenticators, it's the least preferred.
+    pub fn or<C>(self, next: C) -> CompositeAuthenticator<Self, C> {
+        CompositeAuthenticator::new(self, next)
+    }
+}
+
+/// Returns whether `method` is a method the client offered.
+fn offered(method: AuthMethod, methods: &[AuthMethod]) -> bool {
+    method != AuthMethod::NoAcceptableMethods && methods.contains(&method)
+}
+
+impl<T, A, B> Authenticator<T> for CompositeAuthenticator<A, B>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send,
+    A: Authenticator<T> + Send,
+    B: Authenticator<T, Credentials = A::Credentials> + Send,
+    A::Credentials: Send,
+{
+    type Credentials = A::Credentials;
+
+    fn select_method(&self, methods: &[AuthMethod], context: &SessionContext) -> AuthMethod {
+        let method = self.first.select_method(methods, context);
+        if offered(method, methods) {
+            return method;
+        }
+
+        let method = self.second.select_method(methods, context);
+        if offered(method, methods) {
+            return method;
+        }
+
+        AuthMethod::NoAcceptableMethods
+    }
+
+    async fn authenticate(
+        &mut self,
+        conn: &mut T,
+        selected_method: AuthMethod,
+        context: &mut SessionContext,
+    ) -> io::Result<Option<Self::Credentials>> {
+        // The selection is made again, the first authenticator selects the method only if it
+        // selected it among the methods of the client
+        let methods = [selected_method];
+        let selected = if offered(self.first.select_method(&methods, context), &methods) {
+            Some(Side::First)
+        } else if offered(self.second.select_method(&methods, context), &methods) {
+            Some(Side::Second)
+        } else {
+            None
+        };
+
+        let credentials = match selected {
+            Some(Side::First) => {
+                self.first
+                    .authenticate(conn, selected_method, context)
+                    .await?
+            }
+            Some(Side::Second) => {
+                self.second
+                    .authenticate(conn, selected_method, context)
+                    .await?
+            }
+            None => {
+                debug!("No authenticator supports the methods of the client");
+                return Ok(None);
+            }
+        };
+
+        if credentials.is_some() {
+            self.authenticated = selected;
+        }
+        Ok(credentials)
+    }
+
+    async fn authenticate_socks4(
+        &mut self,
+        user_id: &str,
+        context: &mut SessionContext,
+    ) -> io::Result<Option<Self::Credentials>> {
+        if let Some(credentials) = self.first.authenticate_socks4(user_id, context).await? {
+            self.authenticated = Some(Side::First);
+            return Ok(Some(credentials));
+        }
+
+        let credentials = self.second.authenticate_socks4(user_id, context).await?;
+        if credentials.is_some() {
+            self.authenticated = Some(Side::Second);
+        }
+        Ok(credentials)
+    }
+
+    async fn authenticate_http(
+        &mut self,
+        user: Option<User>,
+        context: &mut SessionContext,
+    ) -> io::Result<Option<Self::Credentials>> {
+        if let Some(credentials) = self.first.authenticate_http(user.clone(), context).await? {
+            self.authenticated = Some(Side::First);
+            return Ok(Some(credentials));
+        }
+
+        let credentials = self.second.authenticate_http(user, context).await?;
+        if credentials.is_some() {
+            self.authenticated = Some(Side::Second);
+        }
+        Ok(credentials)
+    }
+
+    /// Returns the name reported by the authenticator that authenticated the client.
+    fn user_name(&self) -> Option<&str> {
+        match self.authenticated? {
+            Side::First => self.first.user_name(),
+            Side::Second => self.second.user_name(),
+        }
+    }
+
+    /// Lets the authenticator that authenticated the client encapsulate the session.
+    fn encapsulate(&mut self, conn: T) -> io::Result<SessionStream<T>> {
+        match self.authenticated {
+            Some(Side::First) => self.first.encapsulate(conn),
+            Some(Side::Second) => self.second.encapsulate(conn),
+            None => Ok(SessionStream::plain(conn)),
+        }
+    }
+}
+
+/// The `MapCredentials` struct converts the credentials produced by an authenticator with a
+/// closure, e.g. to combine authenticators with different credentials types in a
+/// `CompositeAuthenticator`.
+pub struct MapCredentials<A, F, Cr> {
+    inner: A,
+    map: F,
+    credentials: PhantomData<fn() -> Cr>,
+}
+
+impl<A, F, Cr> MapCredentials<A, F, Cr> {
+    /// Creates a new `MapCredentials` converting the credentials of `inner` with `map`.
+    pub fn new(inner: A, map: F) -> Self {
+        Self {
+            inner,
+            map,
+            credentials: PhantomData,
+        }
+    }
+}
+
+impl<T, A, F, Cr> Authenticator<T> for MapCredentials<A, F, Cr>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send,
+    A: Authenticator<T> + Send,
+    F: Fn(A::Credentials) -> Cr + Send,
+{
+    type Credentials = Cr;
+
+    fn select_method(&self, methods: &[AuthMethod], context: &SessionContext) -> AuthMethod {
+        self.inner.select_method(methods, context)
+    }
+
+    async fn authenticate(
+        &mut self,
+        conn: &mut T,
+        selected_method: AuthMethod,
+        context: &mut SessionContext,
+    ) -> io::Result<Option<Cr>> {
+        let credentials = self
+            .inner
+            .authenticate(conn, selected_method, context)
+            .await?;
+        Ok(credentials.map(&self.map))
+    }
+
+    async fn authenticate_socks4(
+        &mut self,
+        user_id: &str,
+        context: &mut SessionContext,
+    ) -> io::Result<Option<Cr>> {
+        let credentials = self.inner.authenticate_socks4(user_id, context).await?;
+        Ok(credentials.map(&self.map))
+    }
+
+    async fn authenticate_http(
+        &mut self,
+        user: Option<User>,
+        context: &mut SessionContext,
+    ) -> io::Result<Option<Cr>> {
+        let credentials = self.inner.authenticate_http(user, context).await?;
+        Ok(credentials.map(&self.map))
+    }
+
+    fn user_name(&self) -> Option<&str> {
+        self.inner.user_name()
+    }
+
+    fn encapsulate(&mut self, conn: T) -> io::Result<SessionStream<T>> {
+        self.inner.encapsulate(conn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{duplex, DuplexStream};
+
+    use super::*;
+
+    /// Supports one method, and accepts one SOCKS4 user id and one HTTP user.
+    struct Fixed {
+        method: AuthMethod,
+        name: &'static str,
+    }
+
+    impl Authenticator<DuplexStream> for Fixed {
+        type Credentials = String;
+
+        fn select_method(&self, methods: &[AuthMethod], _: &SessionContext) -> AuthMethod {
+            if methods.contains(&self.method) {
+                self.method
+            } else {
+                AuthMethod::NoAcceptableMethods
+            }
+        }
+
+        async fn authenticate(
+            &mut self,
+            _: &mut DuplexStream,
+            selected_method: AuthMethod,
+            _: &mut SessionContext,
+        ) -> io::Result<Option<String>> {
+            assert_eq!(
+                selected_method, self.method,
+                "Method of another authenticator"
+            );
+            Ok(Some(self.name.to_string()))
+        }
+
+        async fn authenticate_socks4(
+            &mut self,
+            user_id: &str,
+            _: &mut SessionContext,
+        ) -> io::Result<Option<String>> {
+            Ok((user_id == self.name).then(|| self.name.to_string()))
+        }
+
+        async fn authenticate_http(
+            &mut self,
+            user: Option<User>,
+            _: &mut SessionContext,
+        ) -> io::Result<Option<String>> {
+            Ok(user
+                .filter(|user| user.username == self.name)
+                .map(|user| user.username))
+        }
+
+        fn user_name(&self) -> Option<&str> {
+            Some(self.name)
+        }
+    }
+
+    fn fixed(method: AuthMethod, name: &'static str) -> Fixed {
+        Fixed { method, name }
+    }
+
+    /// Prefers username and password over no authentication.
+    fn composite() -> CompositeAuthenticator<Fixed, Fixed> {
+        CompositeAuthenticator::new(
+            fixed(AuthMethod::UsernamePassword, "alice"),
+            fixed(AuthMethod::NoAuthRequired, "anonymous"),
+        )
+    }
+
+    /// Negotiates a method among `methods` and authenticates with it.
+    async fn negotiate<A>(
+        authenticator: &mut A,
+        methods: &[AuthMethod],
+    ) -> (AuthMethod, Option<A::Credentials>)
+    where
+        A: Authenticator<DuplexStream>,
+    {
+        let mut context = SessionContext::new();
+        let method = authenticator.select_method(methods, &context);
+        let (mut conn, _) = duplex(64);
+        let credentials = authenticator
+            .authenticate(&mut conn, method, &mut context)
+            .await
+            .unwrap();
+        (method, credentials)
+    }
+
+    fn user_name<A: Authenticator<DuplexStream>>(authenticator: &A) -> Option<&str> {
+        authenticator.user_name()
+    }
+
+    #[test]
+    fn selects_methods_in_order_of_preference() {
+        let authenticator = composite().or(fixed(AuthMethod::Gssapi, "kerberos"));
+        let select = |methods: &[AuthMethod]| {
+            Authenticator::<DuplexStream>::select_method(
+                &authenticator,
+                methods,
+                &SessionContext::new(),
+            )
+        };
+
+        assert_eq!(
+            select(&[AuthMethod::NoAuthRequired, AuthMethod::UsernamePassword]),
+            AuthMethod::UsernamePassword
+        );
+        assert_eq!(
+            select(&[AuthMethod::NoAuthRequired, AuthMethod::Gssapi]),
+            AuthMethod::NoAuthRequired
+        );
+        assert_eq!(select(&[AuthMethod::Gssapi]), AuthMethod::Gssapi);
+        assert_eq!(
+            select(&[AuthMethod::PrivateMethods(0x80)]),
+            AuthMethod::NoAcceptableMethods
+        );
+        assert_eq!(select(&[]), AuthMethod::NoAcceptableMethods);
+    }
+
+    #[tokio::test]
+    async fn authenticates_with_the_owner_of_the_method() {
+        let mut authenticator = composite();
+        assert_eq!(user_name(&authenticator), None);
+        assert_eq!(
+            negotiate(&mut authenticator, &[AuthMethod::NoAuthRequired]).await,
+            (AuthMethod::NoAuthRequired, Some("anonymous".to_string()))
+        );
+        assert_eq!(user_name(&authenticator), Some("anonymous"));
+
+        let mut authenticator = composite().or(fixed(AuthMethod::Gssapi, "kerberos"));
+        assert_eq!(
+            negotiate(
+                &mut authenticator,
+                &[AuthMethod::Gssapi, AuthMethod::UsernamePassword]
+            )
+            .await,
+            (AuthMethod::UsernamePassword, Some("alice".to_string()))
+        );
+        assert_eq!(user_name(&authenticator), Some("alice"));
+
+        let mut authenticator = composite().or(fixed(AuthMethod::Gssapi, "kerberos"));
+        assert_eq!(
+            negotiate(&mut authenticator, &[AuthMethod::Gssapi]).await,
+            (AuthMethod::Gssapi, Some("kerberos".to_string()))
+        );
+        assert_eq!(user_name(&authenticator), Some("kerberos"));
+
+        // A method no authenticator supports isn't authenticated
+        let mut authenticator = composite();
+        let (mut conn, _) = duplex(64);
+        let credentials = authenticator
+            .authenticate(
+                &mut conn,
+                AuthMethod::PrivateMethods(0x80),
+                &mut SessionContext::new(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(credentials, None);
+        assert_eq!(user_name(&authenticator), None);
+    }
+
+    #[tokio::test]
+    async fn socks4_and_http_try_every_authenticator() {
+        let mut authenticator = composite();
+        let mut context = SessionContext::new();
+        let credentials = Authenticator::<DuplexStream>::authenticate_socks4(
+            &mut authenticator,
+            "anonymous",
+            &mut context,
+        )
+        .await
+        .unwrap();
+        assert_eq!(credentials, Some("anonymous".to_string()));
+        assert_eq!(user_name(&authenticator), Some("anonymous"));
+
+        let mut authenticator = composite();
+        let user = User {
+            username: "alice".to_string(),
+            password: "secret".into(),
+        };
+        let credentials = Authenticator::<DuplexStream>::authenticate_http(
+            &mut authenticator,
+            Some(user),
+            &mut context,
+        )
+        .await
+        .unwrap();
+        assert_eq!(credentials, Some("alice".to_string()));
+        assert_eq!(user_name(&authenticator), Some("alice"));
+
+        let mut authenticator = composite();
+        let credentials = Authenticator::<DuplexStream>::authenticate_socks4(
+            &mut authenticator,
+            "mallory",
+            &mut context,
+        )
+        .await
+        .unwrap();
+        assert_eq!(credentials, None);
+        assert_eq!(user_name(&authenticator), None);
+    }
+
+    #[tokio::test]
+    async fn map_credentials_converts_every_authentication() {
+        let mut authenticator = MapCredentials::new(
+            fixed(AuthMethod::UsernamePassword, "alice"),
+            |name: String| name.len(),
+        );
+        assert_eq!(
+            negotiate(
+                &mut authenticator,
+                &[AuthMethod::NoAuthRequired, AuthMethod::UsernamePassword]
+            )
+            .await,
+            (AuthMethod::UsernamePassword, Some(5))
+        );
+        assert_eq!(user_name(&authenticator), Some("alice"));
+
+        let mut context = SessionContext::new();
+        for (user_id, expected) in [("alice", Some(5)), ("bob", None)] {
+            let credentials = Authenticator::<DuplexStream>::authenticate_socks4(
+                &mut authenticator,
+                user_id,
+                &mut context,
+            )
+            .await
+            .unwrap();
+            assert_eq!(credentials, expected);
+        }
+    }
+}
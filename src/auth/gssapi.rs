@@ -0,0 +1,395 @@
+//! # GSSAPI Authentication Module
+//!
+//! This module implements the GSSAPI authentication method of SOCKS5, as specified in
+//! [RFC 1961](https://datatracker.ietf.org/doc/html/rfc1961). The `GssapiAuthenticator` handles
+//! the framing of the method: the exchange of context establishment tokens, the negotiation of
+//! the protection level and the encapsulation of the rest of the session in `GssapiStream`, see
+//! `Authenticator::encapsulate`.
+//!
+//! The security context itself, e.g. Kerberos through a GSSAPI library, is provided by an
+//! implementation of the `SecurityContext` trait. `TestSecurityContext`, enabled by the
+//! `test-util` feature, is an insecure mechanism for exercising the method without a KDC.
+//!
+//! ## Example
+//!
+//! ```rust,no_run
+//! use std::io;
+//!
+//! use gerevs::{
+//!     auth::gssapi::{GssapiAuthenticator, ProtectionLevel, SecurityContext, Step},
+//!     method_handlers::{TunnelAssociate, TunnelBind, TunnelConnect},
+//!     Socks5Server,
+//! };
+//! use tokio::net::TcpListener;
+//!
+//! /// The acceptor context of a session, e.g. bindings to the system's GSSAPI library.
+//! struct KerberosContext;
+//!
+//! impl SecurityContext for KerberosContext {
+//!     fn accept(&mut self, token: &[u8]) -> io::Result<Step> {
+//!         // gss_accept_sec_context
+//! #       Err(io::ErrorKind::Unsupported.into())
+//!     }
+//!
+//!     fn principal(&self) -> Option<&str> {
+//!         // The source name of the established context
+//! #       None
+//!     }
+//!
+//!     fn wrap(&mut self, message: &[u8], confidential: bool) -> io::Result<Vec<u8>> {
+//!         // gss_wrap
+//! #       Err(io::ErrorKind::Unsupported.into())
+//!     }
+//!
+//!     fn unwrap(&mut self, token: &[u8]) -> io::Result<(Vec<u8>, bool)> {
+//!         // gss_unwrap
+//! #       Err(io::ErrorKind::Unsupported.into())
+//!     }
+//! }
+//!
+//! # async fn run() -> gerevs::Result<()> {
+//! let listener = TcpListener::bind("0.0.0.0:1080").await?;
+//! let server = Socks5Server::new(
+//!     listener,
+//!     || {
+//!         GssapiAuthenticator::new(KerberosContext)
+//!             .with_required_protection(ProtectionLevel::Confidentiality)
+//!     },
+//!     TunnelConnect::new,
+//!     TunnelBind::new,
+//!     TunnelAssociate::new,
+//! );
+//! server.run().await
+//! # }
+//! ```
+
+use std::io::{self, ErrorKind};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tracing::debug;
+
+use crate::{
+    context::SessionContext,
+    protocol::{
+        gssapi::{read_message, write_abort, write_message, MessageType},
+        AuthMethod,
+    },
+};
+
+use super::{Authenticator, SessionStream};
+
+mod stream;
+#[cfg(any(test, feature = "test-util"))]
+mod test_context;
+
+pub use stream::GssapiStream;
+#[cfg(any(test, feature = "test-util"))]
+pub use test_context::TestSecurityContext;
+
+/// The outcome of a context establishment token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// The context needs more tokens from the client, the output token is sent to the client.
+    Continue(Vec<u8>),
+    /// The context is established, the output token, if not empty, is sent to the client.
+    Complete(Vec<u8>),
+}
+
+/// The `SecurityContext` trait is the acceptor side of a GSSAPI security context, e.g. a thin
+/// wrapper of `gss_accept_sec_context`, `gss_wrap` and `gss_unwrap`. Every session needs a context
+/// of its own.
+pub trait SecurityContext {
+    /// Processes a context establishment token sent by the client.
+    ///
+    /// - `token`: The token sent by the client.
+    /// - Returns: The next step of the establishment, or an error if the context can't be
+    ///   established, in which case the client is sent an abort message.
+    fn accept(&mut self, token: &[u8]) -> io::Result<Step>;
+
+    /// Returns the name of the authenticated client, once the context is established.
+    fn principal(&self) -> Option<&str>;
+
+    /// Protects `message` for the client.
+    ///
+    /// - `confidential`: Whether the message should be encrypted, otherwise it's only protected
+    ///   for integrity.
+    /// - Returns: The token holding the protected message.
+    fn wrap(&mut self, message: &[u8], confidential: bool) -> io::Result<Vec<u8>>;
+
+    /// Verifies a token sent by the client and extracts its message.
+    ///
+    /// - Returns: The message and whether it was encrypted, or an error if the token fails the
+    ///   verification.
+    fn unwrap(&mut self, token: &[u8]) -> io::Result<(Vec<u8>, bool)>;
+}
+
+/// The protection levels of the session, negotiated once the context is established.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtectionLevel {
+    /// Messages are protected for integrity.
+    Integrity = 0x01,
+    /// Messages are protected for integrity and encrypted.
+    Confidentiality = 0x02,
+    /// Every message chooses its protection, messages sent by the server are encrypted.
+    PerMessage = 0x03,
+}
+
+impl ProtectionLevel {
+    pub(crate) fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0x01 => Some(ProtectionLevel::Integrity),
+            0x02 => Some(ProtectionLevel::Confidentiality),
+            0x03 => Some(ProtectionLevel::PerMessage),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn to_u8(self) -> u8 {
+        self as u8
+    }
+}
+
+/// The `GssapiAuthenticator` struct authenticates clients with the GSSAPI method, using the
+/// security context `S`. The credentials of a client are its principal name.
+pub struct GssapiAuthenticator<S> {
+    /// The security context, moved into the `GssapiStream` once the session is encapsulated.
+    context: Option<S>,
+    required_protection: Option<ProtectionLevel>,
+    protection: Option<ProtectionLevel>,
+    principal: Option<String>,
+}
+
+impl<S> GssapiAuthenticator<S> {
+    /// Creates a new `GssapiAuthenticator` with the security context of the session.
+    pub fn new(context: S) -> Self {
+        Self {
+            context: Some(context),
+            required_protection: None,
+            protection: None,
+            principal: None,
+        }
+    }
+
+    /// Sets the protection level used regardless of the level the client asked for. By default
+    /// the client's level is used.
+    pub fn with_required_protection(mut self, protection: ProtectionLevel) -> Self {
+        self.required_protection = Some(protection);
+        self
+    }
+
+    /// Returns the protection level negotiated with the client, once authenticated.
+    pub fn protection(&self) -> Option<ProtectionLevel> {
+        self.protection
+    }
+}
+
+impl<S> GssapiAuthenticator<S>
+where
+    S: SecurityContext,
+{
+    /// Exchanges context establishment tokens with the client until the context is established.
+    ///
+    /// - Returns: Whether the context was established.
+    async fn establish_context<T>(context: &mut S, conn: &mut T) -> io::Result<bool>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+    {
+        loop {
+            let (message_type, token) = read_message(conn).await?;
+            match message_type {
+                MessageType::Authentication => {}
+                MessageType::Abort => {
+                    debug!("Client aborted the GSSAPI context establishment");
+                    return Ok(false);
+                }
+                _ => {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        "Unexpected GSSAPI message type",
+                    ))
+                }
+            }
+
+            match context.accept(&token) {
+                Ok(Step::Continue(token)) => {
+                    write_message(conn, MessageType::Authentication, &token).await?;
+                }
+                Ok(Step::Complete(token)) => {
+                    if !token.is_empty() {
+                        write_message(conn, MessageType::Authentication, &token).await?;
+                    }
+                    return Ok(true);
+                }
+                Err(err) => {
+                    debug!("GSSAPI context establishment failed: {}", err);
+                    write_abort(conn).await?;
+                    return Ok(false);
+                }
+            }
+        }
+    }
+
+    /// Answers the protection level requested by the client with the level of the session,
+    /// `required` if set.
+    async fn negotiate_protection<T>(
+        context: &mut S,
+        conn: &mut T,
+        required: Option<ProtectionLevel>,
+    ) -> io::Result<ProtectionLevel>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+    {
+        let (message_type, token) = read_message(conn).await?;
+        if message_type != MessageType::Protection {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Expected a GSSAPI protection level message",
+            ));
+        }
+
+        let (level, _) = context.unwrap(&token)?;
+        let requested = match level[..] {
+            [level] => ProtectionLevel::from_u8(level),
+            _ => None,
+        }
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "Invalid protection level"))?;
+
+        let protection = required.unwrap_or(requested);
+        debug!(
+            "Client requested {:?} protection, using {:?}",
+            requested, protection
+        );
+        let token = context.wrap(&[protection.to_u8()], false)?;
+        write_message(conn, MessageType::Protection, &token).await?;
+        Ok(protection)
+    }
+}
+
+impl<T, S> Authenticator<T> for GssapiAuthenticator<S>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: SecurityContext + Send + Unpin + 'static,
+{
+    type Credentials = String;
+
+    /// Selects the `Gssapi` method if the client offered it.
+    fn select_method(&self, methods: &[AuthMethod], _: &SessionContext) -> AuthMethod {
+        if methods.contains(&AuthMethod::Gssapi) {
+            AuthMethod::Gssapi
+        } else {
+            AuthMethod::NoAcceptableMethods
+        }
+    }
+
+    async fn authenticate(
+        &mut self,
+        conn: &mut T,
+        _: AuthMethod,
+        _: &mut SessionContext,
+    ) -> io::Result<Option<String>> {
+        let mut context = self
+            .context
+            .take()
+            .ok_or_else(|| io::Error::other("GSSAPI context was already used"))?;
+        if !Self::establish_context(&mut context, conn).await? {
+            return Ok(None);
+        }
+
+        let Some(principal) = context.principal().map(str::to_string) else {
+            debug!("GSSAPI context has no client principal");
+            return Ok(None);
+        };
+
+        let protection =
+            Self::negotiate_protection(&mut context, conn, self.required_protection).await?;
+        self.context = Some(context);
+        self.protection = Some(protection);
+        self.principal = Some(principal.clone());
+        Ok(Some(principal))
+    }
+
+    /// Returns the principal name of the client.
+    fn user_name(&self) -> Option<&str> {
+        self.principal.as_deref()
+    }
+
+    /// Encapsulates the session in a `GssapiStream` with the negotiated protection level.
+    fn encapsulate(&mut self, conn: T) -> io::Result<SessionStream<T>> {
+        match (self.protection, self.context.take()) {
+            (Some(protection), Some(context)) => Ok(SessionStream::encapsulated(
+                GssapiStream::new(conn, context, protection),
+            )),
+            _ => Err(io::Error::new(
+                ErrorKind::PermissionDenied,
+                "GSSAPI context isn't established",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn authenticates_and_encapsulates_the_session() {
+        let (mut client, server) = duplex(1024);
+        let server = tokio::spawn(async move {
+            let mut server = server;
+            let mut authenticator = GssapiAuthenticator::new(TestSecurityContext::acceptor())
+                .with_required_protection(ProtectionLevel::Confidentiality);
+            let credentials = authenticator
+                .authenticate(&mut server, AuthMethod::Gssapi, &mut SessionContext::new())
+                .await?;
+            let protection = authenticator.protection();
+
+            let mut session = authenticator.encapsulate(server)?;
+            let mut request = [0; 4];
+            session.read_exact(&mut request).await?;
+            session.write_all(b"pong").await?;
+            session.flush().await?;
+            io::Result::Ok((credentials, protection, request))
+        });
+
+        // Context establishment
+        let mut context = TestSecurityContext::initiator("alice@EXAMPLE.COM");
+        let mut token = Vec::new();
+        while let Step::Continue(output) = context.initiate(&token).unwrap() {
+            write_message(&mut client, MessageType::Authentication, &output)
+                .await
+                .unwrap();
+            let (message_type, input) = read_message(&mut client).await.unwrap();
+            assert_eq!(message_type, MessageType::Authentication);
+            token = input;
+        }
+
+        // Protection negotiation, the server requires confidentiality
+        let level = context
+            .wrap(&[ProtectionLevel::Integrity.to_u8()], false)
+            .unwrap();
+        write_message(&mut client, MessageType::Protection, &level)
+            .await
+            .unwrap();
+        let (message_type, level) = read_message(&mut client).await.unwrap();
+        assert_eq!(message_type, MessageType::Protection);
+        let (level, _) = context.unwrap(&level).unwrap();
+        assert_eq!(level, [ProtectionLevel::Confidentiality.to_u8()]);
+
+        // Encapsulated session
+        let request = context.wrap(b"ping", true).unwrap();
+        write_message(&mut client, MessageType::Encapsulation, &request)
+            .await
+            .unwrap();
+        let (message_type, response) = read_message(&mut client).await.unwrap();
+        assert_eq!(message_type, MessageType::Encapsulation);
+        assert_eq!(context.unwrap(&response).unwrap(), (b"pong".to_vec(), true));
+
+        let (credentials, protection, request) = server.await.unwrap().unwrap();
+        assert_eq!(credentials.as_deref(), Some("alice@EXAMPLE.COM"));
+        assert_eq!(protection, Some(ProtectionLevel::Confidentiality));
+        assert_eq!(&request, b"ping");
+    }
+}
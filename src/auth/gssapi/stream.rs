@@ -0,0 +1,218 @@
+use std::{
+    io::{self, ErrorKind},
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::protocol::gssapi::{message_to_bytes, parse_message_type, MessageType};
+
+use super::{ProtectionLevel, SecurityContext};
+
+/// The most data wrapped in one message, leaving room in the token for the overhead of the
+/// security context.
+const MAX_MESSAGE_LEN: usize = 16 * 1024;
+
+/// The `GssapiStream` struct encapsulates a session once the GSSAPI method negotiated its
+/// protection level: data written is wrapped by the security context and sent as encapsulation
+/// messages, and the messages read are unwrapped.
+///
+/// Writes are buffered until the whole message is sent, they're completed by the next write or
+/// by flushing the stream.
+pub struct GssapiStream<T, S> {
+    inner: T,
+    context: S,
+    protection: ProtectionLevel,
+    /// The header of the message being read: VER+MTYP+LEN.
+    header: [u8; 4],
+    header_filled: usize,
+    /// The token of the message being read, once its header was read.
+    token: Option<Vec<u8>>,
+    token_filled: usize,
+    /// The unwrapped message, not yet read by the caller.
+    message: Vec<u8>,
+    message_pos: usize,
+    /// The message being written.
+    pending: Vec<u8>,
+    pending_pos: usize,
+}
+
+impl<T, S> GssapiStream<T, S> {
+    /// Creates a new `GssapiStream` encapsulating `inner` with the established `context`.
+    ///
+    /// - `protection`: The negotiated protection level. With `Confidentiality` messages that
+    ///   weren't encrypted are rejected.
+    pub fn new(inner: T, context: S, protection: ProtectionLevel) -> Self {
+        Self {
+            inner,
+            context,
+            protection,
+            header: [0; 4],
+            header_filled: 0,
+            token: None,
+            token_filled: 0,
+            message: Vec::new(),
+            message_pos: 0,
+            pending: Vec::new(),
+            pending_pos: 0,
+        }
+    }
+}
+
+impl<T, S> GssapiStream<T, S>
+where
+    T: AsyncWrite + Unpin,
+{
+    /// Writes the rest of the pending message.
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.pending_pos < self.pending.len() {
+            let written = ready!(
+                Pin::new(&mut self.inner).poll_write(cx, &self.pending[self.pending_pos..])
+            )?;
+            if written == 0 {
+                return Poll::Ready(Err(ErrorKind::WriteZero.into()));
+            }
+            self.pending_pos += written;
+        }
+        self.pending.clear();
+        self.pending_pos = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Reads into `buf[*filled..]`, failing on an end of stream.
+fn poll_fill<T>(
+    inner: &mut T,
+    cx: &mut Context<'_>,
+    buf: &mut [u8],
+    filled: &mut usize,
+) -> Poll<io::Result<()>>
+where
+    T: AsyncRead + Unpin,
+{
+    while *filled < buf.len() {
+        let mut read_buf = ReadBuf::new(&mut buf[*filled..]);
+        ready!(Pin::new(&mut *inner).poll_read(cx, &mut read_buf))?;
+        if read_buf.filled().is_empty() {
+            return Poll::Ready(Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "GSSAPI message was cut",
+            )));
+        }
+        *filled += read_buf.filled().len();
+    }
+    Poll::Ready(Ok(()))
+}
+
+impl<T, S> AsyncRead for GssapiStream<T, S>
+where
+    T: AsyncRead + Unpin,
+    S: SecurityContext + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.message_pos < this.message.len() {
+                let len = buf.remaining().min(this.message.len() - this.message_pos);
+                buf.put_slice(&this.message[this.message_pos..this.message_pos + len]);
+                this.message_pos += len;
+                return Poll::Ready(Ok(()));
+            }
+
+            let token = match &mut this.token {
+                Some(token) => token,
+                None => {
+                    // The end of the stream is only clean between messages
+                    if this.header_filled == 0 {
+                        let mut read_buf = ReadBuf::new(&mut this.header);
+                        ready!(Pin::new(&mut this.inner).poll_read(cx, &mut read_buf))?;
+                        if read_buf.filled().is_empty() {
+                            return Poll::Ready(Ok(()));
+                        }
+                        this.header_filled = read_buf.filled().len();
+                    }
+                    ready!(poll_fill(
+                        &mut this.inner,
+                        cx,
+                        &mut this.header,
+                        &mut this.header_filled
+                    ))?;
+
+                    let [version, message_type, len @ ..] = this.header;
+                    if parse_message_type(version, message_type)? != MessageType::Encapsulation {
+                        return Poll::Ready(Err(io::Error::new(
+                            ErrorKind::InvalidData,
+                            "Expected a GSSAPI encapsulation message",
+                        )));
+                    }
+                    this.token_filled = 0;
+                    this.token.insert(vec![0; u16::from_be_bytes(len) as usize])
+                }
+            };
+            ready!(poll_fill(
+                &mut this.inner,
+                cx,
+                token,
+                &mut this.token_filled
+            ))?;
+
+            let (message, confidential) = this.context.unwrap(token)?;
+            if this.protection == ProtectionLevel::Confidentiality && !confidential {
+                return Poll::Ready(Err(io::Error::new(
+                    ErrorKind::PermissionDenied,
+                    "GSSAPI message wasn't encrypted",
+                )));
+            }
+            this.message = message;
+            this.message_pos = 0;
+            this.header_filled = 0;
+            this.token = None;
+        }
+    }
+}
+
+impl<T, S> AsyncWrite for GssapiStream<T, S>
+where
+    T: AsyncWrite + Unpin,
+    S: SecurityContext + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        let len = buf.len().min(MAX_MESSAGE_LEN);
+        let confidential = this.protection != ProtectionLevel::Integrity;
+        let token = this.context.wrap(&buf[..len], confidential)?;
+        this.pending = message_to_bytes(MessageType::Encapsulation, &token)?;
+
+        // Start sending right away, the rest is sent by the next write or flush
+        if let Poll::Ready(Err(err)) = this.poll_pending(cx) {
+            return Poll::Ready(Err(err));
+        }
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,199 @@
+use std::{
+    io::{self, ErrorKind},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{SecurityContext, Step};
+
+/// The flag of wrapped messages that were encrypted.
+const CONFIDENTIAL: u8 = 0x01;
+/// The length of a wrapped message header: flag+checksum.
+const WRAP_HEADER_LEN: usize = 5;
+/// The key the messages are "encrypted" with.
+const KEY: u8 = 0x5A;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Initial,
+    /// The acceptor sent a challenge, or the initiator sent its greeting.
+    Challenged(u64),
+    Established,
+}
+
+/// The `TestSecurityContext` struct is an insecure security context for testing the GSSAPI method
+/// without a KDC. It must never be used in production: the client is trusted to tell its own
+/// principal name, and messages are merely checksummed and obfuscated.
+///
+/// The context is established in two rounds, the client greets the server with `hello:<principal>`,
+/// the server sends back `challenge:<nonce>`, the client answers `response:<nonce>` and the
+/// server completes the context with `ok`.
+///
+/// The acceptor is used with a `GssapiAuthenticator`, the initiator plays the client side. It's
+/// only available with the `test-util` feature.
+#[derive(Debug, Clone)]
+pub struct TestSecurityContext {
+    initiator: bool,
+    principal: Option<String>,
+    state: State,
+}
+
+impl TestSecurityContext {
+    /// Creates the server side of a context.
+    pub fn acceptor() -> Self {
+        Self {
+            initiator: false,
+            principal: None,
+            state: State::Initial,
+        }
+    }
+
+    /// Creates the client side of a context, authenticating as `principal`.
+    pub fn initiator(principal: impl Into<String>) -> Self {
+        Self {
+            initiator: true,
+            principal: Some(principal.into()),
+            state: State::Initial,
+        }
+    }
+
+    /// Returns whether the context is established.
+    pub fn is_established(&self) -> bool {
+        self.state == State::Established
+    }
+
+    /// Processes a token sent by the server, the client side of `accept`. The first call takes
+    /// an empty token.
+    pub fn initiate(&mut self, token: &[u8]) -> io::Result<Step> {
+        if !self.initiator {
+            return Err(invalid_token("Context isn't an initiator"));
+        }
+
+        match self.state {
+            State::Initial => {
+                self.state = State::Challenged(0);
+                let principal = self.principal.as_deref().unwrap_or_default();
+                Ok(Step::Continue(format!("hello:{principal}").into_bytes()))
+            }
+            State::Challenged(_) if token == b"ok" => {
+                self.state = State::Established;
+                Ok(Step::Complete(Vec::new()))
+            }
+            State::Challenged(_) => {
+                let nonce = parse_token(token, "challenge:")?;
+                self.state = State::Challenged(nonce);
+                Ok(Step::Continue(format!("response:{nonce}").into_bytes()))
+            }
+            State::Established => Err(invalid_token("Context is already established")),
+        }
+    }
+}
+
+impl SecurityContext for TestSecurityContext {
+    fn accept(&mut self, token: &[u8]) -> io::Result<Step> {
+        if self.initiator {
+            return Err(invalid_token("Context isn't an acceptor"));
+        }
+
+        match self.state {
+            State::Initial => {
+                let principal = token
+                    .strip_prefix(b"hello:")
+                    .and_then(|principal| std::str::from_utf8(principal).ok())
+                    .filter(|principal| !principal.is_empty())
+                    .ok_or_else(|| invalid_token("Expected a greeting"))?;
+                self.principal = Some(principal.to_string());
+
+                let nonce = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |time| time.as_nanos() as u64);
+                self.state = State::Challenged(nonce);
+                Ok(Step::Continue(format!("challenge:{nonce}").into_bytes()))
+            }
+            State::Challenged(nonce) => {
+                if parse_token(token, "response:")? != nonce {
+                    return Err(io::Error::new(
+                        ErrorKind::PermissionDenied,
+                        "Wrong challenge response",
+                    ));
+                }
+                self.state = State::Established;
+                Ok(Step::Complete(b"ok".to_vec()))
+            }
+            State::Established => Err(invalid_token("Context is already established")),
+        }
+    }
+
+    fn principal(&self) -> Option<&str> {
+        match self.state {
+            State::Established => self.principal.as_deref(),
+            _ => None,
+        }
+    }
+
+    fn wrap(&mut self, message: &[u8], confidential: bool) -> io::Result<Vec<u8>> {
+        self.ensure_established()?;
+
+        let mut token = Vec::with_capacity(WRAP_HEADER_LEN + message.len());
+        token.push(if confidential { CONFIDENTIAL } else { 0 });
+        token.extend_from_slice(&checksum(message).to_be_bytes());
+        if confidential {
+            token.extend(message.iter().map(|byte| byte ^ KEY));
+        } else {
+            token.extend_from_slice(message);
+        }
+        Ok(token)
+    }
+
+    fn unwrap(&mut self, token: &[u8]) -> io::Result<(Vec<u8>, bool)> {
+        self.ensure_established()?;
+
+        if token.len() < WRAP_HEADER_LEN {
+            return Err(invalid_token("Wrapped token is too short"));
+        }
+        let confidential = token[0] == CONFIDENTIAL;
+        let expected = u32::from_be_bytes([token[1], token[2], token[3], token[4]]);
+        let message: Vec<u8> = if confidential {
+            token[WRAP_HEADER_LEN..]
+                .iter()
+                .map(|byte| byte ^ KEY)
+                .collect()
+        } else {
+            token[WRAP_HEADER_LEN..].to_vec()
+        };
+
+        if checksum(&message) != expected {
+            return Err(invalid_token("Integrity check failed"));
+        }
+        Ok((message, confidential))
+    }
+}
+
+impl TestSecurityContext {
+    fn ensure_established(&self) -> io::Result<()> {
+        if self.is_established() {
+            Ok(())
+        } else {
+            Err(invalid_token("Context isn't established"))
+        }
+    }
+}
+
+fn invalid_token(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+/// Parses the number of a `<prefix><number>` token.
+fn parse_token(token: &[u8], prefix: &str) -> io::Result<u64> {
+    token
+        .strip_prefix(prefix.as_bytes())
+        .and_then(|nonce| std::str::from_utf8(nonce).ok())
+        .and_then(|nonce| nonce.parse().ok())
+        .ok_or_else(|| invalid_token("Unexpected token"))
+}
+
+/// The FNV-1a hash of `data`.
+fn checksum(data: &[u8]) -> u32 {
+    data.iter().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ u32::from(*byte)).wrapping_mul(0x0100_0193)
+    })
+}
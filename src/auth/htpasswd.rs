@@ -0,0 +1,405 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use tracing::{debug, info, warn};
+
+use self::hash::PasswordHash;
+
+use super::username_password_authenticator::{User, UserAuthenticator};
+
+mod hash;
+mod sha_crypt;
+
+/// The users of an htpasswd file.
+struct Users {
+    /// The password hashes, by user name.
+    hashes: HashMap<String, PasswordHash>,
+    /// The hash of the first user of the file, verified for users missing from the file so they
+    /// take as long to reject as its users.
+    unknown: Option<PasswordHash>,
+}
+
+/// What the loaded users were read from, compared to the file to notice it changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Version {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+impl Version {
+    fn of(metadata: &fs::Metadata) -> Self {
+        Self {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+        }
+    }
+}
+
+struct State {
+    users: Arc<Users>,
+    version: Version,
+}
+
+/// The `HtpasswdFile` struct is a `UserAuthenticator` backed by an htpasswd file: a `user:hash`
+/// line per user, with bcrypt (`$2y$`, as written by `htpasswd -B`), Argon2 (`$argon2id$`) or
+/// SHA-crypt (`$5$`, `$6$`) hashes. Empty lines and lines starting with `#` are ignored. Other
+/// schemes, e.g. MD5 or plaintext passwords, are rejected when the file is loaded, as are SHA-crypt
+/// hashes of more than 1,000,000 rounds.
+///
+/// Passwords are verified on the blocking thread pool, as the hashes are slow by design. Users
+/// missing from the file are verified against the hash of the first user of the file, so they take
+/// as long to reject as wrong passwords when the users share the scheme and cost of their hashes.
+///
+/// The file is reloaded when it changed on disk, which is checked before every authentication.
+/// Authentications don't wait for a reload, they use the previous users until it's done. If the new
+/// file can't be loaded, the previous users are kept until it's fixed.
+///
+/// `HtpasswdFile` is cheap to clone, the clones share the loaded users. The credentials of a client
+/// are its username. It's available with the `htpasswd` feature, enabled by default.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use gerevs::{
+///     auth::{username_password_authenticator::UsernamePasswordAuthenticator, HtpasswdFile},
+///     method_handlers::{TunnelAssociate, TunnelBind, TunnelConnect},
+///     Socks5Server,
+/// };
+/// use tokio::net::TcpListener;
+///
+/// # async fn run() -> gerevs::Result<()> {
+/// let users = HtpasswdFile::open("/etc/gerevs/htpasswd")?;
+///
+/// let listener = TcpListener::bind("0.0.0.0:1080").await?;
+/// let server = Socks5Server::new(
+///     listener,
+///     move || UsernamePasswordAuthenticator::new(users.clone()),
+///     TunnelConnect::new,
+///     TunnelBind::new,
+///     TunnelAssociate::new,
+/// );
+/// server.run().await
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct HtpasswdFile {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    path: PathBuf,
+    state: Mutex<State>,
+    /// Held while the file is reloaded, so it's reloaded once when it changes.
+    reloading: Mutex<()>,
+}
+
+impl HtpasswdFile {
+    /// Loads the htpasswd file at `path`.
+    ///
+    /// - Returns: An error if the file can't be read, or one of its lines is malformed or has an
+    ///   unsupported hash.
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let (users, version) = load(&path)?;
+        info!(
+            "Loaded {} users from {}",
+            users.hashes.len(),
+            path.display()
+        );
+
+        Ok(Self {
+            inner: Arc::new(Inner {
+                path,
+                state: Mutex::new(State {
+                    users: Arc::new(users),
+                    version,
+                }),
+                reloading: Mutex::new(()),
+            }),
+        })
+    }
+
+    /// Returns the path of the file.
+    pub fn path(&self) -> &Path {
+        &self.inner.path
+    }
+}
+
+impl Inner {
+    /// Returns the loaded users and the version they were read from.
+    fn state(&self) -> (Arc<Users>, Version) {
+        let state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        (state.users.clone(), state.version)
+    }
+
+    /// Returns the users, reloading the file first if it changed. The file is read without holding
+    /// the lock of the users, and while another authentication reloads it the previous users are
+    /// returned.
+    fn users(&self) -> Arc<Users> {
+        let (users, version) = self.state();
+        let changed =
+            fs::metadata(&self.path).is_ok_and(|metadata| Version::of(&metadata) != version);
+        if !changed {
+            return users;
+        }
+        let Ok(_reloading) = self.reloading.try_lock() else {
+            return users;
+        };
+
+        // The file may have been reloaded since it was checked
+        let (users, version) = self.state();
+        let changed =
+            fs::metadata(&self.path).is_ok_and(|metadata| Version::of(&metadata) != version);
+        if !changed {
+            return users;
+        }
+
+        match load(&self.path) {
+            Ok((users, version)) => {
+                info!(
+                    "Reloaded {} users from {}",
+                    users.hashes.len(),
+                    self.path.display()
+                );
+                let users = Arc::new(users);
+                let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+                state.users = users.clone();
+                state.version = version;
+                users
+            }
+            Err(err) => {
+                warn!(
+                    "Failed reloading {}, keeping the previous users: {}",
+                    self.path.display(),
+                    err
+                );
+                users
+            }
+        }
+    }
+
+    fn authenticate(&self, user: User) -> Option<String> {
+        let users = self.users();
+        match users.hashes.get(&user.username) {
+            Some(hash) if hash.verify(user.password.expose_secret().as_bytes()) => {
+                Some(user.username)
+            }
+            Some(_) => {
+                debug!("Wrong password for user {:?}", user.username);
+                None
+            }
+            None => {
+                if let Some(hash) = &users.unknown {
+                    hash.verify(user.password.expose_secret().as_bytes());
+                }
+                debug!("Unknown user {:?}", user.username);
+                None
+            }
+        }
+    }
+}
+
+impl UserAuthenticator for HtpasswdFile {
+    type Credentials = String;
+
+    async fn authenticate_user(&mut self, user: User) -> io::Result<Option<String>> {
+        let inner = self.inner.clone();
+        tokio::task::spawn_blocking(move || inner.authenticate(user))
+            .await
+            .map_err(io::Error::other)
+    }
+}
+
+/// Reads and parses the file at `path`, together with the version that was read.
+fn load(path: &Path) -> io::Result<(Users, Version)> {
+    // The metadata is read first, so changes made while reading are noticed on the next check
+    let version = Version::of(&fs::metadata(path)?);
+    let users = parse(&fs::read_to_string(path)?)?;
+    Ok((users, version))
+}
+
+/// Parses the lines of an htpasswd file.
+fn parse(contents: &str) -> io::Result<Users> {
+    let mut hashes = HashMap::new();
+    let mut unknown = None;
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let invalid = |message: &str| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Line {}: {}", number + 1, message),
+            )
+        };
+        let (username, hash) = line
+            .split_once(':')
+            .ok_or_else(|| invalid("Expected user:hash"))?;
+        if username.is_empty() {
+            return Err(invalid("Username cannot be empty"));
+        }
+        let hash = PasswordHash::parse(hash)
+            .ok_or_else(|| invalid("Unsupported or malformed password hash"))?;
+
+        if unknown.is_none() {
+            unknown = Some(hash.clone());
+        }
+        if hashes.insert(username.to_string(), hash).is_some() {
+            return Err(invalid("Duplicate user"));
+        }
+    }
+    Ok(Users { hashes, unknown })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE: &str =
+        "alice:$5$rounds=1000$roundstoolow$yfvwcWrQ8l/K0DAWyuPMDNHpIVlTQebY9l/gL972bIC";
+    const ALICE_PASSWORD: &str = "the minimum number is still observed";
+    const BOB: &str = "bob:$5$saltstring$5B8vYYiY.CVt1RlTTf8KbXBH3hsxY/GNooZaBBGWEc5";
+    const BOB_PASSWORD: &str = "Hello world!";
+
+    fn user(username: &str, password: &str) -> User {
+        User {
+            username: username.to_string(),
+            password: password.into(),
+        }
+    }
+
+    /// A file in the temporary directory, removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "gerevs-htpasswd-{}-{}",
+                std::process::id(),
+                name
+            ));
+            Self(path)
+        }
+
+        fn write(&self, contents: &str) {
+            fs::write(&self.0, contents).unwrap();
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn parses_users_and_skips_comments() {
+        let bcrypt = format!("carol:{}", bcrypt::hash("secret", 4).unwrap());
+        let users = parse(&format!(
+            "# Users\n\n  {}  \n{}\n\t# Bcrypt\n{}\n",
+            ALICE, BOB, bcrypt
+        ))
+        .unwrap();
+
+        assert_eq!(users.hashes.len(), 3);
+        assert!(users.hashes["alice"].verify(ALICE_PASSWORD.as_bytes()));
+        assert!(users.hashes["bob"].verify(BOB_PASSWORD.as_bytes()));
+        assert!(users.hashes["carol"].verify(b"secret"));
+        assert!(!users.hashes["carol"].verify(BOB_PASSWORD.as_bytes()));
+
+        // Unknown users are verified against the first user
+        let unknown = users.unknown.unwrap();
+        assert!(unknown.verify(ALICE_PASSWORD.as_bytes()));
+
+        let empty = parse("# No users yet\n").unwrap();
+        assert!(empty.hashes.is_empty());
+        assert!(empty.unknown.is_none());
+    }
+
+    #[test]
+    fn parse_errors_name_the_line() {
+        for (contents, message) in [
+            ("alice", "Line 1: Expected user:hash"),
+            (
+                "# Users\n:$5$saltstring$5B8vYYiY.CVt1RlTTf8KbXBH3hsxY/GNooZaBBGWEc5",
+                "Line 2: Username cannot be empty",
+            ),
+            (
+                "alice:secret",
+                "Line 1: Unsupported or malformed password hash",
+            ),
+            (
+                "alice:$apr1$salt$hash",
+                "Line 1: Unsupported or malformed password hash",
+            ),
+            (
+                "alice:$2y$04$tooshort",
+                "Line 1: Unsupported or malformed password hash",
+            ),
+            (&format!("{}\n\n{}", ALICE, ALICE), "Line 3: Duplicate user"),
+        ] {
+            let err = parse(contents).err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+            assert_eq!(err.to_string(), message);
+        }
+    }
+
+    #[test]
+    fn reloads_the_file_when_it_changes() {
+        let file = TempFile::new("reload");
+        file.write(&format!("{}\n", ALICE));
+        let htpasswd = HtpasswdFile::open(&file.0).unwrap();
+        assert_eq!(htpasswd.path(), file.0);
+
+        let inner = &htpasswd.inner;
+        assert_eq!(
+            inner.authenticate(user("alice", ALICE_PASSWORD)),
+            Some("alice".to_string())
+        );
+        assert_eq!(inner.authenticate(user("alice", "wrong")), None);
+        assert_eq!(inner.authenticate(user("bob", BOB_PASSWORD)), None);
+
+        // The length changes, so it's noticed even with a coarse modification time
+        file.write(&format!("# Alice left\n{}\n", BOB));
+        assert_eq!(
+            inner.authenticate(user("bob", BOB_PASSWORD)),
+            Some("bob".to_string())
+        );
+        assert_eq!(inner.authenticate(user("alice", ALICE_PASSWORD)), None);
+
+        // A broken file keeps the previous users
+        file.write("bob has no hash\n");
+        assert_eq!(
+            inner.authenticate(user("bob", BOB_PASSWORD)),
+            Some("bob".to_string())
+        );
+        fs::remove_file(&file.0).unwrap();
+        assert_eq!(
+            inner.authenticate(user("bob", BOB_PASSWORD)),
+            Some("bob".to_string())
+        );
+    }
+
+    #[test]
+    fn open_fails_on_missing_or_invalid_files() {
+        let file = TempFile::new("invalid");
+        assert_eq!(
+            HtpasswdFile::open(&file.0).err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+
+        file.write("alice:secret\n");
+        assert_eq!(
+            HtpasswdFile::open(&file.0).err().unwrap().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+}
@@ -0,0 +1,44 @@
+use argon2::{Argon2, PasswordVerifier};
+
+use super::sha_crypt::ShaCrypt;
+
+/// A password hash of an htpasswd file.
+#[derive(Debug, Clone)]
+pub(super) enum PasswordHash {
+    /// `$2a$`, `$2b$`, `$2x$` and `$2y$`.
+    Bcrypt(String),
+    /// `$argon2id$`, `$argon2i$` and `$argon2d$`, in the PHC string format.
+    Argon2(String),
+    /// `$5$` and `$6$`.
+    ShaCrypt(ShaCrypt),
+}
+
+impl PasswordHash {
+    /// Parses `hash`, `None` if its scheme isn't supported or it's malformed.
+    pub(super) fn parse(hash: &str) -> Option<Self> {
+        if ["$2a$", "$2b$", "$2x$", "$2y$"]
+            .iter()
+            .any(|prefix| hash.starts_with(prefix))
+        {
+            // Parsing the cost and salt checks the hash is well formed
+            hash.parse::<bcrypt::HashParts>().ok()?;
+            Some(PasswordHash::Bcrypt(hash.to_string()))
+        } else if hash.starts_with("$argon2") {
+            argon2::PasswordHash::new(hash).ok()?;
+            Some(PasswordHash::Argon2(hash.to_string()))
+        } else {
+            ShaCrypt::parse(hash).map(PasswordHash::ShaCrypt)
+        }
+    }
+
+    /// Returns whether `password` matches the hash. The comparisons are constant time, and this
+    /// is slow by design, it must not run on the async executor.
+    pub(super) fn verify(&self, password: &[u8]) -> bool {
+        match self {
+            PasswordHash::Bcrypt(hash) => bcrypt::verify(password, hash).unwrap_or(false),
+            PasswordHash::Argon2(hash) => argon2::PasswordHash::new(hash)
+                .is_ok_and(|hash| Argon2::default().verify_password(password, &hash).is_ok()),
+            PasswordHash::ShaCrypt(hash) => hash.verify(password),
+        }
+    }
+}
@@ -0,0 +1,352 @@
+//! SHA-crypt, the `$5$` and `$6$` hashes of glibc's `crypt`, as specified in
+//! <https://www.akkadia.org/drepper/SHA-crypt.txt>.
+
+use sha2::{digest::Digest, Sha256, Sha512};
+use subtle::ConstantTimeEq;
+use tracing::warn;
+
+const ROUNDS_DEFAULT: u32 = 5_000;
+const ROUNDS_MIN: u32 = 1_000;
+/// The specification allows up to 999,999,999 rounds, which take minutes to verify. Hashes with
+/// more rounds than this are rejected so a verification can't hold a blocking thread that long.
+const ROUNDS_LIMIT: u32 = 1_000_000;
+const SALT_MAX_LEN: usize = 16;
+
+const CRYPT_ALPHABET: &[u8; 64] =
+    b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// The order the bytes of a SHA-256 digest are encoded in, by groups of three.
+const SHA256_ORDER: [(usize, usize, usize); 10] = [
+    (0, 10, 20),
+    (21, 1, 11),
+    (12, 22, 2),
+    (3, 13, 23),
+    (24, 4, 14),
+    (15, 25, 5),
+    (6, 16, 26),
+    (27, 7, 17),
+    (18, 28, 8),
+    (9, 19, 29),
+];
+
+/// The order the bytes of a SHA-512 digest are encoded in, by groups of three.
+const SHA512_ORDER: [(usize, usize, usize); 21] = [
+    (0, 21, 42),
+    (22, 43, 1),
+    (44, 2, 23),
+    (3, 24, 45),
+    (25, 46, 4),
+    (47, 5, 26),
+    (6, 27, 48),
+    (28, 49, 7),
+    (50, 8, 29),
+    (9, 30, 51),
+    (31, 52, 10),
+    (53, 11, 32),
+    (12, 33, 54),
+    (34, 55, 13),
+    (56, 14, 35),
+    (15, 36, 57),
+    (37, 58, 16),
+    (59, 17, 38),
+    (18, 39, 60),
+    (40, 61, 19),
+    (62, 20, 41),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Algorithm {
+    Sha256,
+    Sha512,
+}
+
+/// A parsed SHA-crypt hash: `$5$[rounds=N$]salt$hash` or `$6$[rounds=N$]salt$hash`.
+#[derive(Debug, Clone)]
+pub(super) struct ShaCrypt {
+    algorithm: Algorithm,
+    rounds: u32,
+    salt: Vec<u8>,
+    hash: String,
+}
+
+impl ShaCrypt {
+    /// Parses `hash`, `None` if it isn't a valid SHA-crypt hash.
+    pub(super) fn parse(hash: &str) -> Option<Self> {
+        let (algorithm, rest, hash_len) = if let Some(rest) = hash.strip_prefix("$5$") {
+            (Algorithm::Sha256, rest, 43)
+        } else if let Some(rest) = hash.strip_prefix("$6$") {
+            (Algorithm::Sha512, rest, 86)
+        } else {
+            return None;
+        };
+
+        let (rounds, rest) = match rest.strip_prefix("rounds=") {
+            Some(rest) => {
+                let (rounds, rest) = rest.split_once('$')?;
+                let rounds: u32 = rounds.parse().ok()?;
+                if rounds > ROUNDS_LIMIT {
+                    warn!(
+                        "SHA-crypt hash with {} rounds, more than the {} supported",
+                        rounds, ROUNDS_LIMIT
+                    );
+                    return None;
+                }
+                (rounds.max(ROUNDS_MIN), rest)
+            }
+            None => (ROUNDS_DEFAULT, rest),
+        };
+
+        let (salt, hash) = rest.rsplit_once('$')?;
+        let valid_hash =
+            hash.len() == hash_len && hash.bytes().all(|c| CRYPT_ALPHABET.contains(&c));
+        if salt.contains('$') || !valid_hash {
+            return None;
+        }
+
+        // Longer salts are truncated, on bytes like glibc even inside a character
+        let salt = &salt.as_bytes()[..salt.len().min(SALT_MAX_LEN)];
+        Some(Self {
+            algorithm,
+            rounds,
+            salt: salt.to_vec(),
+            hash: hash.to_string(),
+        })
+    }
+
+    /// Returns whether `password` matches the hash, compared in constant time.
+    pub(super) fn verify(&self, password: &[u8]) -> bool {
+        let salt = &self.salt;
+        let hash = match self.algorithm {
+            Algorithm::Sha256 => encode(
+                &sha_crypt::<Sha256>(password, salt, self.rounds),
+                &SHA256_ORDER,
+                &[31, 30],
+            ),
+            Algorithm::Sha512 => encode(
+                &sha_crypt::<Sha512>(password, salt, self.rounds),
+                &SHA512_ORDER,
+                &[63],
+            ),
+        };
+        hash.as_bytes().ct_eq(self.hash.as_bytes()).into()
+    }
+}
+
+/// Feeds `len` bytes of `block` repeated into `digest`.
+fn update_repeated<D: Digest>(digest: &mut D, block: &[u8], len: usize) {
+    let mut remaining = len;
+    while remaining > block.len() {
+        digest.update(block);
+        remaining -= block.len();
+    }
+    digest.update(&block[..remaining]);
+}
+
+/// The raw SHA-crypt digest of `password` with `salt`.
+fn sha_crypt<D: Digest>(password: &[u8], salt: &[u8], rounds: u32) -> Vec<u8> {
+    let alternate = D::new()
+        .chain_update(password)
+        .chain_update(salt)
+        .chain_update(password)
+        .finalize();
+
+    let mut digest = D::new().chain_update(password).chain_update(salt);
+    update_repeated(&mut digest, &alternate, password.len());
+    let mut len = password.len();
+    while len > 0 {
+        if len & 1 == 1 {
+            digest.update(&alternate);
+        } else {
+            digest.update(password);
+        }
+        len >>= 1;
+    }
+    let mut current = digest.finalize().to_vec();
+
+    let mut password_digest = D::new();
+    for _ in 0..password.len() {
+        password_digest.update(password);
+    }
+    let password_digest = password_digest.finalize();
+    let p_bytes: Vec<u8> = password_digest
+        .iter()
+        .copied()
+        .cycle()
+        .take(password.len())
+        .collect();
+
+    let mut salt_digest = D::new();
+    for _ in 0..16 + current[0] as usize {
+        salt_digest.update(salt);
+    }
+    let salt_digest = salt_digest.finalize();
+    let s_bytes: Vec<u8> = salt_digest
+        .iter()
+        .copied()
+        .cycle()
+        .take(salt.len())
+        .collect();
+
+    for round in 0..rounds {
+        let mut digest = D::new();
+        if round % 2 == 1 {
+            digest.update(&p_bytes);
+        } else {
+            digest.update(&current);
+        }
+        if round % 3 != 0 {
+            digest.update(&s_bytes);
+        }
+        if round % 7 != 0 {
+            digest.update(&p_bytes);
+        }
+        if round % 2 == 1 {
+            digest.update(&current);
+        } else {
+            digest.update(&p_bytes);
+        }
+        current = digest.finalize().to_vec();
+    }
+    current
+}
+
+/// Encodes `digest` with the crypt base64 alphabet, in the byte order of the algorithm: groups of
+/// three bytes, followed by the `last` bytes.
+fn encode(digest: &[u8], order: &[(usize, usize, usize)], last: &[usize]) -> String {
+    let mut encoded = String::new();
+    let mut push = |value: u32, chars: usize| {
+        let mut value = value;
+        for _ in 0..chars {
+            encoded.push(CRYPT_ALPHABET[(value & 0x3f) as usize] as char);
+            value >>= 6;
+        }
+    };
+
+    for &(b2, b1, b0) in order {
+        push(
+            (digest[b2] as u32) << 16 | (digest[b1] as u32) << 8 | digest[b0] as u32,
+            4,
+        );
+    }
+    match *last {
+        [b1, b0] => push((digest[b1] as u32) << 8 | digest[b0] as u32, 3),
+        [b0] => push(digest[b0] as u32, 2),
+        _ => unreachable!("SHA-crypt digests end with one or two bytes"),
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The test vectors of the specification, as `(password, hash)`. The vectors with tens of
+    /// thousands of rounds are left out, they're too slow in debug builds.
+    const VECTORS: &[(&str, &str)] = &[
+        (
+            "Hello world!",
+            "$5$saltstring$5B8vYYiY.CVt1RlTTf8KbXBH3hsxY/GNooZaBBGWEc5",
+        ),
+        (
+            "Hello world!",
+            "$5$rounds=10000$saltstringsaltst$3xv.VbSHBb41AL9AvLeujZkZRBAwqFMz2.opqey6IcA",
+        ),
+        (
+            "This is just a test",
+            "$5$rounds=5000$toolongsaltstrin$Un/5jzAHMgOGZ5.mWJpuVolil07guHPvOW8mGRcvxa5",
+        ),
+        (
+            "a very much longer text to encrypt.  This one even stretches over morethan one line.",
+            "$5$rounds=1400$anotherlongsalts$Rx.j8H.h8HjEDGomFU8bDkXm3XIUnzyxf12oP84Bnq1",
+        ),
+        (
+            "the minimum number is still observed",
+            "$5$rounds=1000$roundstoolow$yfvwcWrQ8l/K0DAWyuPMDNHpIVlTQebY9l/gL972bIC",
+        ),
+        (
+            "Hello world!",
+            "$6$saltstring$svn8UoSVapNtMuq1ukKS4tPQd8iKwSMHWjl/O817G3uBnIFNjnQJuesI68u4OTLiBFdcbYEdFCoEOfaS35inz1",
+        ),
+        (
+            "Hello world!",
+            "$6$rounds=10000$saltstringsaltst$OW1/O6BYHV6BcXZu8QVeXbDWra3Oeqh0sbHbbMCVNSnCM/UrjmM0Dp8vOuZeHBy/YTBmSK6H9qs/y3RnOaw5v.",
+        ),
+        (
+            "This is just a test",
+            "$6$rounds=5000$toolongsaltstrin$lQ8jolhgVRVhY4b5pZKaysCLi0QBxGoNeKQzQ3glMhwllF7oGDZxUhx1yxdYcz/e1JSbq3y6JMxxl8audkUEm0",
+        ),
+        (
+            "a very much longer text to encrypt.  This one even stretches over morethan one line.",
+            "$6$rounds=1400$anotherlongsalts$POfYwTEok97VWcjxIiSOjiykti.o/pQs.wPvMxQ6Fm7I6IoYN3CmLs66x9t0oSwbtEW7o7UmJEiDwGqd8p4ur1",
+        ),
+        (
+            "the minimum number is still observed",
+            "$6$rounds=1000$roundstoolow$kUMsbe306n21p9R.FRkW3IGn.S9NPN0x50YhH1xhLsPuWGsUSklZt58jaTfF4ZEQpyUNGc0dqbpBYYBaHHrsX.",
+        ),
+    ];
+
+    #[test]
+    fn verifies_the_specification_vectors() {
+        for (password, hash) in VECTORS {
+            let parsed = ShaCrypt::parse(hash).unwrap();
+            assert!(parsed.verify(password.as_bytes()), "{}", hash);
+            assert!(!parsed.verify(b"wrong password"), "{}", hash);
+        }
+    }
+
+    #[test]
+    fn clamps_rounds_and_truncates_salts() {
+        // As written before the rounds and the salt are adjusted
+        let hash = ShaCrypt::parse(
+            "$5$rounds=10$roundstoolow$yfvwcWrQ8l/K0DAWyuPMDNHpIVlTQebY9l/gL972bIC",
+        )
+        .unwrap();
+        assert_eq!(hash.rounds, ROUNDS_MIN);
+        assert!(hash.verify(b"the minimum number is still observed"));
+
+        let hash = ShaCrypt::parse(
+            "$5$rounds=5000$toolongsaltstring$Un/5jzAHMgOGZ5.mWJpuVolil07guHPvOW8mGRcvxa5",
+        )
+        .unwrap();
+        assert_eq!(hash.salt, b"toolongsaltstrin");
+        assert!(hash.verify(b"This is just a test"));
+
+        // The 16th byte is inside a character, as computed by `openssl passwd -5`
+        let hash =
+            ShaCrypt::parse("$5$aéééééééé$a9Pl/DU2wm4W6hv/L4zAAlb4DR6MPPDRB05mF04jWe2").unwrap();
+        assert_eq!(
+            hash.salt,
+            b"a\xc3\xa9\xc3\xa9\xc3\xa9\xc3\xa9\xc3\xa9\xc3\xa9\xc3\xa9\xc3"
+        );
+        assert!(hash.verify(b"password"));
+    }
+
+    #[test]
+    fn rejects_too_many_rounds() {
+        let hash = |rounds: u32| {
+            format!(
+                "$6$rounds={}$saltstring$svn8UoSVapNtMuq1ukKS4tPQd8iKwSMHWjl/O817G3uBnIFNjnQJuesI68u4OTLiBFdcbYEdFCoEOfaS35inz1",
+                rounds
+            )
+        };
+        assert_eq!(
+            ShaCrypt::parse(&hash(ROUNDS_LIMIT)).unwrap().rounds,
+            ROUNDS_LIMIT
+        );
+        assert!(ShaCrypt::parse(&hash(ROUNDS_LIMIT + 1)).is_none());
+        assert!(ShaCrypt::parse(&hash(999_999_999)).is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_hashes() {
+        for hash in [
+            "$5$saltstring",
+            "$5$saltstring$5B8vYYiY.CVt1RlTTf8KbXBH3hsxY/GNooZaBBGWEc",
+            "$5$saltstring$5B8vYYiY.CVt1RlTTf8KbXBH3hsxY/GNooZaBBGWEc!",
+            "$5$rounds=many$saltstring$5B8vYYiY.CVt1RlTTf8KbXBH3hsxY/GNooZaBBGWEc5",
+            "$1$saltstring$5B8vYYiY.CVt1RlTTf8KbXBH3hsxY/GNooZaBBGWEc5",
+        ] {
+            assert!(ShaCrypt::parse(hash).is_none(), "{}", hash);
+        }
+    }
+}
@@ -2,9 +2,9 @@ use std::io;
 
 use tokio::io::{AsyncRead, AsyncWrite};
 
-use crate::protocol::AuthMethod;
+use crate::{context::SessionContext, protocol::AuthMethod};
 
-use super::Authenticator;
+use super::{username_password_authenticator::User, Authenticator};
 
 /// The `NoAuthAuthenticator` struct is an implementation of the `Authenticator` trait that handles
 /// the "no authentication" method in the SOCKS5 protocol. It requires no credentials from the client
@@ -20,13 +20,36 @@ where
 
     /// This method performs the "no authentication" process, which is essentially a no-op in this case.
     /// It immediately returns `Ok(Some(()))` to indicate successful authentication with no credentials.
-    async fn authenticate(&mut self, _: &mut T, _: AuthMethod) -> io::Result<Option<()>> {
+    async fn authenticate(
+        &mut self,
+        _: &mut T,
+        _: AuthMethod,
+        _: &mut SessionContext,
+    ) -> io::Result<Option<()>> {
+        Ok(Some(()))
+    }
+
+    /// SOCKS4 clients are accepted regardless of their user id.
+    async fn authenticate_socks4(
+        &mut self,
+        _: &str,
+        _: &mut SessionContext,
+    ) -> io::Result<Option<()>> {
+        Ok(Some(()))
+    }
+
+    /// HTTP clients are accepted regardless of their credentials.
+    async fn authenticate_http(
+        &mut self,
+        _: Option<User>,
+        _: &mut SessionContext,
+    ) -> io::Result<Option<()>> {
         Ok(Some(()))
     }
 
     /// This method selects the `NoAuthRequired` method if it is present in the provided list of methods.
     /// If not, it selects `NoAcceptableMethods` to indicate that no suitable authentication method is available.
-    fn select_method(&self, methods: &[AuthMethod]) -> AuthMethod {
+    fn select_method(&self, methods: &[AuthMethod], _: &SessionContext) -> AuthMethod {
         if methods.contains(&AuthMethod::NoAuthRequired) {
             AuthMethod::NoAuthRequired
         } else {
@@ -0,0 +1,123 @@
+use std::{
+    io::{self, ErrorKind},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// A stream an authenticator can wrap the connection in, see `Authenticator::encapsulate`.
+pub trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S> AsyncStream for S where S: AsyncRead + AsyncWrite + Unpin + Send {}
+
+enum Inner<T> {
+    Plain(T),
+    Encapsulated(Box<dyn AsyncStream>),
+    /// The connection was handed to the authenticator to be encapsulated.
+    Detached,
+}
+
+/// The `SessionStream` struct is the stream a session continues on once the client
+/// authenticated: the connection itself, or a stream encapsulating it, e.g. wrapping every message
+/// with the security context of the authentication method.
+pub struct SessionStream<T> {
+    inner: Inner<T>,
+}
+
+impl<T> SessionStream<T> {
+    /// Continues the session on the connection as is.
+    pub fn plain(conn: T) -> Self {
+        Self {
+            inner: Inner::Plain(conn),
+        }
+    }
+
+    /// Continues the session on `stream`, which encapsulates the connection.
+    pub fn encapsulated<S>(stream: S) -> Self
+    where
+        S: AsyncStream + 'static,
+    {
+        Self {
+            inner: Inner::Encapsulated(Box::new(stream)),
+        }
+    }
+
+    /// Returns whether the session is encapsulated.
+    pub fn is_encapsulated(&self) -> bool {
+        matches!(self.inner, Inner::Encapsulated(_))
+    }
+
+    /// Returns the connection, unless the session is encapsulated.
+    pub(crate) fn plain_mut(&mut self) -> Option<&mut T> {
+        match &mut self.inner {
+            Inner::Plain(conn) => Some(conn),
+            _ => None,
+        }
+    }
+
+    /// Takes the connection out to be encapsulated, the stream can't be used until it's replaced.
+    pub(crate) fn detach(&mut self) -> Option<T> {
+        match std::mem::replace(&mut self.inner, Inner::Detached) {
+            Inner::Plain(conn) => Some(conn),
+            inner => {
+                self.inner = inner;
+                None
+            }
+        }
+    }
+}
+
+fn detached() -> io::Error {
+    io::Error::new(ErrorKind::NotConnected, "Session stream is detached")
+}
+
+impl<T> AsyncRead for SessionStream<T>
+where
+    T: AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match &mut self.get_mut().inner {
+            Inner::Plain(conn) => Pin::new(conn).poll_read(cx, buf),
+            Inner::Encapsulated(stream) => Pin::new(stream).poll_read(cx, buf),
+            Inner::Detached => Poll::Ready(Err(detached())),
+        }
+    }
+}
+
+impl<T> AsyncWrite for SessionStream<T>
+where
+    T: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match &mut self.get_mut().inner {
+            Inner::Plain(conn) => Pin::new(conn).poll_write(cx, buf),
+            Inner::Encapsulated(stream) => Pin::new(stream).poll_write(cx, buf),
+            Inner::Detached => Poll::Ready(Err(detached())),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.get_mut().inner {
+            Inner::Plain(conn) => Pin::new(conn).poll_flush(cx),
+            Inner::Encapsulated(stream) => Pin::new(stream).poll_flush(cx),
+            Inner::Detached => Poll::Ready(Err(detached())),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.get_mut().inner {
+            Inner::Plain(conn) => Pin::new(conn).poll_shutdown(cx),
+            Inner::Encapsulated(stream) => Pin::new(stream).poll_shutdown(cx),
+            Inner::Detached => Poll::Ready(Err(detached())),
+        }
+    }
+}
@@ -0,0 +1,444 @@
+use std::{io, marker::PhantomData, net::IpAddr, sync::Arc};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tracing::debug;
+
+use crate::{acl::Cidr, context::SessionContext, protocol::AuthMethod};
+
+use super::{username_password_authenticator::User, Authenticator, SessionStream};
+
+/// The `Allowlist` struct maps networks to the credentials given to the clients connecting from
+/// them. When networks overlap, the most specific one (the longest prefix) is used.
+#[derive(Debug, Clone)]
+pub struct Allowlist<Cr> {
+    networks: Vec<(Cidr, Cr)>,
+}
+
+impl<Cr> Allowlist<Cr> {
+    /// Creates a new, empty, `Allowlist`.
+    pub fn new() -> Self {
+        Self {
+            networks: Vec::new(),
+        }
+    }
+
+    /// Admits the clients of `network`, giving them `credentials`.
+    pub fn with_network(mut self, network: Cidr, credentials: Cr) -> Self {
+        self.networks.push((network, credentials));
+        self
+    }
+
+    /// Returns the network containing `ip` and its credentials, `None` if `ip` isn't admitted.
+    pub fn lookup(&self, ip: IpAddr) -> Option<(&Cidr, &Cr)> {
+        self.networks
+            .iter()
+            .filter(|(network, _)| network.contains(ip))
+            .max_by_key(|(network, _)| network.prefix())
+            .map(|(network, credentials)| (network, credentials))
+    }
+}
+
+impl<Cr> Default for Allowlist<Cr> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The fallback of a `SourceIpAuthenticator` that wasn't given one, it rejects every client.
+pub struct NoFallback<Cr>(PhantomData<fn() -> Cr>);
+
+impl<T, Cr> Authenticator<T> for NoFallback<Cr>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    type Credentials = Cr;
+
+    fn select_method(&self, _: &[AuthMethod], _: &SessionContext) -> AuthMethod {
+        AuthMethod::NoAcceptableMethods
+    }
+
+    async fn authenticate(
+        &mut self,
+        _: &mut T,
+        _: AuthMethod,
+        _: &mut SessionContext,
+    ) -> io::Result<Option<Cr>> {
+        Ok(None)
+    }
+}
+
+/// The `SourceIpAuthenticator` struct admits clients by their source address instead of a
+/// password. Clients connecting from a network of the `Allowlist` are accepted with the
+/// `NoAuthRequired` method, and given the credentials the network is mapped to. Other clients are
+/// passed to a fallback authenticator, e.g. a `UsernamePasswordAuthenticator`.
+///
+/// SOCKS4 and HTTP clients of the allowed networks are accepted as well, regardless of their
+/// user id or credentials. The client's address is taken from the `SessionContext`, so sessions
+/// without a peer address are always passed to the fallback.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// # use std::{io, sync::Arc};
+/// use gerevs::{
+///     acl::Cidr,
+///     auth::{
+///         username_password_authenticator::{User, UserAuthenticator, UsernamePasswordAuthenticator},
+///         Allowlist, SourceIpAuthenticator,
+///     },
+///     method_handlers::{TunnelAssociate, TunnelBind, TunnelConnect},
+///     Socks5Server,
+/// };
+/// use tokio::net::TcpListener;
+///
+/// struct Users;
+///
+/// impl UserAuthenticator for Users {
+///     type Credentials = String;
+///
+///     async fn authenticate_user(&mut self, user: User) -> io::Result<Option<String>> {
+///         Ok((user.password == "password").then_some(user.username))
+///     }
+/// }
+///
+/// # async fn run() -> gerevs::Result<()> {
+/// let allowlist = Arc::new(
+///     Allowlist::new()
+///         .with_network("10.1.0.0/16".parse::<Cidr>()?, "office".to_string())
+///         .with_network("10.1.5.0/24".parse::<Cidr>()?, "lab".to_string()),
+/// );
+///
+/// let listener = TcpListener::bind("0.0.0.0:1080").await?;
+/// let server = Socks5Server::new(
+///     listener,
+///     move || {
+///         SourceIpAuthenticator::new(allowlist.clone())
+///             .with_fallback(UsernamePasswordAuthenticator::new(Users))
+///     },
+///     TunnelConnect::new,
+///     TunnelBind::new,
+///     TunnelAssociate::new,
+/// );
+/// server.run().await
+/// # }
+/// ```
+pub struct SourceIpAuthenticator<Cr, F = NoFallback<Cr>> {
+    allowlist: Arc<Allowlist<Cr>>,
+    fallback: F,
+    /// Whether the client was authenticated by the fallback.
+    fallback_used: bool,
+}
+
+impl<Cr> SourceIpAuthenticator<Cr> {
+    /// Creates a new `SourceIpAuthenticator` rejecting the clients outside of `allowlist`.
+    pub fn new(allowlist: Arc<Allowlist<Cr>>) -> Self {
+        Self {
+            allowlist,
+            fallback: NoFallback(PhantomData),
+            fallback_used: false,
+        }
+    }
+}
+
+impl<Cr, F> SourceIpAuthenticator<Cr, F> {
+    /// Passes the clients outside of the allowlist to `fallback`, which must produce the same
+    /// credentials type.
+    pub fn with_fallback<F2>(self, fallback: F2) -> SourceIpAuthenticator<Cr, F2> {
+        SourceIpAuthenticator {
+            allowlist: self.allowlist,
+            fallback,
+            fallback_used: false,
+        }
+    }
+
+    /// Returns the credentials of the client of `context`, if it's admitted by its address.
+    fn admitted(&self, context: &SessionContext) -> Option<Cr>
+    where
+        Cr: Clone,
+    {
+        let peer_addr = context.peer_addr()?;
+        let (network, credentials) = self.allowlist.lookup(peer_addr.ip())?;
+        debug!("Client {} admitted by network {}", peer_addr, network);
+        Some(credentials.clone())
+    }
+}
+
+impl<T, Cr, F> Authenticator<T> for SourceIpAuthenticator<Cr, F>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send,
+    Cr: Clone + Send + Sync,
+    F: Authenticator<T, Credentials = Cr> + Send,
+{
+    type Credentials = Cr;
+
+    /// Selects `NoAuthRequired` for admitted clients that offer it, the fallback selects the
+    /// method of the other clients.
+    fn select_method(&self, methods: &[AuthMethod], context: &SessionContext) -> AuthMethod {
+        let admitted = context
+            .peer_addr()
+            .is_some_and(|peer_addr| self.allowlist.lookup(peer_addr.ip()).is_some());
+        if admitted && methods.contains(&AuthMethod::NoAuthRequired) {
+            AuthMethod::NoAuthRequired
+        } else {
+            self.fallback.select_method(methods, context)
+        }
+    }
+
+    async fn authenticate(
+        &mut self,
+        conn: &mut T,
+        selected_method: AuthMethod,
+        context: &mut SessionContext,
+    ) -> io::Result<Option<Cr>> {
+        if selected_method == AuthMethod::NoAuthRequired {
+            if let Some(credentials) = self.admitted(context) {
+                return Ok(Some(credentials));
+            }
+        }
+        self.fallback_used = true;
+        self.fallback
+            .authenticate(conn, selected_method, context)
+            .await
+    }
+
+    async fn authenticate_socks4(
+        &mut self,
+        user_id: &str,
+        context: &mut SessionContext,
+    ) -> io::Result<Option<Cr>> {
+        match self.admitted(context) {
+            Some(credentials) => Ok(Some(credentials)),
+            None => self.fallback.authenticate_socks4(user_id, context).await,
+        }
+    }
+
+    async fn authenticate_http(
+        &mut self,
+        user: Option<User>,
+        context: &mut SessionContext,
+    ) -> io::Result<Option<Cr>> {
+        match self.admitted(context) {
+            Some(credentials) => Ok(Some(credentials)),
+            None => self.fallback.authenticate_http(user, context).await,
+        }
+    }
+
+    /// Returns the name reported by the fallback, clients admitted by their address have none.
+    fn user_name(&self) -> Option<&str> {
+        self.fallback.user_name()
+    }
+
+    /// Lets the fallback encapsulate the session of the clients it authenticated.
+    fn encapsulate(&mut self, conn: T) -> io::Result<SessionStream<T>> {
+        if self.fallback_used {
+            self.fallback.encapsulate(conn)
+        } else {
+            Ok(SessionStream::plain(conn))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use tokio::io::{duplex, DuplexStream};
+
+    use super::*;
+
+    /// A username and password fallback accepting every user.
+    struct Passwords;
+
+    impl Authenticator<DuplexStream> for Passwords {
+        type Credentials = String;
+
+        fn select_method(&self, methods: &[AuthMethod], _: &SessionContext) -> AuthMethod {
+            if methods.contains(&AuthMethod::UsernamePassword) {
+                AuthMethod::UsernamePassword
+            } else {
+                AuthMethod::NoAcceptableMethods
+            }
+        }
+
+        async fn authenticate(
+            &mut self,
+            _: &mut DuplexStream,
+            selected_method: AuthMethod,
+            _: &mut SessionContext,
+        ) -> io::Result<Option<String>> {
+            Ok((selected_method == AuthMethod::UsernamePassword).then(|| "password".to_string()))
+        }
+
+        async fn authenticate_socks4(
+            &mut self,
+            user_id: &str,
+            _: &mut SessionContext,
+        ) -> io::Result<Option<String>> {
+            Ok(Some(user_id.to_string()))
+        }
+    }
+
+    fn allowlist() -> Arc<Allowlist<String>> {
+        let network = |s: &str| s.parse::<Cidr>().unwrap();
+        Arc::new(
+            Allowlist::new()
+                .with_network(network("10.1.0.0/16"), "office".to_string())
+                .with_network(network("10.0.0.0/8"), "corp".to_string())
+                .with_network(network("10.1.5.0/24"), "lab".to_string()),
+        )
+    }
+
+    fn context(peer_addr: &str) -> SessionContext {
+        let mut context = SessionContext::new();
+        context.set_peer_addr(SocketAddr::new(peer_addr.parse().unwrap(), 40000));
+        context
+    }
+
+    /// Negotiates a method among `methods` and authenticates with it.
+    async fn negotiate<A>(
+        authenticator: &mut A,
+        methods: &[AuthMethod],
+        context: &mut SessionContext,
+    ) -> (AuthMethod, Option<A::Credentials>)
+    where
+        A: Authenticator<DuplexStream>,
+    {
+        let method = authenticator.select_method(methods, context);
+        let (mut conn, _) = duplex(64);
+        let credentials = authenticator
+            .authenticate(&mut conn, method, context)
+            .await
+            .unwrap();
+        (method, credentials)
+    }
+
+    #[test]
+    fn allowlist_prefers_the_longest_prefix() {
+        let allowlist = allowlist();
+        let lookup = |ip: &str| {
+            allowlist
+                .lookup(ip.parse().unwrap())
+                .map(|(_, credentials)| credentials.as_str())
+        };
+        assert_eq!(lookup("10.1.5.7"), Some("lab"));
+        assert_eq!(lookup("10.1.6.1"), Some("office"));
+        assert_eq!(lookup("10.2.0.1"), Some("corp"));
+        assert_eq!(lookup("::ffff:10.1.5.7"), Some("lab"));
+        assert_eq!(lookup("192.168.0.1"), None);
+        assert_eq!(
+            Allowlist::<()>::new().lookup("10.0.0.1".parse().unwrap()),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn admits_allowlisted_clients() {
+        let mut authenticator = SourceIpAuthenticator::new(allowlist()).with_fallback(Passwords);
+        let methods = [AuthMethod::UsernamePassword, AuthMethod::NoAuthRequired];
+        assert_eq!(
+            negotiate(&mut authenticator, &methods, &mut context("10.1.5.7")).await,
+            (AuthMethod::NoAuthRequired, Some("lab".to_string()))
+        );
+        assert_eq!(
+            Authenticator::<DuplexStream>::authenticate_socks4(
+                &mut authenticator,
+                "alice",
+                &mut context("10.2.0.1")
+            )
+            .await
+            .unwrap(),
+            Some("corp".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn allowlisted_clients_without_no_auth_use_the_fallback() {
+        let mut authenticator = SourceIpAuthenticator::new(allowlist()).with_fallback(Passwords);
+        assert_eq!(
+            negotiate(
+                &mut authenticator,
+                &[AuthMethod::UsernamePassword],
+                &mut context("10.1.5.7")
+            )
+            .await,
+            (AuthMethod::UsernamePassword, Some("password".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn other_clients_use_the_fallback() {
+        let methods = [AuthMethod::NoAuthRequired, AuthMethod::UsernamePassword];
+
+        let mut authenticator = SourceIpAuthenticator::new(allowlist()).with_fallback(Passwords);
+        assert_eq!(
+            negotiate(&mut authenticator, &methods, &mut context("192.168.0.1")).await,
+            (AuthMethod::UsernamePassword, Some("password".to_string()))
+        );
+
+        // Sessions without a peer address aren't admitted
+        let mut authenticator = SourceIpAuthenticator::new(allowlist()).with_fallback(Passwords);
+        assert_eq!(
+            negotiate(&mut authenticator, &methods, &mut SessionContext::new()).await,
+            (AuthMethod::UsernamePassword, Some("password".to_string()))
+        );
+
+        // A client outside of the allowlist can't claim `NoAuthRequired`
+        let mut authenticator = SourceIpAuthenticator::new(allowlist()).with_fallback(Passwords);
+        let (mut conn, _) = duplex(64);
+        let credentials = authenticator
+            .authenticate(
+                &mut conn,
+                AuthMethod::NoAuthRequired,
+                &mut context("192.168.0.1"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(credentials, None);
+
+        assert_eq!(
+            Authenticator::<DuplexStream>::authenticate_socks4(
+                &mut authenticator,
+                "alice",
+                &mut context("192.168.0.1")
+            )
+            .await
+            .unwrap(),
+            Some("alice".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn no_fallback_rejects_other_clients() {
+        let mut authenticator = SourceIpAuthenticator::new(allowlist());
+        let methods = [AuthMethod::NoAuthRequired, AuthMethod::UsernamePassword];
+        assert_eq!(
+            negotiate(&mut authenticator, &methods, &mut context("192.168.0.1")).await,
+            (AuthMethod::NoAcceptableMethods, None)
+        );
+        assert_eq!(
+            Authenticator::<DuplexStream>::authenticate_socks4(
+                &mut authenticator,
+                "alice",
+                &mut context("192.168.0.1")
+            )
+            .await
+            .unwrap(),
+            None
+        );
+        assert_eq!(
+            Authenticator::<DuplexStream>::authenticate_http(
+                &mut authenticator,
+                None,
+                &mut context("192.168.0.1")
+            )
+            .await
+            .unwrap(),
+            None
+        );
+
+        assert_eq!(
+            negotiate(&mut authenticator, &methods, &mut context("10.1.6.1")).await,
+            (AuthMethod::NoAuthRequired, Some("office".to_string()))
+        );
+    }
+}
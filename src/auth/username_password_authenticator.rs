@@ -8,6 +8,10 @@
 //! ## Example
 //!
 //! ```rust
+//! # use std::io;
+//! # use gerevs::auth::username_password_authenticator::{
+//! #     User, UserAuthenticator, UsernamePasswordAuthenticator,
+//! # };
 //! struct SimpleUserAuthenticator;
 //!
 //! impl UserAuthenticator for SimpleUserAuthenticator {
@@ -30,28 +34,128 @@
 //! ```
 
 use std::{
+    fmt,
     future::Future,
     io::{self, ErrorKind},
 };
 
+use subtle::ConstantTimeEq;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tracing::debug;
+use zeroize::{Zeroize, Zeroizing};
 
-use crate::protocol::AuthMethod;
+use crate::{context::SessionContext, protocol::AuthMethod};
 
 use super::Authenticator;
 
-const USER_PASSWORD_VERSION: u8 = 0x01;
+pub(crate) const USER_PASSWORD_VERSION: u8 = 0x01;
+
+/// A password, kept out of logs: it's redacted by `Debug` and `Display`, and its memory is zeroed
+/// when it's dropped. Comparisons with other passwords and strings take constant time for a given
+/// length.
+#[derive(Clone, Default)]
+pub struct Password(String);
+
+impl Password {
+    /// Creates a new `Password`.
+    pub fn new(password: impl Into<String>) -> Self {
+        Self(password.into())
+    }
+
+    /// Returns the password in the clear, e.g. to verify it against a hash. It must not be logged.
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Password {
+    fn from(password: String) -> Self {
+        Self(password)
+    }
+}
+
+impl From<&str> for Password {
+    fn from(password: &str) -> Self {
+        Self(password.to_string())
+    }
+}
+
+impl Drop for Password {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl fmt::Debug for Password {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Password([REDACTED])")
+    }
+}
+
+impl fmt::Display for Password {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[REDACTED]")
+    }
+}
+
+impl PartialEq for Password {
+    fn eq(&self, other: &Self) -> bool {
+        *self == *other.0
+    }
+}
+
+impl Eq for Password {}
+
+impl PartialEq<str> for Password {
+    fn eq(&self, other: &str) -> bool {
+        self.0.as_bytes().ct_eq(other.as_bytes()).into()
+    }
+}
+
+impl PartialEq<&str> for Password {
+    fn eq(&self, other: &&str) -> bool {
+        *self == **other
+    }
+}
 
 /// Represents a user with a username and password.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct User {
     pub username: String,
-    pub password: String,
+    pub password: Password,
+}
+
+impl User {
+    /// Turns `Self` into the RFC 1929 sub-negotiation request: VER+ULEN+UNAME+PLEN+PASSWD. The
+    /// request holds the password, it's zeroed when dropped.
+    pub(crate) fn to_bytes(&self) -> io::Result<Zeroizing<Vec<u8>>> {
+        let username = self.username.as_bytes();
+        let password = self.password.expose_secret().as_bytes();
+        if username.is_empty() || username.len() > u8::MAX as usize {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "Username must be between 1 and 255 bytes",
+            ));
+        }
+        if password.is_empty() || password.len() > u8::MAX as usize {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "Password must be between 1 and 255 bytes",
+            ));
+        }
+
+        let mut bytes = Zeroizing::new(Vec::with_capacity(3 + username.len() + password.len()));
+        bytes.push(USER_PASSWORD_VERSION);
+        bytes.push(username.len() as u8);
+        bytes.extend_from_slice(username);
+        bytes.push(password.len() as u8);
+        bytes.extend_from_slice(password);
+        Ok(bytes)
+    }
 }
 
 #[repr(u8)]
-enum AuthStatus {
+pub(crate) enum AuthStatus {
     Success = 0x00,
     Failure = 0x01,
 }
@@ -81,6 +185,7 @@ where
     U: UserAuthenticator,
 {
     user_authenticator: U,
+    user_name: Option<String>,
 }
 
 impl<T, U> Authenticator<T> for UsernamePasswordAuthenticator<U>
@@ -94,7 +199,7 @@ where
     /// Selects the `UsernamePassword` authentication method if it is present in the provided list
     /// of methods. If not, it selects `NoAcceptableMethods` to indicate that no suitable
     /// authentication method is available.
-    fn select_method(&self, methods: &[AuthMethod]) -> AuthMethod {
+    fn select_method(&self, methods: &[AuthMethod], _: &SessionContext) -> AuthMethod {
         if methods.contains(&AuthMethod::UsernamePassword) {
             AuthMethod::UsernamePassword
         } else {
@@ -107,9 +212,14 @@ where
         &mut self,
         conn: &mut T,
         _: AuthMethod,
+        _: &mut SessionContext,
     ) -> io::Result<Option<Self::Credentials>> {
         let user = self.get_user(conn).await?;
+        let username = user.username.clone();
         let credentials = self.user_authenticator.authenticate_user(user).await?;
+        if credentials.is_some() {
+            self.user_name = Some(username);
+        }
 
         self.send_authentication_result(
             conn,
@@ -123,6 +233,28 @@ where
 
         Ok(credentials)
     }
+
+    /// Authenticates the user of the `Proxy-Authorization` header, requests without one are rejected.
+    async fn authenticate_http(
+        &mut self,
+        user: Option<User>,
+        _: &mut SessionContext,
+    ) -> io::Result<Option<Self::Credentials>> {
+        let Some(user) = user else {
+            return Ok(None);
+        };
+        let username = user.username.clone();
+        let credentials = self.user_authenticator.authenticate_user(user).await?;
+        if credentials.is_some() {
+            self.user_name = Some(username);
+        }
+        Ok(credentials)
+    }
+
+    /// Returns the username of the authenticated user.
+    fn user_name(&self) -> Option<&str> {
+        self.user_name.as_deref()
+    }
 }
 
 impl<U> UsernamePasswordAuthenticator<U>
@@ -136,6 +268,7 @@ where
     pub fn new(user_authorizer: U) -> UsernamePasswordAuthenticator<U> {
         UsernamePasswordAuthenticator {
             user_authenticator: user_authorizer,
+            user_name: None,
         }
     }
 
@@ -173,15 +306,20 @@ where
         }
 
         let mut buf = vec![0; password_len as usize];
-        conn.read_exact(&mut buf).await?;
-        let password = String::from_utf8(buf)
-            .map_err(|_| io::Error::new(ErrorKind::InvalidData, "Password was invalid utf8"))?;
-
-        debug!(
-            "Received username: {:?}, and password: {:?}",
-            username, password
-        );
-        let user = User { username, password };
+        if let Err(err) = conn.read_exact(&mut buf).await {
+            buf.zeroize();
+            return Err(err);
+        }
+        let password = String::from_utf8(buf).map_err(|err| {
+            err.into_bytes().zeroize();
+            io::Error::new(ErrorKind::InvalidData, "Password was invalid utf8")
+        })?;
+
+        debug!("Received username: {:?}", username);
+        let user = User {
+            username,
+            password: password.into(),
+        };
         Ok(user)
     }
 
@@ -0,0 +1,233 @@
+//! # Client Module
+//!
+//! This module provides a SOCKS5 client for dialing out through a SOCKS5 proxy server.
+//! It reuses the protocol types of the server side (`SocksSocketAddr`, `AuthMethod`, `Reply`)
+//! so addresses and errors look the same whether gerevs is accepting or initiating connections.
+//!
+//! ## Example
+//!
+//! ```rust,no_run
+//! use gerevs::client::{Addr, Socks5Client, SocksSocketAddr, User};
+//! use tokio::io::AsyncWriteExt;
+//!
+//! # async fn run() -> gerevs::Result<()> {
+//! let client = Socks5Client::with_user(User {
+//!     username: "admin".to_string(),
+//!     password: "password".into(),
+//! });
+//! let destination = SocksSocketAddr {
+//!     addr: Addr::Domain("example.com".to_string()),
+//!     port: 80,
+//! };
+//!
+//! let (mut stream, _bound_addr) = client.connect("127.0.0.1:1080", destination).await?;
+//! stream.write_all(b"GET / HTTP/1.0\r\n\r\n").await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io::{self, ErrorKind};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tracing::debug;
+
+use crate::{
+    auth::username_password_authenticator::{AuthStatus, USER_PASSWORD_VERSION},
+    protocol::{Command, RESERVED, VERSION},
+    resolver::{Resolver, SystemResolver},
+    Socks5Error,
+};
+
+mod associate;
+mod bind;
+mod connect;
+
+pub use crate::auth::username_password_authenticator::User;
+pub use crate::protocol::{Addr, AuthMethod, Reply, SocksSocketAddr};
+pub use associate::UdpAssociation;
+pub use bind::BindListener;
+
+/// The `Socks5Client` struct negotiates SOCKS5 sessions with a proxy server.
+///
+/// A client without a user only offers the `NoAuthRequired` method, a client with a user
+/// additionally offers the `UsernamePassword` method as specified in
+/// [RFC 1929](https://datatracker.ietf.org/doc/html/rfc1929).
+///
+/// The addresses the proxy replies with, e.g. the relay of a UDP association, are resolved with
+/// the `SystemResolver` unless another resolver is set with `with_resolver`.
+#[derive(Debug, Default)]
+pub struct Socks5Client<R = SystemResolver> {
+    user: Option<User>,
+    resolver: R,
+}
+
+/// Makes sure `addr` can be encoded in a request, domain names are limited to 255 bytes.
+fn validate_addr(addr: &SocksSocketAddr) -> io::Result<()> {
+    match &addr.addr {
+        Addr::Domain(domain) if domain.len() > u8::MAX as usize => Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "Domain name must be at most 255 bytes",
+        )),
+        _ => Ok(()),
+    }
+}
+
+impl Socks5Client {
+    /// Creates a new `Socks5Client` that doesn't authenticate.
+    pub fn new() -> Self {
+        Self {
+            user: None,
+            resolver: SystemResolver,
+        }
+    }
+
+    /// Creates a new `Socks5Client` that authenticates with the given username and password
+    /// if the server requires it.
+    pub fn with_user(user: User) -> Self {
+        Self {
+            user: Some(user),
+            resolver: SystemResolver,
+        }
+    }
+}
+
+impl<R> Socks5Client<R> {
+    /// Sets the resolver used to resolve the addresses the proxy replies with.
+    pub fn with_resolver<R2>(self, resolver: R2) -> Socks5Client<R2>
+    where
+        R2: Resolver,
+    {
+        Socks5Client {
+            user: self.user,
+            resolver,
+        }
+    }
+
+    fn offered_methods(&self) -> Vec<AuthMethod> {
+        let mut methods = vec![AuthMethod::NoAuthRequired];
+        if self.user.is_some() {
+            methods.push(AuthMethod::UsernamePassword);
+        }
+        methods
+    }
+
+    /// Negotiates the authentication method and performs the authentication sub-negotiation.
+    async fn handshake<S>(&self, stream: &mut S) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let methods = self.offered_methods();
+
+        let mut request = Vec::with_capacity(2 + methods.len());
+        request.push(VERSION);
+        request.push(methods.len() as u8);
+        request.extend(methods.iter().map(|method| method.to_u8()));
+        stream.write_all(&request).await?;
+        stream.flush().await?;
+
+        let version = stream.read_u8().await?;
+        if version != VERSION {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Unexpected protocol version",
+            ));
+        }
+
+        let method = AuthMethod::from_u8(stream.read_u8().await?);
+        debug!("Server selected method: {:?}", method);
+
+        match (method, &self.user) {
+            (AuthMethod::NoAuthRequired, _) => Ok(()),
+            (AuthMethod::UsernamePassword, Some(user)) => {
+                self.authenticate_user(stream, user).await
+            }
+            (AuthMethod::NoAcceptableMethods, _) => Err(io::Error::new(
+                ErrorKind::PermissionDenied,
+                "Server accepted none of the offered authentication methods",
+            )),
+            _ => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Server selected an authentication method that wasn't offered",
+            )),
+        }
+    }
+
+    /// Performs the username and password sub-negotiation as specified in RFC 1929.
+    async fn authenticate_user<S>(&self, stream: &mut S, user: &User) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        stream.write_all(&user.to_bytes()?).await?;
+        stream.flush().await?;
+
+        let version = stream.read_u8().await?;
+        if version != USER_PASSWORD_VERSION {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Invalid UsernamePassword version",
+            ));
+        }
+
+        let status = stream.read_u8().await?;
+        if status != AuthStatus::Success as u8 {
+            return Err(io::Error::new(
+                ErrorKind::PermissionDenied,
+                "Authentication failed",
+            ));
+        }
+        debug!("Authentication success");
+        Ok(())
+    }
+
+    /// Sends a request for `command` on `addr`.
+    async fn request<S>(stream: &mut S, command: Command, addr: &SocksSocketAddr) -> io::Result<()>
+    where
+        S: AsyncWrite + Unpin,
+    {
+        validate_addr(addr)?;
+
+        stream.write_u8(VERSION).await?;
+        stream.write_u8(command.to_u8()).await?;
+        stream.write_u8(RESERVED).await?;
+        stream.write_all(&addr.to_bytes()).await?;
+        stream.flush().await?;
+        Ok(())
+    }
+
+    /// Reads a reply from the server, returning the BND.ADDR on success and the reply as an
+    /// error otherwise.
+    async fn read_reply<S>(stream: &mut S) -> crate::Result<SocksSocketAddr>
+    where
+        S: AsyncRead + Unpin,
+    {
+        let version = stream.read_u8().await?;
+        if version != VERSION {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Unexpected protocol version",
+            ))?;
+        }
+
+        let reply = stream.read_u8().await?;
+        let Some(reply) = Reply::from_u8(reply) else {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Invalid reply value",
+            ))?;
+        };
+
+        let reserved = stream.read_u8().await?;
+        if reserved != RESERVED {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Unexpected reserved value, expected 0",
+            ))?;
+        }
+
+        let bnd_addr = SocksSocketAddr::read(stream).await?;
+        match reply {
+            Reply::Success => Ok(bnd_addr),
+            err => Err(Socks5Error::Socks5Error(err)),
+        }
+    }
+}
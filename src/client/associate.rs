@@ -0,0 +1,145 @@
+use std::{
+    io::{self, ErrorKind},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use tokio::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use tracing::{debug, instrument, trace, warn};
+
+use crate::{
+    protocol::{Command, SocksSocketAddr, UdpMessage},
+    resolver::Resolver,
+};
+
+use super::{validate_addr, Socks5Client};
+
+const MAX_DATAGRAM_SIZE: usize = u16::MAX as usize;
+
+/// The `UdpAssociation` struct is a UDP relay established through a SOCKS5 proxy server with the
+/// UDP ASSOCIATE command.
+///
+/// Datagrams are wrapped in the SOCKS5 UDP request header before being sent to the relay, and
+/// unwrapped when received from it. The association is kept alive by the control TCP connection
+/// and is terminated when this struct is dropped.
+#[derive(Debug)]
+pub struct UdpAssociation {
+    _control: TcpStream,
+    socket: UdpSocket,
+    relay_addr: SocketAddr,
+    recv_buf: Vec<u8>,
+}
+
+impl<R> Socks5Client<R>
+where
+    R: Resolver,
+{
+    /// Connects to the proxy server at `proxy` and asks it to establish a UDP relay.
+    ///
+    /// - `proxy`: The address of the SOCKS5 proxy server.
+    /// - Returns: A future that resolves to `crate::Result<UdpAssociation>`.
+    #[instrument(skip(self, proxy))]
+    pub async fn associate<A>(&self, proxy: A) -> crate::Result<UdpAssociation>
+    where
+        A: ToSocketAddrs,
+    {
+        let mut control = TcpStream::connect(proxy).await?;
+        let proxy_addr = control.peer_addr()?;
+
+        let socket = match proxy_addr {
+            SocketAddr::V4(_) => UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?,
+            SocketAddr::V6(_) => UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0)).await?,
+        };
+
+        self.handshake(&mut control).await?;
+
+        // The relay only accepts datagrams from the address announced here, an unspecified
+        // IP tells it to only match on the port.
+        let local_addr = socket.local_addr()?;
+        Self::request(&mut control, Command::UdpAssociate, &local_addr.into()).await?;
+        let bnd_addr = Self::read_reply(&mut control).await?;
+
+        let relay_addr = bnd_addr.resolve(&self.resolver).await?.first().copied();
+        let Some(mut relay_addr) = relay_addr else {
+            return Err(io::Error::new(
+                ErrorKind::AddrNotAvailable,
+                "Relay address couldn't be resolved",
+            ))?;
+        };
+        // Servers listening on all interfaces reply with an unspecified address, the relay is
+        // then reachable on the address of the proxy itself.
+        if relay_addr.ip().is_unspecified() {
+            relay_addr.set_ip(proxy_addr.ip());
+        }
+        debug!("Udp relay is at: {}", relay_addr);
+
+        Ok(UdpAssociation {
+            _control: control,
+            socket,
+            relay_addr,
+            recv_buf: vec![0; MAX_DATAGRAM_SIZE],
+        })
+    }
+}
+
+impl UdpAssociation {
+    /// Returns the address of the relay datagrams are sent to.
+    pub fn relay_addr(&self) -> SocketAddr {
+        self.relay_addr
+    }
+
+    /// Returns the local address of the UDP socket used for the association.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Sends `buf` to `dst` through the relay.
+    ///
+    /// - `buf`: The payload of the datagram.
+    /// - `dst`: The address the relay should forward the datagram to.
+    /// - Returns: A future that resolves to `io::Result<usize>`, the number of payload bytes sent.
+    pub async fn send_to(&self, buf: &[u8], dst: &SocksSocketAddr) -> io::Result<usize> {
+        validate_addr(dst)?;
+        let message = UdpMessage {
+            fragment_number: 0,
+            dst: dst.clone(),
+            data: buf,
+        };
+        let message = message.as_bytes();
+        trace!("Sending {} bytes to relay", message.len());
+
+        let sent = self.socket.send_to(&message, self.relay_addr).await?;
+        Ok(sent.saturating_sub(message.len() - buf.len()))
+    }
+
+    /// Receives a datagram from the relay, datagrams from other sources are ignored.
+    /// If `buf` is too small for the payload, the excess bytes are discarded.
+    ///
+    /// - `buf`: The buffer to store the payload in.
+    /// - Returns: A future that resolves to `io::Result<(usize, SocksSocketAddr)>`, the number of
+    ///   payload bytes received and the address the datagram was originally sent from.
+    pub async fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocksSocketAddr)> {
+        loop {
+            let (n, source) = self.socket.recv_from(&mut self.recv_buf).await?;
+            if source != self.relay_addr {
+                warn!("Ignored datagram from {} which isn't the relay", source);
+                continue;
+            }
+
+            let message = match UdpMessage::parse(&self.recv_buf[..n]).await {
+                Ok(message) => message,
+                Err(err) => {
+                    warn!("Ignored invalid datagram from relay: {}", err);
+                    continue;
+                }
+            };
+            if message.fragment_number != 0 {
+                warn!("Ignored fragmented datagram from relay");
+                continue;
+            }
+
+            let len = message.data.len().min(buf.len());
+            buf[..len].copy_from_slice(&message.data[..len]);
+            return Ok((len, message.dst));
+        }
+    }
+}
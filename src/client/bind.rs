@@ -0,0 +1,79 @@
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpStream, ToSocketAddrs},
+};
+use tracing::{debug, instrument};
+
+use crate::protocol::{Command, SocksSocketAddr};
+
+use super::Socks5Client;
+
+/// The `BindListener` struct represents a BIND request the proxy server accepted and is now
+/// listening for, it holds the first reply of the server until the incoming connection arrives.
+#[derive(Debug)]
+pub struct BindListener<S> {
+    stream: S,
+    bound_addr: SocksSocketAddr,
+}
+
+impl<R> Socks5Client<R> {
+    /// Connects to the proxy server at `proxy` and asks it to listen for an incoming connection.
+    ///
+    /// - `proxy`: The address of the SOCKS5 proxy server.
+    /// - `addr`: The DST.ADDR of the BIND request.
+    /// - Returns: A future that resolves to `crate::Result<BindListener<TcpStream>>`.
+    pub async fn bind<A>(
+        &self,
+        proxy: A,
+        addr: SocksSocketAddr,
+    ) -> crate::Result<BindListener<TcpStream>>
+    where
+        A: ToSocketAddrs,
+    {
+        let stream = TcpStream::connect(proxy).await?;
+        self.bind_with(stream, addr).await
+    }
+
+    /// Issues a BIND request over an already established connection to a proxy server.
+    ///
+    /// - `stream`: The connection to the SOCKS5 proxy server.
+    /// - `addr`: The DST.ADDR of the BIND request.
+    /// - Returns: A future that resolves to `crate::Result<BindListener<S>>`.
+    #[instrument(skip(self, stream))]
+    pub async fn bind_with<S>(
+        &self,
+        mut stream: S,
+        addr: SocksSocketAddr,
+    ) -> crate::Result<BindListener<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        self.handshake(&mut stream).await?;
+
+        Self::request(&mut stream, Command::Bind, &addr).await?;
+        let bound_addr = Self::read_reply(&mut stream).await?;
+        debug!("Server listening on: {}", bound_addr);
+
+        Ok(BindListener { stream, bound_addr })
+    }
+}
+
+impl<S> BindListener<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Returns the address the proxy server is listening on, from the first reply.
+    pub fn bound_addr(&self) -> &SocksSocketAddr {
+        &self.bound_addr
+    }
+
+    /// Waits for the proxy server to accept the incoming connection.
+    ///
+    /// - Returns: A future that resolves to `crate::Result<(S, SocksSocketAddr)>`, the stream
+    ///   relayed to the accepted peer and the peer address from the second reply.
+    pub async fn accept(mut self) -> crate::Result<(S, SocksSocketAddr)> {
+        let peer_addr = <Socks5Client>::read_reply(&mut self.stream).await?;
+        debug!("Server accepted: {}", peer_addr);
+        Ok((self.stream, peer_addr))
+    }
+}
@@ -0,0 +1,55 @@
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpStream, ToSocketAddrs},
+};
+use tracing::{debug, instrument};
+
+use crate::protocol::{Command, SocksSocketAddr};
+
+use super::Socks5Client;
+
+impl<R> Socks5Client<R> {
+    /// Connects to the proxy server at `proxy` and asks it to establish a TCP connection to
+    /// `destination`.
+    ///
+    /// - `proxy`: The address of the SOCKS5 proxy server.
+    /// - `destination`: The address the proxy server should connect to.
+    /// - Returns: A future that resolves to `crate::Result<(TcpStream, SocksSocketAddr)>`, the
+    ///   stream relayed to `destination` and the BND.ADDR the server replied with.
+    pub async fn connect<A>(
+        &self,
+        proxy: A,
+        destination: SocksSocketAddr,
+    ) -> crate::Result<(TcpStream, SocksSocketAddr)>
+    where
+        A: ToSocketAddrs,
+    {
+        let stream = TcpStream::connect(proxy).await?;
+        self.connect_with(stream, destination).await
+    }
+
+    /// Issues a CONNECT request to `destination` over an already established connection to a
+    /// proxy server, this allows tunneling through other proxies or transports.
+    ///
+    /// - `stream`: The connection to the SOCKS5 proxy server.
+    /// - `destination`: The address the proxy server should connect to.
+    /// - Returns: A future that resolves to `crate::Result<(S, SocksSocketAddr)>`, the stream
+    ///   relayed to `destination` and the BND.ADDR the server replied with.
+    #[instrument(skip(self, stream))]
+    pub async fn connect_with<S>(
+        &self,
+        mut stream: S,
+        destination: SocksSocketAddr,
+    ) -> crate::Result<(S, SocksSocketAddr)>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        self.handshake(&mut stream).await?;
+
+        Self::request(&mut stream, Command::Connect, &destination).await?;
+        let bnd_addr = Self::read_reply(&mut stream).await?;
+        debug!("Connection established, bound to: {}", bnd_addr);
+
+        Ok((stream, bnd_addr))
+    }
+}
@@ -0,0 +1,224 @@
+//! # Context Module
+//!
+//! This module provides `SessionContext`, the information a session has about its client, passed
+//! to the authenticator and to the method handlers: the addresses of the connection, the id and
+//! start time of the session and the negotiated authentication method. Its `Extensions` let the
+//! authenticator attach typed values of its own for the method handlers, e.g. the groups of the
+//! authenticated user.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use gerevs::{
+//!     context::SessionContext,
+//!     method_handlers::{Connect, SocksSocketAddr},
+//! };
+//! use tokio::{
+//!     io::{AsyncRead, AsyncWrite},
+//!     net::TcpStream,
+//! };
+//!
+//! struct Department(String);
+//!
+//! struct LoggedConnect;
+//!
+//! impl<C: Send> Connect<C> for LoggedConnect {
+//!     type ServerConnection = TcpStream;
+//!
+//!     async fn establish_connection(
+//!         &mut self,
+//!         destination: SocksSocketAddr,
+//!         _credentials: C,
+//!         context: &SessionContext,
+//!     ) -> gerevs::Result<TcpStream> {
+//!         let department = context.extensions().get::<Department>();
+//!         println!(
+//!             "Session {} of {:?} ({:?}) connects to {}",
+//!             context.session_id(),
+//!             context.peer_addr(),
+//!             department.map(|department| &department.0),
+//!             destination
+//!         );
+//!         Ok(TcpStream::connect(destination.to_string()).await?)
+//!     }
+//!
+//!     async fn start_listening<T>(self, mut client: T, mut connection: TcpStream) -> gerevs::Result<()>
+//!     where
+//!         T: AsyncWrite + AsyncRead + Send + Unpin + 'static,
+//!     {
+//!         tokio::io::copy_bidirectional(&mut client, &mut connection).await?;
+//!         Ok(())
+//!     }
+//! }
+//! ```
+
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt,
+    net::SocketAddr,
+    time::{Instant, SystemTime},
+};
+
+use crate::{observer::SessionId, protocol::AuthMethod};
+
+/// The `SessionContext` struct describes the client of a session. It's created by the session,
+/// `Socks5Server` fills in both addresses of the connection, while a `Socks5Socket` created by
+/// hand is given them with `Socks5Socket::with_peer_addr` and `Socks5Socket::with_local_addr`.
+pub struct SessionContext {
+    session_id: SessionId,
+    peer_addr: Option<SocketAddr>,
+    local_addr: Option<SocketAddr>,
+    started_at: SystemTime,
+    started: Instant,
+    method: Option<AuthMethod>,
+    extensions: Extensions,
+}
+
+impl SessionContext {
+    pub(crate) fn new() -> Self {
+        Self {
+            session_id: SessionId::next(),
+            peer_addr: None,
+            local_addr: None,
+            started_at: SystemTime::now(),
+            started: Instant::now(),
+            method: None,
+            extensions: Extensions::default(),
+        }
+    }
+
+    /// Returns the id of the session, carried by the events reported to the `SessionObserver`.
+    pub fn session_id(&self) -> SessionId {
+        self.session_id
+    }
+
+    /// Returns the address of the client, when known by the session.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    pub(crate) fn set_peer_addr(&mut self, peer_addr: SocketAddr) {
+        self.peer_addr = Some(peer_addr);
+    }
+
+    /// Returns the local address the client connected to, when known by the session.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    pub(crate) fn set_local_addr(&mut self, local_addr: SocketAddr) {
+        self.local_addr = Some(local_addr);
+    }
+
+    /// Returns the time the session started at.
+    pub fn started_at(&self) -> SystemTime {
+        self.started_at
+    }
+
+    /// Returns the time elapsed since the session started.
+    pub fn elapsed(&self) -> std::time::Duration {
+        self.started.elapsed()
+    }
+
+    /// Returns the authentication method selected for a SOCKS5 client, `None` before the method
+    /// is selected, and for SOCKS4 and HTTP clients which don't negotiate a method.
+    pub fn method(&self) -> Option<AuthMethod> {
+        self.method
+    }
+
+    pub(crate) fn set_method(&mut self, method: AuthMethod) {
+        self.method = Some(method);
+    }
+
+    /// Returns the values attached to the session.
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    /// Returns the values attached to the session, for the authenticator to attach its own.
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+}
+
+impl fmt::Debug for SessionContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionContext")
+            .field("session_id", &self.session_id)
+            .field("peer_addr", &self.peer_addr)
+            .field("local_addr", &self.local_addr)
+            .field("started_at", &self.started_at)
+            .field("method", &self.method)
+            .field("extensions", &self.extensions)
+            .finish()
+    }
+}
+
+/// The `Extensions` struct is a map holding at most one value of every type.
+#[derive(Default)]
+pub struct Extensions {
+    values: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl Extensions {
+    /// Inserts `value`, returning the value of the same type it replaced.
+    pub fn insert<V>(&mut self, value: V) -> Option<V>
+    where
+        V: Send + Sync + 'static,
+    {
+        self.values
+            .insert(TypeId::of::<V>(), Box::new(value))
+            .and_then(|previous| previous.downcast().ok())
+            .map(|previous| *previous)
+    }
+
+    /// Returns the value of type `V`, if one was inserted.
+    pub fn get<V>(&self) -> Option<&V>
+    where
+        V: Send + Sync + 'static,
+    {
+        self.values
+            .get(&TypeId::of::<V>())
+            .and_then(|value| value.downcast_ref())
+    }
+
+    /// Returns the value of type `V` mutably, if one was inserted.
+    pub fn get_mut<V>(&mut self) -> Option<&mut V>
+    where
+        V: Send + Sync + 'static,
+    {
+        self.values
+            .get_mut(&TypeId::of::<V>())
+            .and_then(|value| value.downcast_mut())
+    }
+
+    /// Removes and returns the value of type `V`, if one was inserted.
+    pub fn remove<V>(&mut self) -> Option<V>
+    where
+        V: Send + Sync + 'static,
+    {
+        self.values
+            .remove(&TypeId::of::<V>())
+            .and_then(|value| value.downcast().ok())
+            .map(|value| *value)
+    }
+
+    /// Returns the number of values.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Returns whether no value was inserted.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Extensions")
+            .field("len", &self.values.len())
+            .finish()
+    }
+}
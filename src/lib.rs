@@ -42,7 +42,7 @@
 //!
 //! Basic usage:
 //!
-//! ```rust
+//! ```rust,no_run
 //! use gerevs::{
 //!     auth::NoAuthAuthenticator,
 //!     method_handlers::{TunnelAssociate, TunnelBind, TunnelConnect},
@@ -70,9 +70,9 @@
 //!     let mut socks5_stream = Socks5Socket::new(
 //!         client,
 //!         NoAuthAuthenticator,
-//!         TunnelConnect,
-//!         TunnelBind,
-//!         TunnelAssociate,
+//!         TunnelConnect::new(),
+//!         TunnelBind::new(),
+//!         TunnelAssociate::new(),
 //!     );
 //!     socks5_stream.run().await
 //! }
@@ -80,6 +80,34 @@
 //!
 //! This example demonstrates setting up a basic SOCKS5 proxy server that listens for incoming connections on port 1080 and handles them asynchronously.
 //!
+//! The accept loop above is also available as `Socks5Server`, which additionally supports graceful shutdown:
+//!
+//! ```rust,no_run
+//! use gerevs::{
+//!     auth::NoAuthAuthenticator,
+//!     method_handlers::{TunnelAssociate, TunnelBind, TunnelConnect},
+//!     Socks5Server,
+//! };
+//! use std::error::Error;
+//! use tokio::net::TcpListener;
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), Box<dyn Error>> {
+//!     let listener = TcpListener::bind("0.0.0.0:1080").await?;
+//!     let server = Socks5Server::new(
+//!         listener,
+//!         || NoAuthAuthenticator,
+//!         TunnelConnect::new,
+//!         TunnelBind::new,
+//!         TunnelAssociate::new,
+//!     );
+//!     server
+//!         .run_until(async { tokio::signal::ctrl_c().await.unwrap() })
+//!         .await?;
+//!     Ok(())
+//! }
+//! ```
+//!
 //! ### Explanation of Non-Obvious Parts
 //!
 //! 1. **`NoAuthAuthenticator`**:
@@ -92,6 +120,7 @@
 //!     - `TunnelConnect` implements the `Connect` trait, establishing a direct TCP connection to a specified target server.
 //!     - `TunnelBind` implements the `Bind` trait, setting up a TCP listener that waits for incoming connections from a target server, and forwards any messages between the two.
 //!     - `TunnelAssociate` implements the `Associate` trait, Forwards UDP packets between the client and the target server.
+//!     - Domain names are resolved with the system resolver by default, `with_resolver` accepts any `resolver::Resolver` such as `resolver::DnsResolver`.
 //!
 //! 3. **`Socks5Socket`**:
 //!     - This is the main struct from the `gerevs` crate that represents a SOCKS5 connection.
@@ -101,7 +130,11 @@
 //!     - This method starts the SOCKS5 protocol operations on the given connection.
 //!     - It processes the handshake, authentication (if any), and the command handling (CONNECT, BIND, or UDP ASSOCIATE) based on the client's requests.
 //!
-//! 5. **`gerevs::Result`**:
+//! 5. **`Socks5Server`**:
+//!     - Owns a `TcpListener` and runs a `Socks5Socket` on its own task for every accepted connection.
+//!     - The authenticator and method handlers are created per connection by the given factories.
+//!
+//! 6. **`gerevs::Result`**:
 //!     - A custom result type provided by the `gerevs` crate
 //!
 //! By understanding these parts, you can see how the `gerevs` crate simplifies the implementation of a SOCKS5 proxy server, handling the complex protocol details and allowing you to focus on the server logic.
@@ -110,11 +143,22 @@ use std::io;
 
 use protocol::Reply;
 
+pub mod access_log;
+pub mod accounting;
+pub mod acl;
 pub mod auth;
+pub mod client;
+pub mod context;
 pub mod method_handlers;
+pub mod metrics;
+pub mod observer;
 pub(crate) mod protocol;
+pub mod rate_limit;
+pub mod resolver;
+mod server;
 mod socks5_socket;
-pub use socks5_socket::Socks5Socket;
+pub use server::Socks5Server;
+pub use socks5_socket::{FragmentPolicy, Socks5Socket, Timeouts};
 use thiserror::Error;
 
 pub type Result<T> = std::result::Result<T, Socks5Error>;
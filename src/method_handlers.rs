@@ -1,9 +1,12 @@
+mod address_guard;
 mod associate;
 mod bind;
 mod connect;
 
-pub use crate::protocol::SocksSocketAddr;
+pub use crate::protocol::{Addr, SocksSocketAddr};
+pub use address_guard::AddressGuard;
 pub use associate::associate_denier::AssociateDenier;
+pub use associate::guarded_associate::GuardedAssociate;
 pub use associate::tunnel_associate::TunnelAssociate;
 pub use associate::Associate;
 
@@ -11,6 +14,9 @@ pub use bind::bind_denier::BindDenier;
 pub use bind::tunnel_bind::TunnelBind;
 pub use bind::Bind;
 
+pub use connect::chain_connect::{ChainConnect, Hop, HopProtocol};
 pub use connect::connect_denier::ConnectDenier;
+pub use connect::guarded_connect::GuardedConnect;
+pub use connect::strategy::{ConnectStrategy, HappyEyeballs};
 pub use connect::tunnel_connect::TunnelConnect;
 pub use connect::Connect;
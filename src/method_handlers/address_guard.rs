@@ -0,0 +1,224 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use tracing::{debug, warn};
+
+use crate::{
+    acl::Cidr,
+    protocol::{Reply, SocksSocketAddr},
+    resolver::Resolver,
+    Socks5Error,
+};
+
+/// Networks that aren't reachable from the internet, or that reach the proxy host itself.
+const FORBIDDEN_NETWORKS: &[(IpAddr, u8)] = &[
+    // IPv4: "this network", private, shared address space (CGNAT), loopback, link-local
+    // (including cloud metadata services), IETF protocol assignments, documentation, benchmarking,
+    // multicast and reserved (including broadcast).
+    (IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 8),
+    (IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)), 8),
+    (IpAddr::V4(Ipv4Addr::new(100, 64, 0, 0)), 10),
+    (IpAddr::V4(Ipv4Addr::new(127, 0, 0, 0)), 8),
+    (IpAddr::V4(Ipv4Addr::new(169, 254, 0, 0)), 16),
+    (IpAddr::V4(Ipv4Addr::new(172, 16, 0, 0)), 12),
+    (IpAddr::V4(Ipv4Addr::new(192, 0, 0, 0)), 24),
+    (IpAddr::V4(Ipv4Addr::new(192, 0, 2, 0)), 24),
+    (IpAddr::V4(Ipv4Addr::new(192, 168, 0, 0)), 16),
+    (IpAddr::V4(Ipv4Addr::new(198, 18, 0, 0)), 15),
+    (IpAddr::V4(Ipv4Addr::new(198, 51, 100, 0)), 24),
+    (IpAddr::V4(Ipv4Addr::new(203, 0, 113, 0)), 24),
+    (IpAddr::V4(Ipv4Addr::new(224, 0, 0, 0)), 4),
+    (IpAddr::V4(Ipv4Addr::new(240, 0, 0, 0)), 4),
+    // IPv6: unspecified, loopback and IPv4-compatible, local-use NAT64, Teredo, documentation,
+    // unique local (ULA), link-local, deprecated site-local and multicast. IPv4-mapped addresses
+    // are checked as IPv4, as are the IPv4 addresses embedded in well-known NAT64 and 6to4
+    // addresses, see `embedded_ipv4`.
+    (IpAddr::V6(Ipv6Addr::UNSPECIFIED), 96),
+    (
+        IpAddr::V6(Ipv6Addr::new(0x64, 0xff9b, 1, 0, 0, 0, 0, 0)),
+        48,
+    ),
+    (IpAddr::V6(Ipv6Addr::new(0x2001, 0, 0, 0, 0, 0, 0, 0)), 32),
+    (
+        IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0)),
+        32,
+    ),
+    (IpAddr::V6(Ipv6Addr::new(0xfc00, 0, 0, 0, 0, 0, 0, 0)), 7),
+    (IpAddr::V6(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0)), 10),
+    (IpAddr::V6(Ipv6Addr::new(0xfec0, 0, 0, 0, 0, 0, 0, 0)), 10),
+    (IpAddr::V6(Ipv6Addr::new(0xff00, 0, 0, 0, 0, 0, 0, 0)), 8),
+];
+
+/// The `AddressGuard` struct decides which IP addresses a proxy may reach on behalf of its clients,
+/// protecting the networks behind the proxy from server side request forgery (SSRF).
+///
+/// `AddressGuard::default()` forbids private, loopback, link-local, unique local, documentation,
+/// multicast and other non-public ranges, for both IPv4 and IPv6. IPv4-mapped IPv6 addresses are checked as the
+/// IPv4 address they map, and NAT64 (`64:ff9b::/96`) and 6to4 (`2002::/16`) addresses are also
+/// checked as the IPv4 address they embed, which they may be translated or tunneled to.
+#[derive(Debug, Clone)]
+pub struct AddressGuard {
+    forbidden: Vec<Cidr>,
+    allowed: Vec<Cidr>,
+}
+
+impl Default for AddressGuard {
+    fn default() -> Self {
+        Self {
+            forbidden: FORBIDDEN_NETWORKS
+                .iter()
+                .filter_map(|&(addr, prefix)| Cidr::new(addr, prefix))
+                .collect(),
+            allowed: Vec::new(),
+        }
+    }
+}
+
+impl AddressGuard {
+    /// Creates an `AddressGuard` that doesn't forbid any address.
+    pub fn empty() -> Self {
+        Self {
+            forbidden: Vec::new(),
+            allowed: Vec::new(),
+        }
+    }
+
+    /// Forbids reaching addresses inside `network`.
+    pub fn forbid(mut self, network: Cidr) -> Self {
+        self.forbidden.push(network);
+        self
+    }
+
+    /// Allows reaching addresses inside `network` even if they are inside a forbidden network.
+    pub fn allow(mut self, network: Cidr) -> Self {
+        self.allowed.push(network);
+        self
+    }
+
+    /// Returns whether `ip` may be reached. An address embedding an IPv4 address may only be
+    /// reached if the IPv4 address may be too.
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        let permitted = |ip: IpAddr| {
+            self.allowed.iter().any(|network| network.contains(ip))
+                || !self.forbidden.iter().any(|network| network.contains(ip))
+        };
+        let embedded_permitted = match embedded_ipv4(ip) {
+            Some(embedded) => permitted(embedded.into()),
+            None => true,
+        };
+        permitted(ip) && embedded_permitted
+    }
+
+    /// Resolves `dst` once and vets every resulting address. The returned addresses are the only
+    /// ones that should be connected to, resolving the domain again would allow DNS rebinding.
+    ///
+    /// - `dst`: The destination requested by the client.
+    /// - `resolver`: The resolver used to resolve a domain destination.
+    /// - Returns: A future that resolves to `crate::Result<Vec<SocketAddr>>`, an error with
+    ///   `ConnectionNotAllowedByRuleset` if any of the addresses is forbidden.
+    pub async fn vet<R>(
+        &self,
+        dst: &SocksSocketAddr,
+        resolver: &R,
+    ) -> crate::Result<Vec<SocketAddr>>
+    where
+        R: Resolver,
+    {
+        let resolved = dst.resolve(resolver).await?;
+        if resolved.is_empty() {
+            return Err(Socks5Error::Socks5Error(Reply::HostUnreachable));
+        }
+
+        if let Some(forbidden) = resolved.iter().find(|addr| !self.is_allowed(addr.ip())) {
+            warn!(
+                "Destination {} resolved to forbidden address {}",
+                dst, forbidden
+            );
+            return Err(Socks5Error::Socks5Error(
+                Reply::ConnectionNotAllowedByRuleset,
+            ));
+        }
+
+        debug!("Destination {} vetted: {:?}", dst, resolved);
+        Ok(resolved)
+    }
+}
+
+/// Returns the IPv4 address embedded in a NAT64 (RFC 6052 well-known prefix) or 6to4 (RFC 3056)
+/// address.
+fn embedded_ipv4(ip: IpAddr) -> Option<Ipv4Addr> {
+    let IpAddr::V6(ip) = ip else {
+        return None;
+    };
+    let octets = ip.octets();
+    match ip.segments() {
+        [0x64, 0xff9b, 0, 0, 0, 0, _, _] => Some(Ipv4Addr::new(
+            octets[12], octets[13], octets[14], octets[15],
+        )),
+        [0x2002, ..] => Some(Ipv4Addr::new(octets[2], octets[3], octets[4], octets[5])),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_forbids_non_public_addresses() {
+        let guard = AddressGuard::default();
+        for (ip, allowed) in [
+            ("127.0.0.1", false),
+            ("169.254.169.254", false),
+            ("10.1.2.3", false),
+            ("192.0.2.1", false),
+            ("198.51.100.1", false),
+            ("203.0.113.1", false),
+            ("255.255.255.255", false),
+            ("::1", false),
+            ("fd00::1", false),
+            ("fe80::1", false),
+            ("2001:db8::1", false),
+            ("::ffff:127.0.0.1", false),
+            ("::ffff:10.0.0.1", false),
+            // NAT64 and 6to4 embedding a private address
+            ("64:ff9b::a00:1", false),
+            ("64:ff9b::7f00:1", false),
+            ("2002:7f00:1::", false),
+            ("2002:a00:1::1", false),
+            // Local-use NAT64 and Teredo
+            ("64:ff9b:1::808:808", false),
+            ("2001:0:4136:e378:8000:63bf:3fff:fdd2", false),
+            // Public addresses, also when embedded
+            ("93.184.215.14", true),
+            ("::ffff:8.8.8.8", true),
+            ("2606:4700:4700::1111", true),
+            ("64:ff9b::808:808", true),
+            ("2002:808:808::", true),
+        ] {
+            assert_eq!(guard.is_allowed(ip.parse().unwrap()), allowed, "{}", ip);
+        }
+    }
+
+    #[test]
+    fn embedded_ipv4_addresses() {
+        let embedded = |ip: &str| embedded_ipv4(ip.parse().unwrap());
+        assert_eq!(embedded("64:ff9b::a00:1"), Some(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(embedded("2002:7f00:1::"), Some(Ipv4Addr::new(127, 0, 0, 1)));
+        assert_eq!(embedded("64:ff9b:1::a00:1"), None);
+        assert_eq!(embedded("2001:db8::1"), None);
+        assert_eq!(embedded("10.0.0.1"), None);
+    }
+
+    #[test]
+    fn allowed_networks_take_precedence() {
+        let guard = AddressGuard::default().allow("10.1.0.0/16".parse().unwrap());
+        assert!(guard.is_allowed("10.1.2.3".parse().unwrap()));
+        assert!(guard.is_allowed("64:ff9b::a01:203".parse().unwrap()));
+        assert!(!guard.is_allowed("10.2.0.1".parse().unwrap()));
+
+        let guard = AddressGuard::empty().forbid("8.8.8.0/24".parse().unwrap());
+        assert!(!guard.is_allowed("8.8.8.8".parse().unwrap()));
+        assert!(!guard.is_allowed("2002:808:808::".parse().unwrap()));
+        assert!(guard.is_allowed("127.0.0.1".parse().unwrap()));
+    }
+}
@@ -1,7 +1,9 @@
-use std::net::SocketAddr;
-use tokio::net::ToSocketAddrs;
+use std::{io, net::SocketAddr};
+
+use crate::{context::SessionContext, protocol::SocksSocketAddr, resolver::SystemResolver};
 
 pub mod associate_denier;
+pub mod guarded_associate;
 pub mod tunnel_associate;
 
 /// The `Associate` trait defines the necessary operations for handling the SOCKS5 UDP ASSOCIATE command.
@@ -23,33 +25,61 @@ where
     /// resolves to a result containing the local socket addressa (Needed for the socks5 protocol) and a connection object.
     ///
     /// - `credentials`: The credentials required for the operation.
+    /// - `context`: The context of the session, see `SessionContext`.
     /// - Returns: A future that resolves to `crate::Result<(SocketAddr, Self::Connection)>`.
     fn bind(
         &self,
         credentials: &C,
+        context: &SessionContext,
     ) -> impl std::future::Future<Output = crate::Result<(SocketAddr, Self::Connection)>> + Send;
 
-    /// Sends a UDP packet to the specified destination address. It returns a future that resolves
-    /// to a result containing the number of bytes sent.
+    /// Sends a UDP packet from the client to the specified destination address. It returns a future
+    /// that resolves to a result containing the number of bytes sent.
+    ///
+    /// An error drops the datagram without closing the association, e.g. when the destination isn't
+    /// allowed. Dropped datagrams aren't counted as relayed.
+    ///
+    /// - `conn`: A mutable reference to the connection object.
+    /// - `buf`: The buffer containing the data to be sent.
+    /// - `dst`: The destination address to which the data should be sent, as requested by the client.
+    /// - `credentials`: The credentials required for the operation.
+    /// - Returns: A future that resolves to `crate::Result<usize>`.
+    fn send_to(
+        &mut self,
+        conn: &mut Self::Connection,
+        buf: &[u8],
+        dst: SocksSocketAddr,
+        credentials: &C,
+    ) -> impl std::future::Future<Output = crate::Result<usize>> + Send;
+
+    /// Sends a UDP packet, already wrapped in the UDP request header, back to the client.
+    /// It returns a future that resolves to a result containing the number of bytes sent.
+    ///
+    /// By default this calls `send_to` with the client's address, implementations that filter
+    /// destinations in `send_to` should override it so replies always reach the client. As with
+    /// `send_to`, an error drops the datagram.
     ///
     /// - `conn`: A mutable reference to the connection object.
     /// - `buf`: The buffer containing the data to be sent.
-    /// - `dst`: The destination address to which the data should be sent.
+    /// - `client`: The address of the client.
     /// - `credentials`: The credentials required for the operation.
     /// - Returns: A future that resolves to `crate::Result<usize>`.
-    fn send_to<A>(
+    fn send_to_client(
         &mut self,
         conn: &mut Self::Connection,
         buf: &[u8],
-        dst: A,
+        client: SocketAddr,
         credentials: &C,
-    ) -> impl std::future::Future<Output = crate::Result<usize>> + Send
-    where
-        A: ToSocketAddrs + Send;
+    ) -> impl std::future::Future<Output = crate::Result<usize>> + Send {
+        self.send_to(conn, buf, client.into(), credentials)
+    }
 
     /// Receives a UDP packet from a source address. It returns a future that resolves to a result
     /// containing the number of bytes received and the source address.
     ///
+    /// I/O errors are ignored and the association keeps receiving, while a `Socks5Error::Socks5Error`
+    /// closes the association, e.g. when the session may no longer relay datagrams.
+    ///
     /// - `conn`: A mutable reference to the connection object.
     /// - `buf`: The buffer to store the received data.
     /// - `credentials`: The credentials required for the operation.
@@ -60,4 +90,19 @@ where
         buf: &mut [u8],
         credentials: &C,
     ) -> impl std::future::Future<Output = crate::Result<(usize, SocketAddr)>> + Send;
+
+    /// Resolves `addr` with the resolver of the handler, e.g. the address the client announced it
+    /// sends its datagrams from. It returns a future that resolves to the resolved addresses.
+    ///
+    /// By default this uses the `SystemResolver`, implementations with a configurable `Resolver`
+    /// should override it so every address of the association is resolved the same way.
+    ///
+    /// - `addr`: The address to resolve.
+    /// - Returns: A future that resolves to `io::Result<Vec<SocketAddr>>`.
+    fn resolve(
+        &self,
+        addr: &SocksSocketAddr,
+    ) -> impl std::future::Future<Output = io::Result<Vec<SocketAddr>>> + Send {
+        addr.resolve(&SystemResolver)
+    }
 }
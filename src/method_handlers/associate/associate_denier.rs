@@ -1,6 +1,9 @@
 use std::net::SocketAddr;
 
-use crate::protocol::Reply;
+use crate::{
+    context::SessionContext,
+    protocol::{Reply, SocksSocketAddr},
+};
 
 use super::Associate;
 
@@ -13,20 +16,21 @@ where
     C: Sync + Send,
 {
     type Connection = ();
-    async fn bind(&self, _: &C) -> crate::Result<(SocketAddr, Self::Connection)> {
+    async fn bind(
+        &self,
+        _: &C,
+        _: &SessionContext,
+    ) -> crate::Result<(SocketAddr, Self::Connection)> {
         Err(crate::Socks5Error::Socks5Error(Reply::CommandNotSupported))
     }
 
-    async fn send_to<A>(
+    async fn send_to(
         &mut self,
         _: &mut Self::Connection,
         _: &[u8],
-        _: A,
+        _: SocksSocketAddr,
         _: &C,
-    ) -> crate::Result<usize>
-    where
-        A: tokio::net::ToSocketAddrs,
-    {
+    ) -> crate::Result<usize> {
         unreachable!()
     }
 
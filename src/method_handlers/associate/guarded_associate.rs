@@ -0,0 +1,108 @@
+use std::{io, net::SocketAddr, sync::Arc};
+
+use tokio::net::UdpSocket;
+
+use crate::{
+    context::SessionContext,
+    method_handlers::{AddressGuard, TunnelAssociate},
+    protocol::SocksSocketAddr,
+    resolver::{Resolver, SystemResolver},
+};
+
+use super::Associate;
+
+/// The `GuardedAssociate` struct is an implementation of the `Associate` trait that relays UDP
+/// packets like `TunnelAssociate`, but only to destinations allowed by an `AddressGuard`.
+/// Packets to forbidden destinations are dropped.
+///
+/// Domain names are resolved exactly once per packet and the packet is sent to the vetted address.
+///
+/// `GuardedAssociate::default()` uses `AddressGuard::default()`, which forbids private, loopback
+/// and link-local destinations.
+pub struct GuardedAssociate<R = SystemResolver> {
+    guard: Arc<AddressGuard>,
+    resolver: R,
+}
+
+impl GuardedAssociate {
+    /// Creates a new `GuardedAssociate` checking destinations with `guard`.
+    pub fn new(guard: Arc<AddressGuard>) -> Self {
+        Self {
+            guard,
+            resolver: SystemResolver,
+        }
+    }
+}
+
+impl Default for GuardedAssociate {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl<R> GuardedAssociate<R> {
+    /// Sets the resolver used to resolve the destination domain names.
+    pub fn with_resolver<R2>(self, resolver: R2) -> GuardedAssociate<R2>
+    where
+        R2: Resolver,
+    {
+        GuardedAssociate {
+            guard: self.guard,
+            resolver,
+        }
+    }
+}
+
+impl<C, R> Associate<C> for GuardedAssociate<R>
+where
+    C: Sync + Send,
+    R: Resolver,
+{
+    type Connection = UdpSocket;
+
+    async fn bind(
+        &self,
+        credentials: &C,
+        context: &SessionContext,
+    ) -> crate::Result<(SocketAddr, Self::Connection)> {
+        TunnelAssociate::new().bind(credentials, context).await
+    }
+
+    async fn send_to(
+        &mut self,
+        conn: &mut Self::Connection,
+        buf: &[u8],
+        dst: SocksSocketAddr,
+        _: &C,
+    ) -> crate::Result<usize> {
+        let addrs = self.guard.vet(&dst, &self.resolver).await?;
+        let res = conn.send_to(buf, addrs[0]).await?;
+        Ok(res)
+    }
+
+    async fn send_to_client(
+        &mut self,
+        conn: &mut Self::Connection,
+        buf: &[u8],
+        client: SocketAddr,
+        _: &C,
+    ) -> crate::Result<usize> {
+        let res = conn.send_to(buf, client).await?;
+        Ok(res)
+    }
+
+    async fn recv_from(
+        &mut self,
+        conn: &mut Self::Connection,
+        buf: &mut [u8],
+        credentials: &C,
+    ) -> crate::Result<(usize, SocketAddr)> {
+        TunnelAssociate::new()
+            .recv_from(conn, buf, credentials)
+            .await
+    }
+
+    async fn resolve(&self, addr: &SocksSocketAddr) -> io::Result<Vec<SocketAddr>> {
+        addr.resolve(&self.resolver).await
+    }
+}
@@ -1,7 +1,13 @@
-use std::net::SocketAddr;
+use std::{io, net::SocketAddr};
 
 use tokio::net::UdpSocket;
 
+use crate::{
+    context::SessionContext,
+    protocol::SocksSocketAddr,
+    resolver::{Resolver, SystemResolver},
+};
+
 use super::Associate;
 
 /// The `TunnelAssociate` struct is an implementation of the `Associate` trait that handles
@@ -13,31 +19,62 @@ use super::Associate;
 /// any additional processing or filtering.
 ///
 /// This struct can be used in scenarios where basic UDP traffic needs to be tunneled through
-/// a SOCKS5 proxy server without any special handling or configuration.pub struct TunnelAssociate;
-pub struct TunnelAssociate;
+/// a SOCKS5 proxy server without any special handling or configuration.
+pub struct TunnelAssociate<R = SystemResolver> {
+    resolver: R,
+}
+
+impl TunnelAssociate {
+    /// Creates a new `TunnelAssociate` resolving domain names with the `SystemResolver`.
+    pub fn new() -> Self {
+        Self {
+            resolver: SystemResolver,
+        }
+    }
+}
+
+impl Default for TunnelAssociate {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R> TunnelAssociate<R> {
+    /// Sets the resolver used to resolve the domain names packets are sent to.
+    pub fn with_resolver<R2>(self, resolver: R2) -> TunnelAssociate<R2>
+    where
+        R2: Resolver,
+    {
+        TunnelAssociate { resolver }
+    }
+}
 
-impl<C> Associate<C> for TunnelAssociate
+impl<C, R> Associate<C> for TunnelAssociate<R>
 where
     C: Sync + Send,
+    R: Resolver,
 {
     type Connection = UdpSocket;
-    async fn bind(&self, _: &C) -> crate::Result<(SocketAddr, Self::Connection)> {
+    async fn bind(
+        &self,
+        _: &C,
+        _: &SessionContext,
+    ) -> crate::Result<(SocketAddr, Self::Connection)> {
         let socket = UdpSocket::bind("0.0.0.0:0").await?;
         let peer_addr = socket.local_addr()?;
         Ok((peer_addr, socket))
     }
 
-    async fn send_to<A>(
+    async fn send_to(
         &mut self,
         conn: &mut Self::Connection,
         buf: &[u8],
-        dst: A,
+        dst: SocksSocketAddr,
         _: &C,
-    ) -> crate::Result<usize>
-    where
-        A: tokio::net::ToSocketAddrs,
-    {
-        let res = conn.send_to(buf, dst).await?;
+    ) -> crate::Result<usize> {
+        let res = conn
+            .send_to(buf, &*dst.resolve(&self.resolver).await?)
+            .await?;
         Ok(res)
     }
 
@@ -50,4 +87,8 @@ where
         let res = conn.recv_from(buf).await?;
         Ok(res)
     }
+
+    async fn resolve(&self, addr: &SocksSocketAddr) -> io::Result<Vec<SocketAddr>> {
+        addr.resolve(&self.resolver).await
+    }
 }
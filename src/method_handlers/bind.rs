@@ -2,7 +2,7 @@ use std::net::SocketAddr;
 
 use tokio::io::{AsyncRead, AsyncWrite};
 
-use crate::protocol::SocksSocketAddr;
+use crate::{context::SessionContext, protocol::SocksSocketAddr};
 
 pub mod bind_denier;
 pub mod tunnel_bind;
@@ -27,12 +27,14 @@ pub trait Bind<C> {
     ///
     /// - `addr`: The address to which the bind operation should be performed.
     /// - `credentials`: The credentials required for the operation.
+    /// - `context`: The context of the session, see `SessionContext`.
     /// - Returns: A future that resolves to `crate::Result<(SocketAddr, Self::Listener)>`.
     fn bind(
         &mut self,
         addr: SocksSocketAddr,
         _: &C,
-    ) -> impl std::future::Future<Output = crate::Result<(SocketAddr, Self::Listener)>>;
+        context: &SessionContext,
+    ) -> impl std::future::Future<Output = crate::Result<(SocketAddr, Self::Listener)>> + Send;
 
     /// Accepts an incoming TCP connection on the bound address.
     /// It returns a future that resolves to a result containing the stream and the client's socket address.
@@ -18,6 +18,7 @@ where
         &mut self,
         _: crate::protocol::SocksSocketAddr,
         _: &C,
+        _: &crate::context::SessionContext,
     ) -> crate::Result<(std::net::SocketAddr, Self::Listener)> {
         Err(Socks5Error::Socks5Error(Reply::CommandNotSupported))
     }
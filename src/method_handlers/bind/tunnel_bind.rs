@@ -2,7 +2,10 @@ use std::io;
 
 use tokio::net::{TcpListener, TcpStream};
 
+use crate::resolver::{Resolver, SystemResolver};
+
 use super::Bind;
+
 /// The `TunnelBind` struct is an implementation of the `Bind` trait that handles TCP BIND requests
 /// by establishing a TCP connection on a random available port and relaying data between the client and the target server.
 ///
@@ -11,9 +14,40 @@ use super::Bind;
 ///
 /// This struct can be used in scenarios where basic TCP traffic needs to be tunneled through
 /// a SOCKS5 proxy server without any special handling or configuration.
-pub struct TunnelBind;
+pub struct TunnelBind<R = SystemResolver> {
+    resolver: R,
+}
+
+impl TunnelBind {
+    /// Creates a new `TunnelBind` resolving domain names with the `SystemResolver`.
+    pub fn new() -> Self {
+        Self {
+            resolver: SystemResolver,
+        }
+    }
+}
+
+impl Default for TunnelBind {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R> TunnelBind<R> {
+    /// Sets the resolver used to resolve the requested bind domain names.
+    pub fn with_resolver<R2>(self, resolver: R2) -> TunnelBind<R2>
+    where
+        R2: Resolver,
+    {
+        TunnelBind { resolver }
+    }
+}
 
-impl Bind<()> for TunnelBind {
+impl<C, R> Bind<C> for TunnelBind<R>
+where
+    C: Send + Sync,
+    R: Resolver,
+{
     type Listener = TcpListener;
 
     type Stream = TcpStream;
@@ -22,7 +56,7 @@ impl Bind<()> for TunnelBind {
         self,
         mut server: T,
         mut client: tokio::net::TcpStream,
-        _: (),
+        _: C,
     ) -> crate::Result<()>
     where
         T: tokio::io::AsyncWrite + tokio::io::AsyncRead + Send + Unpin,
@@ -44,9 +78,10 @@ impl Bind<()> for TunnelBind {
     async fn bind(
         &mut self,
         addr: crate::protocol::SocksSocketAddr,
-        _: &(),
+        _: &C,
+        _: &crate::context::SessionContext,
     ) -> crate::Result<(std::net::SocketAddr, Self::Listener)> {
-        let addrs = &*addr.to_socket_addr().await?;
+        let addrs = &*addr.resolve(&self.resolver).await?;
         let listener = TcpListener::bind(addrs).await?;
         let bound_addr = listener.local_addr()?;
         Ok((bound_addr, listener))
@@ -55,7 +90,7 @@ impl Bind<()> for TunnelBind {
     async fn accept(
         &mut self,
         server: Self::Listener,
-        _: &(),
+        _: &C,
     ) -> crate::Result<(Self::Stream, std::net::SocketAddr)> {
         let res = server.accept().await?;
         Ok(res)
@@ -1,7 +1,12 @@
+use std::net::SocketAddr;
+
 use tokio::io::{AsyncRead, AsyncWrite};
+pub mod chain_connect;
 pub mod connect_denier;
+pub mod guarded_connect;
+pub mod strategy;
 pub mod tunnel_connect;
-use crate::protocol::SocksSocketAddr;
+use crate::{context::SessionContext, protocol::SocksSocketAddr};
 
 /// The `Connect` trait defines the necessary operations for handling the SOCKS5 CONNECT command.
 /// This command is used to establish a TCP connection to a target server through a SOCKS5 proxy server.
@@ -19,13 +24,24 @@ pub trait Connect<C> {
     ///
     /// - `destination`: The target address to which the connection should be established.
     /// - `credentials`: The credentials required for the operation.
+    /// - `context`: The context of the session, see `SessionContext`.
     /// - Returns: A future that resolves to `crate::Result<Self::ServerConnection>`.
     fn establish_connection(
         &mut self,
         destination: SocksSocketAddr,
         credentials: C,
+        context: &SessionContext,
     ) -> impl std::future::Future<Output = crate::Result<Self::ServerConnection>> + Send;
 
+    /// Returns the address reported to the client in the BND.ADDR field of the reply, usually the
+    /// address the connection was actually established with.
+    ///
+    /// - `connection`: The established server connection.
+    /// - Returns: The address to report, `None` reports the requested destination.
+    fn reply_address(&self, _connection: &Self::ServerConnection) -> Option<SocketAddr> {
+        None
+    }
+
     /// Starts listening on the established server connection and forwards data between the client
     /// and the server connection. It returns a future that resolves to a result indicating the
     /// success or failure of the operation.
@@ -0,0 +1,377 @@
+use std::{
+    io::{self, ErrorKind},
+    sync::Arc,
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+};
+use tracing::{debug, instrument, warn};
+use zeroize::Zeroizing;
+
+use crate::{
+    auth::username_password_authenticator::User,
+    client::Socks5Client,
+    context::SessionContext,
+    protocol::{
+        http::{base64_encode, format_authority, MAX_HTTP_HEADER},
+        Reply, SocksSocketAddr,
+    },
+    resolver::{Resolver, SystemResolver},
+    Socks5Error,
+};
+
+use super::{strategy::ConnectStrategy, tunnel_connect::TunnelConnect, Connect};
+
+/// The protocol spoken with an upstream proxy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HopProtocol {
+    /// A SOCKS5 proxy, asked to connect with a CONNECT request.
+    Socks5,
+    /// An HTTP proxy, asked to connect with the `CONNECT` method.
+    HttpConnect,
+}
+
+/// An upstream proxy in a chain.
+#[derive(Debug, Clone)]
+pub struct Hop {
+    protocol: HopProtocol,
+    addr: SocksSocketAddr,
+    user: Option<User>,
+}
+
+impl Hop {
+    /// Creates a new `Hop` through the SOCKS5 proxy at `addr`.
+    pub fn socks5(addr: impl Into<SocksSocketAddr>) -> Self {
+        Self {
+            protocol: HopProtocol::Socks5,
+            addr: addr.into(),
+            user: None,
+        }
+    }
+
+    /// Creates a new `Hop` through the HTTP proxy at `addr`.
+    pub fn http_connect(addr: impl Into<SocksSocketAddr>) -> Self {
+        Self {
+            protocol: HopProtocol::HttpConnect,
+            addr: addr.into(),
+            user: None,
+        }
+    }
+
+    /// Authenticates to this proxy as `user`, with the username/password method for SOCKS5
+    /// proxies and with basic `Proxy-Authorization` for HTTP proxies.
+    pub fn with_user(mut self, user: User) -> Self {
+        self.user = Some(user);
+        self
+    }
+
+    /// Returns the protocol spoken with this proxy.
+    pub fn protocol(&self) -> HopProtocol {
+        self.protocol
+    }
+
+    /// Returns the address of this proxy.
+    pub fn addr(&self) -> &SocksSocketAddr {
+        &self.addr
+    }
+
+    /// Asks this proxy to connect to `destination` over `stream`, a connection to the proxy.
+    async fn tunnel(
+        &self,
+        stream: TcpStream,
+        destination: SocksSocketAddr,
+    ) -> crate::Result<TcpStream> {
+        match self.protocol {
+            HopProtocol::Socks5 => {
+                let client = match &self.user {
+                    Some(user) => Socks5Client::with_user(user.clone()),
+                    None => Socks5Client::new(),
+                };
+                let (stream, _) = client.connect_with(stream, destination).await?;
+                Ok(stream)
+            }
+            HopProtocol::HttpConnect => {
+                http_connect(stream, &destination, self.user.as_ref()).await
+            }
+        }
+    }
+}
+
+/// The `ChainConnect` struct is an implementation of the `Connect` trait that establishes the
+/// destination connection through one or more upstream proxies in sequence.
+///
+/// The first hop is connected to directly, every following hop is reached through the previous
+/// ones and the last hop connects to the destination. Data is relayed like `TunnelConnect` once
+/// the chain is established. Cloning a `ChainConnect` is cheap, so it can be shared by the
+/// handler factory of a `Socks5Server`.
+#[derive(Clone)]
+pub struct ChainConnect<R = SystemResolver> {
+    hops: Arc<[Hop]>,
+    resolver: R,
+    strategy: ConnectStrategy,
+}
+
+impl ChainConnect {
+    /// Creates a new `ChainConnect` going through `hops` in order.
+    pub fn new(hops: Vec<Hop>) -> Self {
+        Self {
+            hops: hops.into(),
+            resolver: SystemResolver,
+            strategy: ConnectStrategy::default(),
+        }
+    }
+}
+
+impl<R> ChainConnect<R> {
+    /// Sets the resolver used to resolve the address of the first hop.
+    pub fn with_resolver<R2>(self, resolver: R2) -> ChainConnect<R2>
+    where
+        R2: Resolver,
+    {
+        ChainConnect {
+            hops: self.hops,
+            resolver,
+            strategy: self.strategy,
+        }
+    }
+
+    /// Sets how the connection to the first hop is established when it has several addresses.
+    pub fn with_strategy(mut self, strategy: ConnectStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+}
+
+impl<C, R> Connect<C> for ChainConnect<R>
+where
+    C: Send,
+    R: Resolver,
+{
+    type ServerConnection = TcpStream;
+
+    #[instrument(skip(self, _credentials, _context))]
+    async fn establish_connection(
+        &mut self,
+        destination: SocksSocketAddr,
+        _credentials: C,
+        _context: &SessionContext,
+    ) -> crate::Result<TcpStream> {
+        let Some((first, _)) = self.hops.split_first() else {
+            warn!("Proxy chain has no hops");
+            return Err(Socks5Error::Socks5Error(Reply::GeneralFailure));
+        };
+
+        let addrs = first.addr.resolve(&self.resolver).await?;
+        let mut stream = self.strategy.connect(&addrs).await?;
+        debug!("Connected to first hop: {}", first.addr);
+
+        // Every hop is asked to connect to the next one, the last one to the destination
+        let targets = self.hops[1..]
+            .iter()
+            .map(|hop| hop.addr.clone())
+            .chain(Some(destination));
+        for (hop, target) in self.hops.iter().zip(targets) {
+            debug!("Tunneling through {} to {}", hop.addr, target);
+            stream = hop.tunnel(stream, target).await?;
+        }
+
+        Ok(stream)
+    }
+
+    async fn start_listening<T>(self, client: T, server: TcpStream) -> crate::Result<()>
+    where
+        T: AsyncWrite + AsyncRead + Send + Unpin + 'static,
+    {
+        <TunnelConnect as Connect<C>>::start_listening(TunnelConnect::new(), client, server).await
+    }
+}
+
+/// Issues an HTTP `CONNECT` request to `destination` over `stream`, a connection to an HTTP proxy.
+async fn http_connect(
+    mut stream: TcpStream,
+    destination: &SocksSocketAddr,
+    user: Option<&User>,
+) -> crate::Result<TcpStream> {
+    const PROXY_AUTHORIZATION: &[u8] = b"Proxy-Authorization: Basic ";
+
+    let authority = format_authority(destination);
+    let request_head = format!(
+        "CONNECT {authority} HTTP/1.1\r\nHost: {authority}\r\n",
+        authority = authority
+    );
+    let credentials = user.map(|user| {
+        let password = user.password.expose_secret().as_bytes();
+        let mut credentials =
+            Zeroizing::new(Vec::with_capacity(user.username.len() + 1 + password.len()));
+        credentials.extend_from_slice(user.username.as_bytes());
+        credentials.push(b':');
+        credentials.extend_from_slice(password);
+        Zeroizing::new(base64_encode(&credentials))
+    });
+
+    // The request holds the credentials of the user, it's sized up front since growing it would
+    // leave copies of them in freed memory
+    let len = request_head.len()
+        + credentials.as_ref().map_or(0, |credentials| {
+            PROXY_AUTHORIZATION.len() + credentials.len() + 2
+        })
+        + 2;
+    let mut request = Zeroizing::new(Vec::with_capacity(len));
+    request.extend_from_slice(request_head.as_bytes());
+    if let Some(credentials) = &credentials {
+        request.extend_from_slice(PROXY_AUTHORIZATION);
+        request.extend_from_slice(credentials.as_bytes());
+        request.extend_from_slice(b"\r\n");
+    }
+    request.extend_from_slice(b"\r\n");
+    debug_assert_eq!(request.len(), len);
+    stream.write_all(&request).await?;
+
+    let response = read_response_header(&mut stream).await?;
+    let status = std::str::from_utf8(&response)
+        .ok()
+        .and_then(|response| response.lines().next())
+        .and_then(|status_line| {
+            let mut parts = status_line.split_whitespace();
+            parts
+                .next()
+                .filter(|version| version.starts_with("HTTP/1."))?;
+            parts.next()?.parse::<u16>().ok()
+        })
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "Invalid HTTP response"))?;
+
+    debug!("HTTP proxy answered with status {}", status);
+    match status {
+        200..=299 => Ok(stream),
+        403 | 407 => Err(Socks5Error::Socks5Error(
+            Reply::ConnectionNotAllowedByRuleset,
+        )),
+        502 => Err(Socks5Error::Socks5Error(Reply::HostUnreachable)),
+        504 => Err(Socks5Error::Socks5Error(Reply::TTLExpired)),
+        _ => Err(Socks5Error::Socks5Error(Reply::GeneralFailure)),
+    }
+}
+
+/// Reads the response header of an HTTP proxy, at most `MAX_HTTP_HEADER` bytes. The data is
+/// peeked before it's read, so the data of the tunnel that may follow the header stays in `stream`.
+async fn read_response_header(stream: &mut TcpStream) -> io::Result<Vec<u8>> {
+    let mut response = Vec::new();
+    let mut buf = vec![0; MAX_HTTP_HEADER];
+    loop {
+        let remaining = MAX_HTTP_HEADER - response.len();
+        if remaining == 0 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "HTTP response too long",
+            ));
+        }
+        let peeked = stream.peek(&mut buf[..remaining]).await?;
+        if peeked == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+
+        // The end of the header may straddle the previous read
+        let searched = response.len().saturating_sub(3);
+        let read = response.len();
+        response.extend_from_slice(&buf[..peeked]);
+        let end = response[searched..]
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .map(|position| searched + position + 4);
+
+        // Only the peeked bytes up to the end of the header are consumed
+        let consumed = end.map_or(peeked, |end| end - read);
+        stream.read_exact(&mut buf[..consumed]).await?;
+        if let Some(end) = end {
+            response.truncate(end);
+            return Ok(response);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use tokio::net::TcpListener;
+
+    use crate::auth::username_password_authenticator::Password;
+
+    use super::*;
+
+    /// Connects to a proxy that answers with `response`, returns the connection and the request.
+    async fn proxy(response: &'static [u8]) -> (TcpStream, tokio::task::JoinHandle<Vec<u8>>) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let proxy = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                request.push(stream.read_u8().await.unwrap());
+            }
+            stream.write_all(response).await.unwrap();
+            request
+        });
+        (TcpStream::connect(addr).await.unwrap(), proxy)
+    }
+
+    fn destination() -> SocksSocketAddr {
+        SocketAddr::from((Ipv4Addr::new(192, 0, 2, 1), 443)).into()
+    }
+
+    #[tokio::test]
+    async fn leaves_the_tunnel_data_in_the_stream() {
+        let (stream, proxy) = proxy(b"HTTP/1.1 200 OK\r\nVia: proxy\r\n\r\nhello").await;
+        let mut stream = http_connect(stream, &destination(), None).await.unwrap();
+
+        let mut tunnel = [0; 5];
+        stream.read_exact(&mut tunnel).await.unwrap();
+        assert_eq!(&tunnel, b"hello");
+        assert_eq!(
+            proxy.await.unwrap(),
+            b"CONNECT 192.0.2.1:443 HTTP/1.1\r\nHost: 192.0.2.1:443\r\n\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn sends_the_credentials() {
+        let user = User {
+            username: "user".to_owned(),
+            password: Password::new("pass"),
+        };
+        let (stream, proxy) = proxy(b"HTTP/1.1 200 OK\r\n\r\n").await;
+        http_connect(stream, &destination(), Some(&user))
+            .await
+            .unwrap();
+
+        let request = String::from_utf8(proxy.await.unwrap()).unwrap();
+        assert!(request.ends_with("\r\nProxy-Authorization: Basic dXNlcjpwYXNz\r\n\r\n"));
+    }
+
+    #[tokio::test]
+    async fn maps_the_status_to_a_reply() {
+        let (stream, _proxy) = proxy(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n").await;
+        assert!(matches!(
+            http_connect(stream, &destination(), None).await,
+            Err(Socks5Error::Socks5Error(
+                Reply::ConnectionNotAllowedByRuleset
+            ))
+        ));
+
+        let (stream, _proxy) = proxy(b"SSH-2.0-OpenSSH\r\n\r\n").await;
+        assert!(http_connect(stream, &destination(), None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_headers_over_the_limit() {
+        let mut response = b"HTTP/1.1 200 OK\r\nX-Padding: ".to_vec();
+        response.resize(MAX_HTTP_HEADER + 1, b'a');
+        response.extend_from_slice(b"\r\n\r\n");
+        let (mut stream, _proxy) = proxy(response.leak()).await;
+        stream.write_all(b"\r\n\r\n").await.unwrap();
+        let err = read_response_header(&mut stream).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -16,6 +16,7 @@ where
         &mut self,
         _: crate::protocol::SocksSocketAddr,
         _: C,
+        _: &crate::context::SessionContext,
     ) -> crate::Result<Self::ServerConnection> {
         Err(Socks5Error::Socks5Error(Reply::CommandNotSupported))
     }
@@ -0,0 +1,102 @@
+use std::{io, net::SocketAddr, sync::Arc};
+
+use tokio::net::TcpStream;
+
+use crate::{
+    context::SessionContext,
+    method_handlers::AddressGuard,
+    protocol::SocksSocketAddr,
+    resolver::{Resolver, SystemResolver},
+};
+
+use super::{strategy::ConnectStrategy, Connect};
+
+/// The `GuardedConnect` struct is an implementation of the `Connect` trait that handles TCP CONNECT
+/// requests like `TunnelConnect`, but only to addresses allowed by an `AddressGuard`.
+///
+/// Domain names are resolved exactly once and the connection is made to the vetted addresses,
+/// so a DNS response changing between the check and the connection can't bypass the guard.
+///
+/// `GuardedConnect::default()` uses `AddressGuard::default()`, which forbids private, loopback
+/// and link-local destinations.
+pub struct GuardedConnect<R = SystemResolver> {
+    guard: Arc<AddressGuard>,
+    resolver: R,
+    strategy: ConnectStrategy,
+}
+
+impl GuardedConnect {
+    /// Creates a new `GuardedConnect` checking destinations with `guard`.
+    pub fn new(guard: Arc<AddressGuard>) -> Self {
+        Self {
+            guard,
+            resolver: SystemResolver,
+            strategy: ConnectStrategy::default(),
+        }
+    }
+}
+
+impl Default for GuardedConnect {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl<R> GuardedConnect<R> {
+    /// Sets the resolver used to resolve the destination domain names.
+    pub fn with_resolver<R2>(self, resolver: R2) -> GuardedConnect<R2>
+    where
+        R2: Resolver,
+    {
+        GuardedConnect {
+            guard: self.guard,
+            resolver,
+            strategy: self.strategy,
+        }
+    }
+
+    /// Sets how the connection is established when the destination has several addresses.
+    pub fn with_strategy(mut self, strategy: ConnectStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+}
+
+impl<C, R> Connect<C> for GuardedConnect<R>
+where
+    C: Send,
+    R: Resolver,
+{
+    type ServerConnection = TcpStream;
+
+    async fn establish_connection(
+        &mut self,
+        addr: SocksSocketAddr,
+        _credentials: C,
+        _context: &SessionContext,
+    ) -> crate::Result<TcpStream> {
+        let addrs = self.guard.vet(&addr, &self.resolver).await?;
+        let res = self.strategy.connect(&addrs).await?;
+        Ok(res)
+    }
+
+    fn reply_address(&self, connection: &TcpStream) -> Option<SocketAddr> {
+        connection.peer_addr().ok()
+    }
+
+    async fn start_listening<T>(self, mut client: T, mut server: TcpStream) -> crate::Result<()>
+    where
+        T: tokio::io::AsyncWrite + tokio::io::AsyncRead + Send + Unpin,
+    {
+        let res = tokio::io::copy_bidirectional(&mut client, &mut server)
+            .await
+            .map(|_| ());
+        if let Err(err) = &res {
+            if matches!(err.kind(), io::ErrorKind::NotConnected) {
+                return Ok(());
+            }
+        }
+        res?;
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+use std::{
+    io::{self, ErrorKind},
+    net::SocketAddr,
+    time::Duration,
+};
+
+use tokio::{net::TcpStream, task::JoinSet};
+use tracing::debug;
+
+/// The default delay between connection attempts, as recommended by RFC 8305.
+const DEFAULT_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// The way a TCP connection is established when a destination has several addresses.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectStrategy {
+    /// Tries the addresses one after the other in the order they were resolved, every attempt
+    /// waits for the previous one to fail.
+    #[default]
+    Sequential,
+    /// Races the addresses as described by `HappyEyeballs`.
+    HappyEyeballs(HappyEyeballs),
+}
+
+impl ConnectStrategy {
+    /// Connects to one of `addrs` according to the strategy.
+    pub async fn connect(&self, addrs: &[SocketAddr]) -> io::Result<TcpStream> {
+        match self {
+            ConnectStrategy::Sequential => TcpStream::connect(addrs).await,
+            ConnectStrategy::HappyEyeballs(happy_eyeballs) => happy_eyeballs.connect(addrs).await,
+        }
+    }
+}
+
+/// The `HappyEyeballs` struct implements the connection racing of RFC 8305.
+///
+/// The addresses are interleaved by family, starting with IPv6, and a new attempt is started
+/// every `attempt_delay` or as soon as the previous attempt fails, without waiting for earlier
+/// attempts to finish. The first attempt to succeed wins and all others are cancelled, so an
+/// unreachable address only costs `attempt_delay` instead of a full connect timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HappyEyeballs {
+    attempt_delay: Duration,
+    prefer_ipv6: bool,
+}
+
+impl Default for HappyEyeballs {
+    fn default() -> Self {
+        Self {
+            attempt_delay: DEFAULT_ATTEMPT_DELAY,
+            prefer_ipv6: true,
+        }
+    }
+}
+
+impl HappyEyeballs {
+    /// Creates a new `HappyEyeballs` with a 250ms attempt delay, preferring IPv6.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the delay before starting the next connection attempt.
+    pub fn with_attempt_delay(mut self, attempt_delay: Duration) -> Self {
+        self.attempt_delay = attempt_delay;
+        self
+    }
+
+    /// Sets whether the first attempt is made to an IPv6 address, defaults to `true`.
+    pub fn with_prefer_ipv6(mut self, prefer_ipv6: bool) -> Self {
+        self.prefer_ipv6 = prefer_ipv6;
+        self
+    }
+
+    /// Races connection attempts to `addrs` and returns the first established connection.
+    pub async fn connect(&self, addrs: &[SocketAddr]) -> io::Result<TcpStream> {
+        let mut pending = self.order(addrs).into_iter();
+        // Dropping the set aborts the attempts that lost the race
+        let mut attempts = JoinSet::new();
+        let mut last_err = None;
+        let mut start_next = true;
+
+        loop {
+            if start_next {
+                if let Some(addr) = pending.next() {
+                    debug!("Attempting connection to {}", addr);
+                    attempts.spawn(async move { (addr, TcpStream::connect(addr).await) });
+                }
+            }
+
+            if attempts.is_empty() {
+                return Err(last_err.unwrap_or_else(|| {
+                    io::Error::new(ErrorKind::InvalidInput, "No addresses to connect to")
+                }));
+            }
+
+            // A failed attempt starts the next one right away instead of waiting for the delay
+            start_next = tokio::select! {
+                Some(res) = attempts.join_next() => match res {
+                    Ok((addr, Ok(stream))) => {
+                        debug!("Connection to {} won the race", addr);
+                        return Ok(stream);
+                    }
+                    Ok((addr, Err(err))) => {
+                        debug!("Connection to {} failed: {}", addr, err);
+                        last_err = Some(err);
+                        true
+                    }
+                    Err(err) => {
+                        last_err = Some(io::Error::other(err));
+                        true
+                    }
+                },
+                _ = tokio::time::sleep(self.attempt_delay), if pending.len() > 0 => true,
+            };
+        }
+    }
+
+    /// Interleaves the addresses by family, keeping the resolver order within each family.
+    fn order(&self, addrs: &[SocketAddr]) -> Vec<SocketAddr> {
+        let (preferred, other): (Vec<_>, Vec<_>) = addrs
+            .iter()
+            .partition(|addr| addr.is_ipv6() == self.prefer_ipv6);
+
+        let mut ordered = Vec::with_capacity(addrs.len());
+        let mut other = other.into_iter();
+        for addr in preferred {
+            ordered.push(addr);
+            ordered.extend(other.next());
+        }
+        ordered.extend(other);
+        ordered
+    }
+}
@@ -1,10 +1,14 @@
-use std::io;
+use std::{io, net::SocketAddr};
 
 use tokio::net::TcpStream;
 
-use crate::protocol::SocksSocketAddr;
+use crate::{
+    context::SessionContext,
+    protocol::SocksSocketAddr,
+    resolver::{Resolver, SystemResolver},
+};
 
-use super::Connect;
+use super::{strategy::ConnectStrategy, Connect};
 
 /// The `TunnelConnect` struct is an implementation of the `Connect` trait that handles
 /// TCP CONNECT requests by establishing a TCP connection and relaying data between the client
@@ -15,20 +19,70 @@ use super::Connect;
 ///
 /// This struct can be used in scenarios where basic TCP traffic needs to be tunneled through
 /// a SOCKS5 proxy server without any special handling or configuration.
-pub struct TunnelConnect;
+pub struct TunnelConnect<R = SystemResolver> {
+    resolver: R,
+    strategy: ConnectStrategy,
+}
+
+impl TunnelConnect {
+    /// Creates a new `TunnelConnect` resolving domain names with the `SystemResolver`.
+    pub fn new() -> Self {
+        Self {
+            resolver: SystemResolver,
+            strategy: ConnectStrategy::default(),
+        }
+    }
+}
+
+impl Default for TunnelConnect {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R> TunnelConnect<R> {
+    /// Sets the resolver used to resolve the destination domain names.
+    pub fn with_resolver<R2>(self, resolver: R2) -> TunnelConnect<R2>
+    where
+        R2: Resolver,
+    {
+        TunnelConnect {
+            resolver,
+            strategy: self.strategy,
+        }
+    }
 
-impl Connect<()> for TunnelConnect {
+    /// Sets how the connection is established when the destination has several addresses.
+    pub fn with_strategy(mut self, strategy: ConnectStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+}
+
+impl<C, R> Connect<C> for TunnelConnect<R>
+where
+    C: Send,
+    R: Resolver,
+{
     type ServerConnection = TcpStream;
 
     async fn establish_connection(
         &mut self,
         addr: SocksSocketAddr,
-        _credentials: (),
+        _credentials: C,
+        _context: &SessionContext,
     ) -> crate::Result<TcpStream> {
-        let res = TcpStream::connect(&*addr.to_socket_addr().await?).await?;
+        let res = self
+            .strategy
+            .connect(&addr.resolve(&self.resolver).await?)
+            .await?;
         Ok(res)
     }
 
+    fn reply_address(&self, connection: &TcpStream) -> Option<SocketAddr> {
+        connection.peer_addr().ok()
+    }
+
     async fn start_listening<T>(self, mut client: T, mut server: TcpStream) -> crate::Result<()>
     where
         T: tokio::io::AsyncWrite + tokio::io::AsyncRead + Send + Unpin,
@@ -0,0 +1,462 @@
+//! # Metrics Module
+//!
+//! This module provides `Metrics`, a set of counters and histograms describing the sessions of a
+//! proxy, rendered in the Prometheus text exposition format. A `Metrics` instance is shared with
+//! an `Arc` by the sessions it's given to with `Socks5Server::with_metrics` or
+//! `Socks5Socket::with_metrics`, and DNS latency is recorded by wrapping a resolver in a
+//! `MeteredResolver`.
+//!
+//! `Metrics::serve` optionally answers `GET /metrics` on a listener of its own, alternatively
+//! `Metrics::render` returns the exposition to be served by an existing HTTP server.
+//!
+//! ## Example
+//!
+//! ```rust,no_run
+//! use std::sync::Arc;
+//!
+//! use gerevs::{
+//!     auth::NoAuthAuthenticator,
+//!     method_handlers::{TunnelAssociate, TunnelBind, TunnelConnect},
+//!     metrics::{MeteredResolver, Metrics},
+//!     resolver::SystemResolver,
+//!     Socks5Server,
+//! };
+//! use tokio::net::TcpListener;
+//!
+//! # async fn run() -> gerevs::Result<()> {
+//! let metrics = Arc::new(Metrics::new());
+//! tokio::spawn(
+//!     metrics
+//!         .clone()
+//!         .serve(TcpListener::bind("127.0.0.1:9100").await?),
+//! );
+//!
+//! let resolver = MeteredResolver::new(SystemResolver, metrics.clone());
+//! let listener = TcpListener::bind("0.0.0.0:1080").await?;
+//! let server = Socks5Server::new(
+//!     listener,
+//!     || NoAuthAuthenticator,
+//!     move || TunnelConnect::new().with_resolver(resolver.clone()),
+//!     TunnelBind::new,
+//!     TunnelAssociate::new,
+//! )
+//! .with_metrics(metrics);
+//! server.run().await
+//! # }
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    io::{self, ErrorKind},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::Instant,
+};
+use tracing::{debug, warn};
+
+use crate::{
+    protocol::{http::MAX_HTTP_HEADER, AuthMethod, Command, Reply},
+    resolver::Resolver,
+    server::AcceptBackoff,
+};
+
+/// The upper bounds of the latency histogram buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Deadline for a scrape request to be received.
+const SCRAPE_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Default)]
+struct LabeledCounter(Mutex<BTreeMap<&'static str, u64>>);
+
+impl LabeledCounter {
+    fn inc(&self, label: &'static str) {
+        let mut values = self.0.lock().unwrap_or_else(|err| err.into_inner());
+        *values.entry(label).or_default() += 1;
+    }
+
+    fn values(&self) -> BTreeMap<&'static str, u64> {
+        self.0.lock().unwrap_or_else(|err| err.into_inner()).clone()
+    }
+}
+
+struct Histogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Self {
+            buckets: Default::default(),
+            count: AtomicU64::new(0),
+            sum_nanos: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|&le| seconds <= le) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+/// The `Metrics` struct holds the counters and histograms of a proxy. See the module
+/// documentation for how it's wired.
+pub struct Metrics {
+    connections: AtomicU64,
+    active_sessions: AtomicI64,
+    handshake_failures: LabeledCounter,
+    auth_methods: LabeledCounter,
+    commands: LabeledCounter,
+    replies: LabeledCounter,
+    bytes_up: AtomicU64,
+    bytes_down: AtomicU64,
+    udp_truncated: AtomicU64,
+    connect_duration: Histogram,
+    dns_duration: Histogram,
+}
+
+impl Metrics {
+    /// Creates a new `Metrics` with every counter at zero.
+    pub fn new() -> Self {
+        Self {
+            connections: AtomicU64::new(0),
+            active_sessions: AtomicI64::new(0),
+            handshake_failures: LabeledCounter::default(),
+            auth_methods: LabeledCounter::default(),
+            commands: LabeledCounter::default(),
+            replies: LabeledCounter::default(),
+            bytes_up: AtomicU64::new(0),
+            bytes_down: AtomicU64::new(0),
+            udp_truncated: AtomicU64::new(0),
+            connect_duration: Histogram::new(),
+            dns_duration: Histogram::new(),
+        }
+    }
+
+    /// Records a new session, the returned guard marks it as active until dropped.
+    pub(crate) fn session_started(self: &Arc<Self>) -> ActiveSession {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        self.active_sessions.fetch_add(1, Ordering::Relaxed);
+        ActiveSession(self.clone())
+    }
+
+    pub(crate) fn handshake_failed(&self, err: &io::Error) {
+        let cause = match err.kind() {
+            ErrorKind::TimedOut => "timeout",
+            ErrorKind::PermissionDenied => "auth",
+            ErrorKind::InvalidData | ErrorKind::InvalidInput => "protocol",
+            ErrorKind::UnexpectedEof => "closed",
+            _ => "io",
+        };
+        self.handshake_failures.inc(cause);
+    }
+
+    pub(crate) fn auth_method_selected(&self, method: AuthMethod) {
+        self.auth_methods.inc(match method {
+            AuthMethod::NoAuthRequired => "no_auth",
+            AuthMethod::Gssapi => "gssapi",
+            AuthMethod::UsernamePassword => "username_password",
+            AuthMethod::IanaAssigned(_) => "iana_assigned",
+            AuthMethod::PrivateMethods(_) => "private",
+            AuthMethod::NoAcceptableMethods => "no_acceptable",
+        });
+    }
+
+    pub(crate) fn command(&self, command: Command) {
+        self.commands.inc(match command {
+            Command::Connect => "connect",
+            Command::Bind => "bind",
+            Command::UdpAssociate => "udp_associate",
+        });
+    }
+
+    pub(crate) fn reply_sent(&self, reply: Reply) {
+        self.replies.inc(match reply {
+            Reply::Success => "success",
+            Reply::GeneralFailure => "general_failure",
+            Reply::ConnectionNotAllowedByRuleset => "not_allowed_by_ruleset",
+            Reply::NetworkUnreachable => "network_unreachable",
+            Reply::HostUnreachable => "host_unreachable",
+            Reply::ConnectionRefused => "connection_refused",
+            Reply::TTLExpired => "ttl_expired",
+            Reply::CommandNotSupported => "command_not_supported",
+            Reply::AddressTypeNotSupported => "address_type_not_supported",
+        });
+    }
+
+    pub(crate) fn relayed_up(&self, bytes: usize) {
+        self.bytes_up.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn relayed_down(&self, bytes: usize) {
+        self.bytes_down.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn udp_truncated(&self) {
+        self.udp_truncated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn connected(&self, duration: Duration) {
+        self.connect_duration.observe(duration);
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        render_counter(
+            &mut out,
+            "gerevs_connections_total",
+            "Client connections served.",
+            self.connections.load(Ordering::Relaxed),
+        );
+        render_header(
+            &mut out,
+            "gerevs_active_sessions",
+            "Sessions currently running.",
+            "gauge",
+        );
+        let _ = writeln!(
+            out,
+            "gerevs_active_sessions {}",
+            self.active_sessions.load(Ordering::Relaxed)
+        );
+        render_labeled(
+            &mut out,
+            "gerevs_handshake_failures_total",
+            "Handshakes that failed, by cause.",
+            "cause",
+            &self.handshake_failures,
+        );
+        render_labeled(
+            &mut out,
+            "gerevs_auth_methods_total",
+            "Authentication methods selected for SOCKS5 clients.",
+            "method",
+            &self.auth_methods,
+        );
+        render_labeled(
+            &mut out,
+            "gerevs_commands_total",
+            "Requests received, by command.",
+            "command",
+            &self.commands,
+        );
+        render_labeled(
+            &mut out,
+            "gerevs_replies_total",
+            "Replies sent, by reply code.",
+            "reply",
+            &self.replies,
+        );
+        render_header(
+            &mut out,
+            "gerevs_relayed_bytes_total",
+            "Bytes relayed, up is data sent by clients and down is data sent to clients.",
+            "counter",
+        );
+        let _ = writeln!(
+            out,
+            "gerevs_relayed_bytes_total{{direction=\"up\"}} {}",
+            self.bytes_up.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "gerevs_relayed_bytes_total{{direction=\"down\"}} {}",
+            self.bytes_down.load(Ordering::Relaxed)
+        );
+        render_counter(
+            &mut out,
+            "gerevs_udp_truncated_datagrams_total",
+            "UDP datagrams dropped for being larger than the receive buffer or a datagram once wrapped.",
+            self.udp_truncated.load(Ordering::Relaxed),
+        );
+        render_histogram(
+            &mut out,
+            "gerevs_connect_duration_seconds",
+            "Time taken to establish CONNECT connections.",
+            &self.connect_duration,
+        );
+        render_histogram(
+            &mut out,
+            "gerevs_dns_duration_seconds",
+            "Time taken by DNS resolutions of a MeteredResolver.",
+            &self.dns_duration,
+        );
+
+        out
+    }
+
+    /// Serves the metrics over HTTP, answering `GET /metrics` with `render` and every other
+    /// request with `404 Not Found`. Errors while accepting a connection are logged, and the next
+    /// accept is delayed.
+    ///
+    /// # Returns
+    ///
+    /// A future that never resolves.
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> io::Result<()> {
+        let mut backoff = AcceptBackoff::default();
+        loop {
+            if backoff.is_paused() {
+                backoff.resumed().await;
+            }
+            let (stream, addr) = match listener.accept().await {
+                Ok(accepted) => {
+                    backoff.reset();
+                    accepted
+                }
+                Err(err) => {
+                    warn!("Failed accepting metrics connection: {}", err);
+                    backoff.failed();
+                    continue;
+                }
+            };
+
+            let metrics = self.clone();
+            tokio::spawn(async move {
+                if let Err(err) = metrics.answer_scrape(stream).await {
+                    debug!("Failed serving metrics to {}: {}", addr, err);
+                }
+            });
+        }
+    }
+
+    async fn answer_scrape(&self, mut stream: TcpStream) -> io::Result<()> {
+        let mut request = Vec::new();
+        let mut chunk = [0; 1024];
+        let read_header = async {
+            while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                if request.len() > MAX_HTTP_HEADER {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        "HTTP request header too long",
+                    ));
+                }
+                let n = stream.read(&mut chunk).await?;
+                if n == 0 {
+                    return Err(ErrorKind::UnexpectedEof.into());
+                }
+                request.extend_from_slice(&chunk[..n]);
+            }
+            Ok(())
+        };
+        tokio::time::timeout(SCRAPE_REQUEST_TIMEOUT, read_header)
+            .await
+            .map_err(|_| io::Error::from(ErrorKind::TimedOut))??;
+
+        let mut request_line = request
+            .split(|&byte| byte == b'\r')
+            .next()
+            .unwrap_or_default()
+            .split(|&byte| byte == b' ');
+        let response = match (request_line.next(), request_line.next()) {
+            (Some(b"GET"), Some(b"/metrics")) => {
+                let body = self.render();
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+            }
+            _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                .to_string(),
+        };
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Marks a session as active until dropped.
+pub(crate) struct ActiveSession(Arc<Metrics>);
+
+impl Drop for ActiveSession {
+    fn drop(&mut self) {
+        self.0.active_sessions.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn render_header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn render_counter(out: &mut String, name: &str, help: &str, value: u64) {
+    render_header(out, name, help, "counter");
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn render_labeled(out: &mut String, name: &str, help: &str, label: &str, counter: &LabeledCounter) {
+    render_header(out, name, help, "counter");
+    for (value, count) in counter.values() {
+        let _ = writeln!(out, "{}{{{}=\"{}\"}} {}", name, label, value, count);
+    }
+}
+
+fn render_histogram(out: &mut String, name: &str, help: &str, histogram: &Histogram) {
+    render_header(out, name, help, "histogram");
+    let mut cumulative = 0;
+    for (le, bucket) in LATENCY_BUCKETS.iter().zip(&histogram.buckets) {
+        cumulative += bucket.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, le, cumulative);
+    }
+    let count = histogram.count.load(Ordering::Relaxed);
+    let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+    let sum = Duration::from_nanos(histogram.sum_nanos.load(Ordering::Relaxed));
+    let _ = writeln!(out, "{}_sum {}", name, sum.as_secs_f64());
+    let _ = writeln!(out, "{}_count {}", name, count);
+}
+
+/// The `MeteredResolver` struct wraps a `Resolver` and records the latency of its resolutions in
+/// the DNS latency histogram of a `Metrics`.
+#[derive(Clone)]
+pub struct MeteredResolver<R> {
+    inner: R,
+    metrics: Arc<Metrics>,
+}
+
+impl<R> MeteredResolver<R> {
+    /// Creates a new `MeteredResolver`.
+    ///
+    /// - `inner`: The resolver performing the resolutions.
+    /// - `metrics`: The metrics the latency is recorded in.
+    pub fn new(inner: R, metrics: Arc<Metrics>) -> Self {
+        Self { inner, metrics }
+    }
+}
+
+impl<R> Resolver for MeteredResolver<R>
+where
+    R: Resolver,
+{
+    async fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        let started = Instant::now();
+        let res = self.inner.resolve(host, port).await;
+        self.metrics.dns_duration.observe(started.elapsed());
+        res
+    }
+}
@@ -0,0 +1,149 @@
+//! # Observer Module
+//!
+//! This module provides the `SessionObserver` trait, notified of the events in the lifecycle of
+//! every session: the accepted connection, the authentication, the parsed request, the replies
+//! and the end of the relay. Observers are shared with an `Arc` by the sessions they're given to
+//! with `Socks5Server::with_observer` or `Socks5Socket::with_observer`, and the events of a
+//! session carry its `SessionId` so they can be correlated.
+//!
+//! The callbacks are called inline by the session, so they should return quickly. Observers that
+//! need to perform I/O should hand the events to a task of their own, e.g. through a channel.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use std::io;
+//!
+//! use gerevs::observer::{SessionId, SessionObserver};
+//!
+//! struct AuthAlerts;
+//!
+//! impl SessionObserver for AuthAlerts {
+//!     fn auth_failed(&self, session: SessionId, error: &io::Error) {
+//!         eprintln!("Session {} failed to authenticate: {}", session, error);
+//!     }
+//! }
+//! ```
+
+use std::{
+    fmt::{self, Display},
+    io,
+    net::SocketAddr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::Socks5Error;
+
+pub use crate::protocol::{AuthMethod, Command, Reply, SocksSocketAddr};
+
+/// Identifies a session, unique within the process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SessionId(u64);
+
+impl SessionId {
+    pub(crate) fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Returns the id as a number.
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+impl Display for SessionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// The direction a UDP datagram was forwarded in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// From the client to the destination it addressed.
+    ToDestination,
+    /// From a remote peer back to the client.
+    ToClient,
+}
+
+/// The traffic of a finished relay. For UDP associations the bytes count datagram payloads.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RelayStats {
+    /// Bytes sent by the client.
+    pub bytes_up: u64,
+    /// Bytes sent to the client.
+    pub bytes_down: u64,
+    /// Time from the start of the relay until it finished.
+    pub duration: Duration,
+}
+
+/// The `SessionObserver` trait is notified of the events of sessions. Every callback has an empty
+/// default implementation, so observers only implement the events they're interested in.
+pub trait SessionObserver: Send + Sync {
+    /// A session started for a new client connection.
+    ///
+    /// - `peer_addr`: The address of the client, when known by the session.
+    fn connection_accepted(&self, _session: SessionId, _peer_addr: Option<SocketAddr>) {}
+
+    /// The authenticator selected `method` among those offered by a SOCKS5 client.
+    fn method_selected(&self, _session: SessionId, _method: AuthMethod) {}
+
+    /// The client authenticated successfully.
+    ///
+    /// - `user`: The name the client authenticated as, see `Authenticator::user_name`.
+    fn auth_succeeded(&self, _session: SessionId, _user: Option<&str>) {}
+
+    /// The client failed to authenticate, either because its credentials were rejected
+    /// (`ErrorKind::PermissionDenied`) or because of an error during the authentication.
+    fn auth_failed(&self, _session: SessionId, _error: &io::Error) {}
+
+    /// The request of an authenticated client was parsed.
+    fn request_parsed(
+        &self,
+        _session: SessionId,
+        _command: Command,
+        _destination: &SocksSocketAddr,
+    ) {
+    }
+
+    /// The connection of a CONNECT request was established with `destination`, the resolved
+    /// address of the requested destination. Only reported when the connect handler knows the
+    /// address, see `Connect::reply_address`.
+    fn destination_connected(&self, _session: SessionId, _destination: SocketAddr) {}
+
+    /// A reply was sent to the client. BIND sessions send a second reply once a peer connected.
+    fn reply_sent(&self, _session: SessionId, _reply: Reply, _bound_addr: &SocksSocketAddr) {}
+
+    /// The relay of a CONNECT, BIND or UDP ASSOCIATE session finished.
+    ///
+    /// - `error`: The error that ended the relay, `None` if it finished cleanly.
+    fn relay_finished(
+        &self,
+        _session: SessionId,
+        _stats: &RelayStats,
+        _error: Option<&Socks5Error>,
+    ) {
+    }
+
+    /// A UDP datagram was forwarded.
+    ///
+    /// - `peer`: The destination of the datagram, or its source when forwarded to the client.
+    /// - `bytes`: The size of the payload.
+    fn udp_datagram_forwarded(
+        &self,
+        _session: SessionId,
+        _direction: Direction,
+        _peer: &SocksSocketAddr,
+        _bytes: usize,
+    ) {
+    }
+
+    /// The session ended, this is the last event of every session. It's notified exactly once per
+    /// session, also when the task running the session is aborted or panics.
+    ///
+    /// - `error`: The error that ended the session, `None` if it finished cleanly. Aborted sessions
+    ///   end with a `ConnectionAborted` error.
+    fn connection_closed(&self, _session: SessionId, _error: Option<&Socks5Error>) {}
+}
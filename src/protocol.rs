@@ -1,12 +1,18 @@
 mod addr;
 mod command;
+pub(crate) mod gssapi;
+pub(crate) mod http;
 mod methods;
 mod reply;
+mod socks4;
+mod udp_message;
 
-pub use addr::SocksSocketAddr;
+pub use addr::{Addr, SocksSocketAddr};
 pub use command::Command;
 pub use methods::AuthMethod;
 pub use reply::Reply;
+pub use socks4::{Socks4Reply, SOCKS4_MAX_FIELD_LEN, SOCKS4_REPLY_VERSION, SOCKS4_VERSION};
+pub(crate) use udp_message::UdpMessage;
 
 pub const VERSION: u8 = 0x05;
 pub const RESERVED: u8 = 0x00;
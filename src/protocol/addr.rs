@@ -1,12 +1,14 @@
 use std::{
     fmt::{self, Display},
     io::{self, ErrorKind},
-    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, ToSocketAddrs},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
     ops::Deref,
 };
 
 use tokio::io::{AsyncRead, AsyncReadExt};
 
+use crate::resolver::{Resolver, SystemResolver};
+
 #[derive(Debug, Clone, Copy)]
 pub enum AddressType {
     Ipv4 = 0x01,
@@ -28,7 +30,7 @@ impl AddressType {
         self as u8
     }
 }
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SocksSocketAddr {
     pub port: u16,
     pub addr: Addr,
@@ -51,21 +53,21 @@ impl Default for SocksSocketAddr {
 
 impl SocksSocketAddr {
     pub async fn to_socket_addr(&self) -> io::Result<impl Deref<Target = [SocketAddr]>> {
+        self.resolve(&SystemResolver).await
+    }
+
+    /// Resolves the address with `resolver`, IP addresses are returned without resolving.
+    pub async fn resolve<R>(&self, resolver: &R) -> io::Result<Vec<SocketAddr>>
+    where
+        R: Resolver,
+    {
         match self.addr {
             Addr::Ipv4(addrv4) => Ok(vec![SocketAddrV4::new(addrv4, self.port).into()]),
             Addr::Ipv6(addrv6) => Ok(vec![SocketAddrV6::new(addrv6, self.port, 0, 0).into()]),
-            Addr::Domain(ref domain) => {
-                let domain = format!("{}:{}", domain, self.port);
-
-                Ok(
-                    tokio::task::spawn_blocking(move || domain.to_socket_addrs())
-                        .await
-                        .expect("Task isn't aborted")?
-                        .collect(),
-                )
-            }
+            Addr::Domain(ref domain) => resolver.resolve(domain, self.port).await,
         }
     }
+
     pub async fn read<T>(stream: &mut T) -> io::Result<Self>
     where
         T: AsyncRead + Unpin,
@@ -138,7 +140,7 @@ impl From<SocketAddr> for SocksSocketAddr {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Addr {
     Ipv4(Ipv4Addr),
     Ipv6(Ipv6Addr),
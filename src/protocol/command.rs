@@ -15,4 +15,8 @@ impl Command {
             _ => None,
         }
     }
+
+    pub fn to_u8(self) -> u8 {
+        self as u8
+    }
 }
@@ -0,0 +1,112 @@
+use std::io::{self, ErrorKind};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// The version byte of the GSSAPI method messages, defined in
+/// [RFC 1961](https://datatracker.ietf.org/doc/html/rfc1961).
+pub const GSSAPI_VERSION: u8 = 0x01;
+/// The length of the header of a message: VER+MTYP+LEN.
+pub const GSSAPI_HEADER_LEN: usize = 4;
+/// The largest token a message can hold.
+pub const GSSAPI_MAX_TOKEN_LEN: usize = u16::MAX as usize;
+
+/// The message types of the GSSAPI method.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+    /// A context establishment token.
+    Authentication = 0x01,
+    /// A protection level, wrapped by the security context.
+    Protection = 0x02,
+    /// Session data, wrapped by the security context.
+    Encapsulation = 0x03,
+    /// Sent instead of a token when the context establishment failed.
+    Abort = 0xFF,
+}
+
+impl MessageType {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0x01 => Some(MessageType::Authentication),
+            0x02 => Some(MessageType::Protection),
+            0x03 => Some(MessageType::Encapsulation),
+            0xFF => Some(MessageType::Abort),
+            _ => None,
+        }
+    }
+
+    pub fn to_u8(self) -> u8 {
+        self as u8
+    }
+}
+
+/// Parses the version and type of a message.
+pub fn parse_message_type(version: u8, message_type: u8) -> io::Result<MessageType> {
+    if version != GSSAPI_VERSION {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "Invalid GSSAPI message version",
+        ));
+    }
+
+    MessageType::from_u8(message_type)
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "Invalid GSSAPI message type"))
+}
+
+/// Turns a message into bytes: VER+MTYP+LEN+TOKEN
+pub fn message_to_bytes(message_type: MessageType, token: &[u8]) -> io::Result<Vec<u8>> {
+    if token.len() > GSSAPI_MAX_TOKEN_LEN {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "GSSAPI token is too long",
+        ));
+    }
+
+    let mut bytes = Vec::with_capacity(GSSAPI_HEADER_LEN + token.len());
+    bytes.push(GSSAPI_VERSION);
+    bytes.push(message_type.to_u8());
+    bytes.extend_from_slice(&(token.len() as u16).to_be_bytes());
+    bytes.extend_from_slice(token);
+    Ok(bytes)
+}
+
+/// Reads a message, its type and token.
+pub async fn read_message<T>(conn: &mut T) -> io::Result<(MessageType, Vec<u8>)>
+where
+    T: AsyncRead + Unpin,
+{
+    let version = conn.read_u8().await?;
+    let message_type = parse_message_type(version, conn.read_u8().await?)?;
+
+    // An abort message has no length nor token
+    if message_type == MessageType::Abort {
+        return Ok((message_type, Vec::new()));
+    }
+
+    let len = conn.read_u16().await?;
+    let mut token = vec![0; len as usize];
+    conn.read_exact(&mut token).await?;
+    Ok((message_type, token))
+}
+
+/// Writes a message with `token`.
+pub async fn write_message<T>(
+    conn: &mut T,
+    message_type: MessageType,
+    token: &[u8],
+) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin,
+{
+    conn.write_all(&message_to_bytes(message_type, token)?)
+        .await
+}
+
+/// Writes an abort message, it's only made of the version and type.
+pub async fn write_abort<T>(conn: &mut T) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin,
+{
+    conn.write_all(&[GSSAPI_VERSION, MessageType::Abort.to_u8()])
+        .await
+}
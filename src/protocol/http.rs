@@ -0,0 +1,308 @@
+use std::{
+    io::{self, ErrorKind},
+    net::IpAddr,
+};
+
+use super::{Addr, Reply, SocksSocketAddr};
+
+/// The longest request or response header accepted.
+pub const MAX_HTTP_HEADER: usize = 8192;
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Returns whether `byte` can start an HTTP request, methods are made of letters.
+pub fn is_http_method_start(byte: u8) -> bool {
+    byte.is_ascii_uppercase()
+}
+
+/// Maps a SOCKS5 reply to the HTTP status a proxy answers with.
+pub fn http_status(reply: Reply) -> (u16, &'static str) {
+    match reply {
+        Reply::Success => (200, "Connection established"),
+        Reply::ConnectionNotAllowedByRuleset => (403, "Forbidden"),
+        Reply::CommandNotSupported => (405, "Method Not Allowed"),
+        Reply::AddressTypeNotSupported => (400, "Bad Request"),
+        Reply::TTLExpired => (504, "Gateway Timeout"),
+        Reply::GeneralFailure
+        | Reply::NetworkUnreachable
+        | Reply::HostUnreachable
+        | Reply::ConnectionRefused => (502, "Bad Gateway"),
+    }
+}
+
+/// Parses an authority (`host:port`), IPv6 addresses must be enclosed in brackets.
+pub fn parse_authority(authority: &str, default_port: Option<u16>) -> io::Result<SocksSocketAddr> {
+    let invalid = || {
+        io::Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid authority: {}", authority),
+        )
+    };
+
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(rest) => {
+            let (host, rest) = rest.split_once(']').ok_or_else(invalid)?;
+            (host, rest.strip_prefix(':'))
+        }
+        None => match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+
+    let port = match port {
+        Some(port) => port.parse().map_err(|_| invalid())?,
+        None => default_port.ok_or_else(invalid)?,
+    };
+
+    let addr = match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => Addr::Ipv4(ip),
+        Ok(IpAddr::V6(ip)) => Addr::Ipv6(ip),
+        Err(_) if !host.is_empty() && host.len() <= u8::MAX as usize => {
+            Addr::Domain(host.to_string())
+        }
+        Err(_) => return Err(invalid()),
+    };
+
+    Ok(SocksSocketAddr { port, addr })
+}
+
+/// Where the body of a forwarded request ends, the bytes the client sends after it aren't relayed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestBody {
+    /// The number of bytes left of a body with a `Content-Length`.
+    Length(u64),
+    /// A `Transfer-Encoding: chunked` body.
+    Chunked(Chunked),
+}
+
+/// The state of a chunked body, RFC 7230 section 4.1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chunked {
+    /// Reading the hexadecimal size of a chunk.
+    Size {
+        size: u64,
+        digits: u8,
+    },
+    /// Skipping the extensions of a chunk.
+    Extension {
+        size: u64,
+    },
+    SizeLf {
+        size: u64,
+    },
+    Data(u64),
+    DataCr,
+    DataLf,
+    /// At the start of a trailer line, the body ends with an empty line.
+    TrailerStart,
+    Trailer,
+    TrailerLf {
+        empty: bool,
+    },
+    Done,
+}
+
+impl RequestBody {
+    /// Starts a chunked body.
+    pub fn chunked() -> Self {
+        RequestBody::Chunked(Chunked::Size { size: 0, digits: 0 })
+    }
+
+    /// Consumes the body bytes at the start of `data`, a malformed chunked body ends at the first
+    /// invalid byte.
+    ///
+    /// - Returns: How many bytes of `data` belong to the body.
+    pub fn consume(&mut self, data: &[u8]) -> usize {
+        match self {
+            RequestBody::Length(remaining) => {
+                let len = data
+                    .len()
+                    .min(usize::try_from(*remaining).unwrap_or(usize::MAX));
+                *remaining -= len as u64;
+                len
+            }
+            RequestBody::Chunked(state) => {
+                let mut pos = 0;
+                while pos < data.len() {
+                    match *state {
+                        Chunked::Done => break,
+                        Chunked::Data(remaining) => {
+                            let len = (data.len() - pos)
+                                .min(usize::try_from(remaining).unwrap_or(usize::MAX));
+                            pos += len;
+                            *state = match remaining - len as u64 {
+                                0 => Chunked::DataCr,
+                                remaining => Chunked::Data(remaining),
+                            };
+                        }
+                        current => match current.next(data[pos]) {
+                            Some(next) => {
+                                *state = next;
+                                pos += 1;
+                            }
+                            None => {
+                                *state = Chunked::Done;
+                                break;
+                            }
+                        },
+                    }
+                }
+                pos
+            }
+        }
+    }
+}
+
+impl Chunked {
+    /// The state after `byte`, `None` if the body is malformed.
+    fn next(self, byte: u8) -> Option<Self> {
+        let next = match (self, byte) {
+            (Chunked::Size { size, digits }, byte) if byte.is_ascii_hexdigit() && digits < 15 => {
+                let digit = (byte as char).to_digit(16).expect("Hex digit") as u64;
+                Chunked::Size {
+                    size: size << 4 | digit,
+                    digits: digits + 1,
+                }
+            }
+            (Chunked::Size { size, digits }, b';') if digits > 0 => Chunked::Extension { size },
+            (Chunked::Size { size, digits }, b'\r') if digits > 0 => Chunked::SizeLf { size },
+            (Chunked::Extension { size }, b'\r') => Chunked::SizeLf { size },
+            (Chunked::Extension { .. }, b'\n') => return None,
+            (Chunked::Extension { size }, _) => Chunked::Extension { size },
+            (Chunked::SizeLf { size: 0 }, b'\n') => Chunked::TrailerStart,
+            (Chunked::SizeLf { size }, b'\n') => Chunked::Data(size),
+            (Chunked::DataCr, b'\r') => Chunked::DataLf,
+            (Chunked::DataLf, b'\n') => Chunked::Size { size: 0, digits: 0 },
+            (Chunked::TrailerStart, b'\r') => Chunked::TrailerLf { empty: true },
+            (Chunked::Trailer, b'\r') => Chunked::TrailerLf { empty: false },
+            (Chunked::TrailerStart | Chunked::Trailer, b'\n') => return None,
+            (Chunked::TrailerStart | Chunked::Trailer, _) => Chunked::Trailer,
+            (Chunked::TrailerLf { empty: true }, b'\n') => Chunked::Done,
+            (Chunked::TrailerLf { empty: false }, b'\n') => Chunked::TrailerStart,
+            _ => return None,
+        };
+        Some(next)
+    }
+}
+
+/// Formats `addr` as an authority, enclosing IPv6 addresses in brackets.
+pub fn format_authority(addr: &SocksSocketAddr) -> String {
+    match &addr.addr {
+        Addr::Ipv6(ip) => format!("[{}]:{}", ip, addr.port),
+        _ => addr.to_string(),
+    }
+}
+
+pub fn base64_encode(input: &[u8]) -> String {
+    let mut output = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let triple = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                output.push(BASE64_ALPHABET[(triple >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
+
+pub fn base64_decode(input: &str) -> Option<Vec<u8>> {
+    let input = input.trim_end_matches('=').as_bytes();
+    if input.len() % 4 == 1 {
+        return None;
+    }
+
+    let mut output = Vec::with_capacity(input.len() * 3 / 4);
+    for chunk in input.chunks(4) {
+        let mut triple = 0u32;
+        for (i, &c) in chunk.iter().enumerate() {
+            let value = BASE64_ALPHABET.iter().position(|&b| b == c)? as u32;
+            triple |= value << (18 - 6 * i);
+        }
+        let bytes = triple.to_be_bytes();
+        output.extend_from_slice(&bytes[1..chunk.len()]);
+    }
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+
+    use super::*;
+
+    #[test]
+    fn base64_round_trip() {
+        for input in [&b""[..], b"a", b"ab", b"abc", b"user:pass\xff"] {
+            assert_eq!(base64_decode(&base64_encode(input)).unwrap(), input);
+        }
+        assert_eq!(base64_encode(b"alice:secret"), "YWxpY2U6c2VjcmV0");
+        assert_eq!(base64_decode("YWxpY2U6c2VjcmV0").unwrap(), b"alice:secret");
+    }
+
+    #[test]
+    fn base64_rejects_invalid_input() {
+        assert_eq!(base64_decode("YWxp*2U6"), None);
+        assert_eq!(base64_decode("YWxpY"), None);
+        assert_eq!(base64_decode("YW xp"), None);
+    }
+
+    #[test]
+    fn parses_authorities() {
+        let addr = parse_authority("example.com:8080", None).unwrap();
+        assert_eq!(addr.addr, Addr::Domain("example.com".to_string()));
+        assert_eq!(addr.port, 8080);
+
+        let addr = parse_authority("[::1]", Some(80)).unwrap();
+        assert_eq!(addr.addr, Addr::Ipv6(Ipv6Addr::LOCALHOST));
+        assert_eq!(addr.port, 80);
+        assert_eq!(format_authority(&addr), "[::1]:80");
+
+        assert!(parse_authority("example.com", None).is_err());
+        assert!(parse_authority("example.com:http", None).is_err());
+        assert!(parse_authority(":80", None).is_err());
+        assert!(parse_authority(&format!("{}:80", "a".repeat(256)), None).is_err());
+    }
+
+    #[test]
+    fn length_body_ends_after_its_length() {
+        let mut body = RequestBody::Length(5);
+        assert_eq!(body.consume(b"abc"), 3);
+        assert_eq!(body.consume(b"deGET / HTTP/1.1"), 2);
+        assert_eq!(body.consume(b"more"), 0);
+    }
+
+    #[test]
+    fn chunked_body_ends_after_the_last_chunk() {
+        let chunked = b"4;ext=1\r\nWiki\r\n5\r\npedia\r\n0\r\nTrailer: x\r\n\r\n";
+        let mut body = RequestBody::chunked();
+        let mut data = chunked.to_vec();
+        data.extend_from_slice(b"GET http://other/ HTTP/1.1\r\n\r\n");
+        assert_eq!(body.consume(&data), chunked.len());
+        assert_eq!(body.consume(b"more"), 0);
+
+        // Fed one byte at a time
+        let mut body = RequestBody::chunked();
+        let consumed: usize = data.iter().map(|byte| body.consume(&[*byte])).sum();
+        assert_eq!(consumed, chunked.len());
+    }
+
+    #[test]
+    fn malformed_chunked_body_ends_at_the_invalid_byte() {
+        let mut body = RequestBody::chunked();
+        assert_eq!(body.consume(b"4\r\nWikiXX"), 7);
+        assert_eq!(body.consume(b"\r\n0\r\n\r\n"), 0);
+
+        let mut body = RequestBody::chunked();
+        assert_eq!(body.consume(b"zz\r\n"), 0);
+    }
+}
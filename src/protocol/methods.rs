@@ -41,7 +41,7 @@ impl AuthMethod {
             USERNAME_PASSWORD => AuthMethod::UsernamePassword,
             (IANA_ASSIGNED_LOWER..=IANA_ASSIGNED_UPPER) => AuthMethod::IanaAssigned(value),
             PRIVATE_METHOD_LOWER..=PRIVATE_METHOD_UPPER => AuthMethod::PrivateMethods(value),
-            _ => unreachable!("u8 range handled fully"),
+            NO_ACCEPTABLE_METHODS => AuthMethod::NoAcceptableMethods,
         }
     }
     pub(crate) fn to_u8(self) -> u8 {
@@ -0,0 +1,36 @@
+use super::Reply;
+
+/// The version byte of SOCKS4 and SOCKS4a requests.
+pub const SOCKS4_VERSION: u8 = 0x04;
+/// The version byte of SOCKS4 replies.
+pub const SOCKS4_REPLY_VERSION: u8 = 0x00;
+/// The longest user id or domain name accepted in a SOCKS4 request.
+pub const SOCKS4_MAX_FIELD_LEN: usize = 255;
+
+/// The reply codes of SOCKS4, defined in the
+/// [SOCKS4 protocol](https://www.openssh.com/txt/socks4.protocol).
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Socks4Reply {
+    Granted = 0x5A,
+    RejectedOrFailed = 0x5B,
+    /// Sent when the authenticator rejects the user id, the protocol doesn't have a dedicated code
+    /// for this so the identd mismatch code is used.
+    UserIdRejected = 0x5D,
+}
+
+impl Socks4Reply {
+    pub fn to_u8(self) -> u8 {
+        self as u8
+    }
+}
+
+impl From<Reply> for Socks4Reply {
+    /// SOCKS4 can't tell failures apart, every SOCKS5 failure is a rejection.
+    fn from(reply: Reply) -> Self {
+        match reply {
+            Reply::Success => Socks4Reply::Granted,
+            _ => Socks4Reply::RejectedOrFailed,
+        }
+    }
+}
@@ -2,7 +2,7 @@ use std::io::{self, Cursor};
 
 use tokio::io::{AsyncReadExt, AsyncSeekExt};
 
-use crate::protocol::{SocksSocketAddr, RESERVED_16};
+use super::{SocksSocketAddr, RESERVED_16};
 
 #[derive(Debug)]
 pub struct UdpMessage<'a> {
@@ -0,0 +1,322 @@
+//! # Rate Limiting Module
+//!
+//! This module provides bandwidth limiting that can wrap any `Connect`, `Bind` or `Associate`
+//! implementation. Traffic is limited with token buckets, one token per byte:
+//!
+//! - Per user buckets, keyed by the `Authenticator::Credentials` of the session and shared by all
+//!   concurrent sessions of the same credentials. Anonymous sessions (`()` credentials) share one.
+//! - Global buckets, shared by every session of the `RateLimiter`.
+//!
+//! Upload (client to destination) and download (destination to client) are limited separately.
+//! TCP relays are slowed down to the allowed rate, while UDP datagrams that don't fit in the
+//! buckets are dropped.
+//!
+//! ## Example
+//!
+//! ```rust,no_run
+//! use std::sync::Arc;
+//!
+//! use gerevs::{
+//!     auth::NoAuthAuthenticator,
+//!     method_handlers::{TunnelAssociate, TunnelBind, TunnelConnect},
+//!     rate_limit::{Rate, RateLimitAssociate, RateLimitBind, RateLimitConnect, RateLimiter},
+//!     Socks5Server,
+//! };
+//! use tokio::net::TcpListener;
+//!
+//! # async fn run() -> gerevs::Result<()> {
+//! let limiter = Arc::new(
+//!     RateLimiter::new()
+//!         .with_user_download(Rate::bytes_per_second(1024 * 1024))
+//!         .with_user_upload(Rate::bytes_per_second(256 * 1024))
+//!         .with_global_download(Rate::bytes_per_second(100 * 1024 * 1024)),
+//! );
+//!
+//! let listener = TcpListener::bind("0.0.0.0:1080").await?;
+//! let server = Socks5Server::new(
+//!     listener,
+//!     || NoAuthAuthenticator,
+//!     {
+//!         let limiter = limiter.clone();
+//!         move || RateLimitConnect::new(TunnelConnect::new(), limiter.clone())
+//!     },
+//!     {
+//!         let limiter = limiter.clone();
+//!         move || RateLimitBind::new(TunnelBind::new(), limiter.clone())
+//!     },
+//!     move || RateLimitAssociate::new(TunnelAssociate::new(), limiter.clone()),
+//! );
+//! server.run().await
+//! # }
+//! ```
+
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+mod associate;
+mod bind;
+mod bucket;
+mod connect;
+mod stream;
+
+pub use associate::RateLimitAssociate;
+pub use bind::RateLimitBind;
+pub use connect::RateLimitConnect;
+
+use bucket::TokenBucket;
+
+/// A rate in bytes per second, with the burst a bucket may accumulate while idle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rate {
+    bytes_per_second: u64,
+    burst: u64,
+}
+
+impl Rate {
+    /// Creates a `Rate` of `bytes_per_second`, allowing a burst of one second of traffic.
+    pub fn bytes_per_second(bytes_per_second: u64) -> Self {
+        Self {
+            bytes_per_second,
+            burst: bytes_per_second,
+        }
+    }
+
+    /// Sets the number of bytes that may be sent at once after being idle.
+    pub fn with_burst(mut self, burst: u64) -> Self {
+        self.burst = burst;
+        self
+    }
+}
+
+/// The buckets one direction of a session is limited by.
+#[derive(Clone, Default)]
+pub(crate) struct Buckets(Vec<Arc<TokenBucket>>);
+
+impl Buckets {
+    fn push(&mut self, bucket: Option<&Arc<TokenBucket>>) {
+        self.0.extend(bucket.cloned());
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Takes up to `want` tokens from every bucket.
+    ///
+    /// - Returns: The number of tokens taken, or how long to wait before trying again.
+    pub(crate) fn take_up_to(&self, want: usize) -> Result<usize, Duration> {
+        let mut granted = want;
+        for (i, bucket) in self.0.iter().enumerate() {
+            match bucket.take_up_to(granted) {
+                Ok(taken) => {
+                    self.0[..i]
+                        .iter()
+                        .for_each(|bucket| bucket.give_back(granted - taken));
+                    granted = taken;
+                }
+                Err(wait) => {
+                    self.0[..i]
+                        .iter()
+                        .for_each(|bucket| bucket.give_back(granted));
+                    return Err(wait);
+                }
+            }
+        }
+        Ok(granted)
+    }
+
+    /// Takes `amount` tokens from every bucket if all of them have enough.
+    pub(crate) fn try_take(&self, amount: usize) -> bool {
+        for (i, bucket) in self.0.iter().enumerate() {
+            if !bucket.try_take(amount) {
+                self.0[..i]
+                    .iter()
+                    .for_each(|bucket| bucket.give_back(amount));
+                return false;
+            }
+        }
+        true
+    }
+
+    pub(crate) fn give_back(&self, amount: usize) {
+        self.0.iter().for_each(|bucket| bucket.give_back(amount));
+    }
+}
+
+/// The buckets of a session.
+#[derive(Clone, Default)]
+pub(crate) struct SessionLimits {
+    /// Limits data sent by the client.
+    pub(crate) upload: Buckets,
+    /// Limits data sent to the client.
+    pub(crate) download: Buckets,
+}
+
+struct UserBuckets {
+    upload: Option<Arc<TokenBucket>>,
+    download: Option<Arc<TokenBucket>>,
+}
+
+impl UserBuckets {
+    /// Whether the buckets can be dropped: no session holds them and they refilled, so new
+    /// buckets would behave the same.
+    fn is_idle(&self) -> bool {
+        [&self.upload, &self.download]
+            .into_iter()
+            .flatten()
+            .all(|bucket| Arc::strong_count(bucket) == 1 && bucket.is_full())
+    }
+}
+
+/// The `RateLimiter` struct holds the token buckets shared by the sessions it limits, keyed by the
+/// credentials type `K`. It's shared by the rate limiting handlers with an `Arc`.
+pub struct RateLimiter<K> {
+    user_upload: Option<Rate>,
+    user_download: Option<Rate>,
+    global_upload: Option<Arc<TokenBucket>>,
+    global_download: Option<Arc<TokenBucket>>,
+    users: Mutex<HashMap<K, UserBuckets>>,
+}
+
+impl<K> RateLimiter<K>
+where
+    K: Hash + Eq + Clone,
+{
+    /// Creates a new `RateLimiter` without any limits.
+    pub fn new() -> Self {
+        Self {
+            user_upload: None,
+            user_download: None,
+            global_upload: None,
+            global_download: None,
+            users: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Limits the data sent by the clients of every user to `rate`.
+    pub fn with_user_upload(mut self, rate: Rate) -> Self {
+        self.user_upload = Some(rate);
+        self
+    }
+
+    /// Limits the data sent to the clients of every user to `rate`.
+    pub fn with_user_download(mut self, rate: Rate) -> Self {
+        self.user_download = Some(rate);
+        self
+    }
+
+    /// Limits the data sent by all clients together to `rate`.
+    pub fn with_global_upload(mut self, rate: Rate) -> Self {
+        self.global_upload = Some(Arc::new(TokenBucket::new(rate)));
+        self
+    }
+
+    /// Limits the data sent to all clients together to `rate`.
+    pub fn with_global_download(mut self, rate: Rate) -> Self {
+        self.global_download = Some(Arc::new(TokenBucket::new(rate)));
+        self
+    }
+
+    /// Returns the buckets a session of `credentials` is limited by.
+    pub(crate) fn session(&self, credentials: &K) -> SessionLimits {
+        let mut limits = SessionLimits::default();
+
+        if self.user_upload.is_some() || self.user_download.is_some() {
+            let mut users = self.users.lock().unwrap_or_else(|err| err.into_inner());
+            if !users.contains_key(credentials) {
+                users.retain(|_, buckets| !buckets.is_idle());
+            }
+            let user = users
+                .entry(credentials.clone())
+                .or_insert_with(|| UserBuckets {
+                    upload: self
+                        .user_upload
+                        .map(|rate| Arc::new(TokenBucket::new(rate))),
+                    download: self
+                        .user_download
+                        .map(|rate| Arc::new(TokenBucket::new(rate))),
+                });
+            limits.upload.push(user.upload.as_ref());
+            limits.download.push(user.download.as_ref());
+        }
+
+        limits.upload.push(self.global_upload.as_ref());
+        limits.download.push(self.global_download.as_ref());
+        limits
+    }
+}
+
+impl<K> Default for RateLimiter<K>
+where
+    K: Hash + Eq + Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::advance;
+
+    use super::*;
+
+    fn buckets(rates: &[u64]) -> (Buckets, Vec<Arc<TokenBucket>>) {
+        let rates: Vec<_> = rates
+            .iter()
+            .map(|&rate| Arc::new(TokenBucket::new(Rate::bytes_per_second(rate))))
+            .collect();
+        (Buckets(rates.clone()), rates)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn take_up_to_gives_back_what_other_buckets_deny() {
+        let (buckets, rates) = buckets(&[1000, 100]);
+        assert_eq!(buckets.take_up_to(500), Ok(100));
+        // The first bucket got back the 400 tokens the second one didn't grant
+        assert!(rates[0].try_take(900));
+        assert!(!rates[0].try_take(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn take_up_to_waits_for_the_emptiest_bucket() {
+        let (buckets, rates) = buckets(&[1000, 100]);
+        assert_eq!(buckets.take_up_to(100), Ok(100));
+
+        assert_eq!(buckets.take_up_to(50), Err(Duration::from_millis(500)));
+        // Nothing was taken from the first bucket
+        assert!(rates[0].try_take(900));
+        rates[0].give_back(900);
+
+        advance(Duration::from_millis(500)).await;
+        assert_eq!(buckets.take_up_to(50), Ok(50));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn try_take_is_all_or_nothing() {
+        let (buckets, rates) = buckets(&[1000, 100]);
+        assert!(rates[1].try_take(50));
+        assert!(!buckets.try_take(100));
+        assert!(rates[0].is_full());
+        rates[1].give_back(50);
+
+        assert!(buckets.try_take(100));
+        buckets.give_back(100);
+        assert!(rates.iter().all(|bucket| bucket.is_full()));
+    }
+
+    #[test]
+    fn users_share_their_buckets() {
+        let limiter = RateLimiter::new().with_user_upload(Rate::bytes_per_second(1000));
+        let alice = limiter.session(&"alice");
+        assert!(alice.download.is_empty());
+        assert!(alice.upload.try_take(1000));
+
+        assert!(!limiter.session(&"alice").upload.try_take(1));
+        assert!(limiter.session(&"bob").upload.try_take(1000));
+    }
+}
@@ -0,0 +1,216 @@
+use std::{hash::Hash, io, net::SocketAddr, sync::Arc};
+
+use crate::{
+    context::SessionContext,
+    method_handlers::Associate,
+    protocol::{SocksSocketAddr, UdpMessage},
+};
+
+use super::{RateLimiter, SessionLimits};
+
+/// The `RateLimitAssociate` struct wraps an `Associate` implementation and limits the bandwidth of
+/// the relayed datagrams with the buckets of the session's credentials. Datagrams that don't fit in
+/// the buckets are dropped, only their payload is charged in both directions. Datagrams the inner
+/// handler fails to send aren't charged.
+pub struct RateLimitAssociate<A, K> {
+    inner: A,
+    limiter: Arc<RateLimiter<K>>,
+    limits: Option<SessionLimits>,
+}
+
+impl<A, K> RateLimitAssociate<A, K> {
+    /// Creates a new `RateLimitAssociate`.
+    ///
+    /// - `inner`: The handler relaying the datagrams.
+    /// - `limiter`: The limiter holding the shared buckets.
+    pub fn new(inner: A, limiter: Arc<RateLimiter<K>>) -> Self {
+        Self {
+            inner,
+            limiter,
+            limits: None,
+        }
+    }
+}
+
+impl<A, K> RateLimitAssociate<A, K>
+where
+    K: Hash + Eq + Clone,
+{
+    fn limits(&mut self, credentials: &K) -> &SessionLimits {
+        self.limits
+            .get_or_insert_with(|| self.limiter.session(credentials))
+    }
+}
+
+impl<A, K> Associate<K> for RateLimitAssociate<A, K>
+where
+    A: Associate<K> + Send + Sync,
+    A::Connection: Send,
+    K: Hash + Eq + Clone + Send + Sync,
+{
+    type Connection = A::Connection;
+
+    async fn bind(
+        &self,
+        credentials: &K,
+        context: &SessionContext,
+    ) -> crate::Result<(SocketAddr, Self::Connection)> {
+        self.inner.bind(credentials, context).await
+    }
+
+    async fn send_to(
+        &mut self,
+        conn: &mut Self::Connection,
+        buf: &[u8],
+        dst: SocksSocketAddr,
+        credentials: &K,
+    ) -> crate::Result<usize> {
+        if !self.limits(credentials).upload.try_take(buf.len()) {
+            return Err(io::Error::other(format!(
+                "Upload limit reached, dropped datagram to {}",
+                dst
+            )))?;
+        }
+        let res = self.inner.send_to(conn, buf, dst, credentials).await;
+        if res.is_err() {
+            // The datagram was dropped, it wasn't relayed
+            self.limits(credentials).upload.give_back(buf.len());
+        }
+        res
+    }
+
+    async fn send_to_client(
+        &mut self,
+        conn: &mut Self::Connection,
+        buf: &[u8],
+        client: SocketAddr,
+        credentials: &K,
+    ) -> crate::Result<usize> {
+        // `buf` is wrapped in the UDP request header, only the payload is charged
+        let payload = UdpMessage::parse(buf)
+            .await
+            .map_or(buf.len(), |message| message.data.len());
+        if !self.limits(credentials).download.try_take(payload) {
+            return Err(io::Error::other(
+                "Download limit reached, dropped datagram to the client",
+            ))?;
+        }
+        let res = self
+            .inner
+            .send_to_client(conn, buf, client, credentials)
+            .await;
+        if res.is_err() {
+            self.limits(credentials).download.give_back(payload);
+        }
+        res
+    }
+
+    async fn recv_from(
+        &mut self,
+        conn: &mut Self::Connection,
+        buf: &mut [u8],
+        credentials: &K,
+    ) -> crate::Result<(usize, SocketAddr)> {
+        self.inner.recv_from(conn, buf, credentials).await
+    }
+
+    async fn resolve(&self, addr: &SocksSocketAddr) -> io::Result<Vec<SocketAddr>> {
+        self.inner.resolve(addr).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use crate::{protocol::Reply, rate_limit::Rate, Socks5Error};
+
+    use super::*;
+
+    /// Fails to send while `fail` is set.
+    struct Flaky {
+        fail: bool,
+    }
+
+    impl Associate<()> for Flaky {
+        type Connection = ();
+
+        async fn bind(&self, _: &(), _: &SessionContext) -> crate::Result<(SocketAddr, ())> {
+            Err(Socks5Error::Socks5Error(Reply::CommandNotSupported))
+        }
+
+        async fn send_to(
+            &mut self,
+            _: &mut (),
+            buf: &[u8],
+            _: SocksSocketAddr,
+            _: &(),
+        ) -> crate::Result<usize> {
+            if self.fail {
+                return Err(io::Error::other("Send failed"))?;
+            }
+            Ok(buf.len())
+        }
+
+        async fn recv_from(
+            &mut self,
+            _: &mut (),
+            _: &mut [u8],
+            _: &(),
+        ) -> crate::Result<(usize, SocketAddr)> {
+            Err(Socks5Error::Socks5Error(Reply::GeneralFailure))
+        }
+    }
+
+    fn addr() -> SocketAddr {
+        (Ipv4Addr::LOCALHOST, 5353).into()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_sends_are_not_charged() {
+        let limiter = Arc::new(
+            RateLimiter::new()
+                .with_user_upload(Rate::bytes_per_second(100))
+                .with_user_download(Rate::bytes_per_second(100)),
+        );
+        let mut associate = RateLimitAssociate::new(Flaky { fail: true }, limiter);
+
+        let datagram = [0; 100];
+        let wrapped = UdpMessage {
+            fragment_number: 0,
+            dst: addr().into(),
+            data: &datagram,
+        }
+        .as_bytes();
+        for _ in 0..3 {
+            assert!(associate
+                .send_to(&mut (), &datagram, addr().into(), &())
+                .await
+                .is_err());
+            assert!(associate
+                .send_to_client(&mut (), &wrapped, addr(), &())
+                .await
+                .is_err());
+        }
+
+        // Only the payload is charged, the whole burst is still there
+        associate.inner.fail = false;
+        associate
+            .send_to(&mut (), &datagram, addr().into(), &())
+            .await
+            .unwrap();
+        associate
+            .send_to_client(&mut (), &wrapped, addr(), &())
+            .await
+            .unwrap();
+
+        assert!(associate
+            .send_to(&mut (), &datagram, addr().into(), &())
+            .await
+            .is_err());
+        assert!(associate
+            .send_to_client(&mut (), &wrapped, addr(), &())
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,68 @@
+use std::{hash::Hash, net::SocketAddr, sync::Arc};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{context::SessionContext, method_handlers::Bind, protocol::SocksSocketAddr};
+
+use super::{stream::Throttled, RateLimiter};
+
+/// The `RateLimitBind` struct wraps a `Bind` implementation and limits the bandwidth of the
+/// relayed connection with the buckets of the session's credentials.
+pub struct RateLimitBind<B, K> {
+    inner: B,
+    limiter: Arc<RateLimiter<K>>,
+}
+
+impl<B, K> RateLimitBind<B, K> {
+    /// Creates a new `RateLimitBind`.
+    ///
+    /// - `inner`: The handler accepting and relaying the connection.
+    /// - `limiter`: The limiter holding the shared buckets.
+    pub fn new(inner: B, limiter: Arc<RateLimiter<K>>) -> Self {
+        Self { inner, limiter }
+    }
+}
+
+impl<B, K> Bind<K> for RateLimitBind<B, K>
+where
+    B: Bind<K> + Send,
+    B::Listener: Send,
+    B::Stream: Send,
+    K: Hash + Eq + Clone + Send + Sync,
+{
+    type Listener = B::Listener;
+    type Stream = B::Stream;
+
+    async fn bind(
+        &mut self,
+        addr: SocksSocketAddr,
+        credentials: &K,
+        context: &SessionContext,
+    ) -> crate::Result<(SocketAddr, Self::Listener)> {
+        self.inner.bind(addr, credentials, context).await
+    }
+
+    async fn accept(
+        &mut self,
+        server: Self::Listener,
+        credentials: &K,
+    ) -> crate::Result<(Self::Stream, SocketAddr)> {
+        self.inner.accept(server, credentials).await
+    }
+
+    async fn start_listening<T>(
+        self,
+        server: T,
+        client: Self::Stream,
+        credentials: K,
+    ) -> crate::Result<()>
+    where
+        T: AsyncWrite + AsyncRead + Send + Unpin + 'static,
+    {
+        // `server` is the stream of the SOCKS client, `client` the peer that connected to it
+        let server = Throttled::new(server, self.limiter.session(&credentials));
+        self.inner
+            .start_listening(server, client, credentials)
+            .await
+    }
+}
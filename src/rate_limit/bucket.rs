@@ -0,0 +1,147 @@
+use std::{sync::Mutex, time::Duration};
+
+use tokio::time::Instant;
+
+use super::Rate;
+
+/// Reads and writes are not granted less than this many bytes unless less was asked for, so a
+/// throttled stream wakes up for a useful amount of data.
+const MIN_GRANT: f64 = 16384.0;
+
+struct State {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// A token bucket holding one token per byte.
+pub(crate) struct TokenBucket {
+    bytes_per_second: f64,
+    burst: f64,
+    state: Mutex<State>,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket.
+    pub(crate) fn new(rate: Rate) -> Self {
+        let bytes_per_second = rate.bytes_per_second.max(1) as f64;
+        let burst = rate.burst.max(1) as f64;
+        Self {
+            bytes_per_second,
+            burst,
+            state: Mutex::new(State {
+                tokens: burst,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    fn with_state<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut State) -> R,
+    {
+        let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.last_refill).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * self.bytes_per_second).min(self.burst);
+        state.last_refill = now;
+        f(&mut state)
+    }
+
+    /// Takes up to `want` tokens.
+    ///
+    /// - Returns: The number of tokens taken, or how long to wait before enough tokens are available.
+    pub(crate) fn take_up_to(&self, want: usize) -> Result<usize, Duration> {
+        let needed = (want as f64).min(self.burst).min(MIN_GRANT);
+        self.with_state(|state| {
+            if state.tokens < needed {
+                let missing = needed - state.tokens;
+                return Err(Duration::from_secs_f64(missing / self.bytes_per_second));
+            }
+            let granted = (state.tokens.floor() as usize).min(want);
+            state.tokens -= granted as f64;
+            Ok(granted)
+        })
+    }
+
+    /// Takes `amount` tokens if they are available. Amounts larger than the burst are taken from a
+    /// full bucket, leaving it in debt.
+    pub(crate) fn try_take(&self, amount: usize) -> bool {
+        let needed = (amount as f64).min(self.burst);
+        self.with_state(|state| {
+            if state.tokens < needed {
+                return false;
+            }
+            state.tokens -= amount as f64;
+            true
+        })
+    }
+
+    pub(crate) fn is_full(&self) -> bool {
+        self.with_state(|state| state.tokens >= self.burst)
+    }
+
+    /// Returns tokens that were taken but not used.
+    pub(crate) fn give_back(&self, amount: usize) {
+        self.with_state(|state| state.tokens = (state.tokens + amount as f64).min(self.burst));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::advance;
+
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn refills_up_to_the_burst() {
+        let bucket = TokenBucket::new(Rate::bytes_per_second(1000));
+        assert!(bucket.is_full());
+        assert_eq!(bucket.take_up_to(600), Ok(600));
+        // Waits for the whole grant rather than handing out what's left
+        assert_eq!(bucket.take_up_to(600), Err(Duration::from_millis(200)));
+        assert_eq!(bucket.take_up_to(400), Ok(400));
+        assert_eq!(bucket.take_up_to(100), Err(Duration::from_millis(100)));
+
+        advance(Duration::from_millis(250)).await;
+        assert_eq!(bucket.take_up_to(250), Ok(250));
+
+        advance(Duration::from_secs(10)).await;
+        assert!(bucket.is_full());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn take_up_to_waits_for_a_useful_grant() {
+        let bucket = TokenBucket::new(Rate::bytes_per_second(100_000).with_burst(100_000));
+        assert_eq!(bucket.take_up_to(95_000), Ok(95_000));
+        // 5000 tokens are left, less than the minimum grant
+        assert_eq!(
+            bucket.take_up_to(50_000),
+            Err(Duration::from_secs_f64((MIN_GRANT - 5000.0) / 100_000.0))
+        );
+        // Smaller reads are granted right away
+        assert_eq!(bucket.take_up_to(1000), Ok(1000));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn try_take_goes_into_debt_for_large_datagrams() {
+        let bucket = TokenBucket::new(Rate::bytes_per_second(1000));
+        assert!(bucket.try_take(1500));
+        assert!(!bucket.try_take(1));
+
+        // The debt of 500 tokens is paid off before anything is granted
+        advance(Duration::from_millis(500)).await;
+        assert!(!bucket.try_take(1));
+        advance(Duration::from_millis(100)).await;
+        assert!(bucket.try_take(100));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn give_back_is_capped_at_the_burst() {
+        let bucket = TokenBucket::new(Rate::bytes_per_second(1000));
+        assert!(bucket.try_take(300));
+        bucket.give_back(300);
+        assert!(bucket.is_full());
+        bucket.give_back(300);
+        assert_eq!(bucket.take_up_to(2000), Ok(1000));
+    }
+}
@@ -0,0 +1,66 @@
+use std::{hash::Hash, net::SocketAddr, sync::Arc};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{context::SessionContext, method_handlers::Connect, protocol::SocksSocketAddr};
+
+use super::{stream::Throttled, RateLimiter, SessionLimits};
+
+/// The `RateLimitConnect` struct wraps a `Connect` implementation and limits the bandwidth of the
+/// relayed connection with the buckets of the session's credentials.
+pub struct RateLimitConnect<C, K> {
+    inner: C,
+    limiter: Arc<RateLimiter<K>>,
+    limits: SessionLimits,
+}
+
+impl<C, K> RateLimitConnect<C, K> {
+    /// Creates a new `RateLimitConnect`.
+    ///
+    /// - `inner`: The handler establishing and relaying the connection.
+    /// - `limiter`: The limiter holding the shared buckets.
+    pub fn new(inner: C, limiter: Arc<RateLimiter<K>>) -> Self {
+        Self {
+            inner,
+            limiter,
+            limits: SessionLimits::default(),
+        }
+    }
+}
+
+impl<C, K> Connect<K> for RateLimitConnect<C, K>
+where
+    C: Connect<K> + Send,
+    C::ServerConnection: Send,
+    K: Hash + Eq + Clone + Send + Sync,
+{
+    type ServerConnection = C::ServerConnection;
+
+    async fn establish_connection(
+        &mut self,
+        destination: SocksSocketAddr,
+        credentials: K,
+        context: &SessionContext,
+    ) -> crate::Result<Self::ServerConnection> {
+        self.limits = self.limiter.session(&credentials);
+        self.inner
+            .establish_connection(destination, credentials, context)
+            .await
+    }
+
+    fn reply_address(&self, connection: &Self::ServerConnection) -> Option<SocketAddr> {
+        self.inner.reply_address(connection)
+    }
+
+    async fn start_listening<T>(
+        self,
+        client: T,
+        connection: Self::ServerConnection,
+    ) -> crate::Result<()>
+    where
+        T: AsyncWrite + AsyncRead + Send + Unpin + 'static,
+    {
+        let client = Throttled::new(client, self.limits);
+        self.inner.start_listening(client, connection).await
+    }
+}
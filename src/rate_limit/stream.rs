@@ -0,0 +1,217 @@
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::{sleep, Sleep},
+};
+
+use super::{Buckets, SessionLimits};
+
+/// Wraps the client stream of a relay, delaying reads and writes until the buckets of the session
+/// allow them.
+pub(crate) struct Throttled<T> {
+    inner: T,
+    limits: SessionLimits,
+    read_delay: Option<Pin<Box<Sleep>>>,
+    write_delay: Option<Pin<Box<Sleep>>>,
+    /// Reads that were granted less than the caller's buffer are read into this buffer first.
+    scratch: Vec<u8>,
+}
+
+impl<T> Throttled<T> {
+    pub(crate) fn new(inner: T, limits: SessionLimits) -> Self {
+        Self {
+            inner,
+            limits,
+            read_delay: None,
+            write_delay: None,
+            scratch: Vec::new(),
+        }
+    }
+}
+
+/// Takes up to `want` tokens from `buckets`, waiting on `delay` while they are empty.
+fn poll_grant(
+    buckets: &Buckets,
+    delay: &mut Option<Pin<Box<Sleep>>>,
+    want: usize,
+    cx: &mut Context<'_>,
+) -> Poll<usize> {
+    loop {
+        if let Some(sleep) = delay {
+            ready!(sleep.as_mut().poll(cx));
+            *delay = None;
+        }
+
+        match buckets.take_up_to(want) {
+            Ok(granted) => return Poll::Ready(granted),
+            Err(wait) => *delay = Some(Box::pin(sleep(wait))),
+        }
+    }
+}
+
+impl<T> AsyncRead for Throttled<T>
+where
+    T: AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let want = buf.remaining();
+        if this.limits.upload.is_empty() || want == 0 {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        }
+
+        let granted = ready!(poll_grant(
+            &this.limits.upload,
+            &mut this.read_delay,
+            want,
+            cx
+        ));
+
+        let result = if granted == want {
+            let filled = buf.filled().len();
+            let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+            result.map_ok(|()| buf.filled().len() - filled)
+        } else {
+            this.scratch.resize(granted, 0);
+            let mut limited = ReadBuf::new(&mut this.scratch);
+            let result = Pin::new(&mut this.inner).poll_read(cx, &mut limited);
+            result.map_ok(|()| {
+                buf.put_slice(limited.filled());
+                limited.filled().len()
+            })
+        };
+
+        match &result {
+            Poll::Ready(Ok(read)) => this.limits.upload.give_back(granted - read),
+            _ => this.limits.upload.give_back(granted),
+        }
+        result.map_ok(|_| ())
+    }
+}
+
+impl<T> AsyncWrite for Throttled<T>
+where
+    T: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.limits.download.is_empty() || buf.is_empty() {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        }
+
+        let granted = ready!(poll_grant(
+            &this.limits.download,
+            &mut this.write_delay,
+            buf.len(),
+            cx
+        ));
+
+        let result = Pin::new(&mut this.inner).poll_write(cx, &buf[..granted]);
+        match &result {
+            Poll::Ready(Ok(written)) => this.limits.download.give_back(granted - written),
+            _ => this.limits.download.give_back(granted),
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use tokio::{
+        io::{duplex, AsyncReadExt, AsyncWriteExt},
+        time::Instant,
+    };
+
+    use crate::rate_limit::{bucket::TokenBucket, Rate};
+
+    use super::*;
+
+    fn limits(rate: u64) -> SessionLimits {
+        let bucket = || Arc::new(TokenBucket::new(Rate::bytes_per_second(rate)));
+        SessionLimits {
+            upload: Buckets(vec![bucket()]),
+            download: Buckets(vec![bucket()]),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn writes_are_slowed_down_to_the_rate() {
+        let (client, mut peer) = duplex(64 * 1024);
+        let mut throttled = Throttled::new(client, limits(1000));
+
+        let started = Instant::now();
+        throttled.write_all(&[0; 3000]).await.unwrap();
+        // The first second of traffic is the burst
+        assert_eq!(started.elapsed(), Duration::from_secs(2));
+
+        let mut received = [0; 3000];
+        peer.read_exact(&mut received).await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reads_are_slowed_down_to_the_rate() {
+        let (client, mut peer) = duplex(64 * 1024);
+        let mut throttled = Throttled::new(client, limits(1000));
+        peer.write_all(&[0; 3000]).await.unwrap();
+
+        let started = Instant::now();
+        let mut received = [0; 3000];
+        throttled.read_exact(&mut received).await.unwrap();
+        assert_eq!(started.elapsed(), Duration::from_secs(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unused_grants_are_given_back() {
+        let (client, mut peer) = duplex(64 * 1024);
+        let limits = limits(1000);
+        let mut throttled = Throttled::new(client, limits.clone());
+
+        // The whole burst is granted, only 10 bytes are read
+        peer.write_all(&[0; 10]).await.unwrap();
+        let mut received = [0; 1000];
+        assert_eq!(throttled.read(&mut received).await.unwrap(), 10);
+        assert!(limits.upload.try_take(990));
+        assert!(!limits.upload.try_take(1));
+
+        // A failed write gives back everything
+        drop(peer);
+        assert!(throttled.write(&[0; 1000]).await.is_err());
+        assert!(limits.download.try_take(1000));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn streams_without_buckets_are_not_throttled() {
+        let (client, mut peer) = duplex(64 * 1024);
+        let mut throttled = Throttled::new(client, SessionLimits::default());
+
+        let started = Instant::now();
+        throttled.write_all(&[0; 30_000]).await.unwrap();
+        let mut received = [0; 30_000];
+        peer.read_exact(&mut received).await.unwrap();
+        assert_eq!(started.elapsed(), Duration::ZERO);
+    }
+}
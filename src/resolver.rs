@@ -0,0 +1,65 @@
+//! # Resolver Module
+//!
+//! This module provides the `Resolver` trait, which the method handlers use to turn the domain
+//! names requested by clients into socket addresses.
+//!
+//! Two implementations are provided:
+//!
+//! - `SystemResolver`: Resolves using the operating system resolver (`getaddrinfo`) on a blocking
+//!   thread, this is the default of every handler.
+//! - `DnsResolver`: An asynchronous DNS client that queries configurable upstream servers directly,
+//!   caches answers for their TTL and supports a static hosts override map.
+//!
+//! ## Example
+//!
+//! ```rust,no_run
+//! use std::net::{IpAddr, Ipv4Addr};
+//!
+//! use gerevs::{method_handlers::TunnelConnect, resolver::DnsResolver};
+//!
+//! let resolver = DnsResolver::new(vec!["1.1.1.1:53".parse().unwrap()])
+//!     .with_host("internal.example", vec![IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7))]);
+//! let connect_handler = TunnelConnect::new().with_resolver(resolver.clone());
+//! ```
+
+use std::{
+    future::Future,
+    io,
+    net::{SocketAddr, ToSocketAddrs},
+};
+
+mod dns_resolver;
+mod message;
+
+pub use dns_resolver::{AddressPreference, DnsResolver};
+
+/// The `Resolver` trait resolves host names into socket addresses.
+pub trait Resolver: Send + Sync {
+    /// Resolves `host` into the socket addresses to try, in order of preference.
+    ///
+    /// - `host`: The domain name to resolve, IP address literals should be returned as is.
+    /// - `port`: The port of the returned socket addresses.
+    /// - Returns: A future that resolves to `io::Result<Vec<SocketAddr>>`, an error if the host
+    ///   couldn't be resolved.
+    fn resolve(
+        &self,
+        host: &str,
+        port: u16,
+    ) -> impl Future<Output = io::Result<Vec<SocketAddr>>> + Send;
+}
+
+/// The `SystemResolver` struct is an implementation of the `Resolver` trait that uses the
+/// resolver of the operating system, running the blocking lookup on tokio's blocking thread pool.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    async fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        let host = host.to_string();
+        let addrs = tokio::task::spawn_blocking(move || (host, port).to_socket_addrs())
+            .await
+            .expect("Task isn't aborted")?
+            .collect();
+        Ok(addrs)
+    }
+}
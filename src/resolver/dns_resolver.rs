@@ -0,0 +1,329 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+    io::{self, ErrorKind},
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+    time::Instant,
+};
+use tracing::{debug, warn};
+
+use super::{
+    message::{Query, RecordType, Response, RCODE_NAME_ERROR, RCODE_NO_ERROR},
+    Resolver,
+};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_ATTEMPTS: usize = 2;
+const DEFAULT_CACHE_SIZE: usize = 4096;
+/// How long an empty answer is cached, as the TTL of the SOA record isn't looked at.
+const NEGATIVE_TTL: Duration = Duration::from_secs(30);
+/// The largest UDP response accepted, responses that don't fit are retried over TCP.
+const MAX_UDP_RESPONSE: usize = 4096;
+
+/// The order in which the addresses of a dual stack host are returned, and which record types
+/// are queried.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AddressPreference {
+    /// Query A and AAAA records, IPv4 addresses come first.
+    #[default]
+    Ipv4First,
+    /// Query A and AAAA records, IPv6 addresses come first.
+    Ipv6First,
+    /// Only query A records.
+    Ipv4Only,
+    /// Only query AAAA records.
+    Ipv6Only,
+}
+
+#[derive(Debug, Clone)]
+struct Config {
+    upstreams: Vec<SocketAddr>,
+    hosts: HashMap<String, Vec<IpAddr>>,
+    timeout: Duration,
+    attempts: usize,
+    preference: AddressPreference,
+    cache_size: usize,
+}
+
+struct CacheEntry {
+    addrs: Vec<IpAddr>,
+    expires: Instant,
+}
+
+type Cache = HashMap<(String, RecordType), CacheEntry>;
+
+/// The `DnsResolver` struct is an implementation of the `Resolver` trait that sends DNS queries
+/// directly to configurable upstream servers over UDP, falling back to TCP for truncated responses.
+///
+/// Answers are cached for the lowest TTL of their records, and names in the hosts override map
+/// are answered without querying the upstream servers. Clones share the same cache.
+#[derive(Clone)]
+pub struct DnsResolver {
+    config: Arc<Config>,
+    cache: Arc<Mutex<Cache>>,
+}
+
+impl DnsResolver {
+    /// Creates a new `DnsResolver` that queries `upstreams` in order.
+    pub fn new(upstreams: Vec<SocketAddr>) -> Self {
+        Self {
+            config: Arc::new(Config {
+                upstreams,
+                hosts: HashMap::new(),
+                timeout: DEFAULT_TIMEOUT,
+                attempts: DEFAULT_ATTEMPTS,
+                preference: AddressPreference::default(),
+                cache_size: DEFAULT_CACHE_SIZE,
+            }),
+            cache: Default::default(),
+        }
+    }
+
+    /// Creates a new `DnsResolver` that queries the name servers listed in `/etc/resolv.conf`.
+    pub fn from_system_config() -> io::Result<Self> {
+        Self::from_resolv_conf("/etc/resolv.conf")
+    }
+
+    /// Creates a new `DnsResolver` that queries the name servers listed in a `resolv.conf` file.
+    pub fn from_resolv_conf<P>(path: P) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let upstreams: Vec<SocketAddr> = std::fs::read_to_string(path)?
+            .lines()
+            .filter_map(|line| line.strip_prefix("nameserver"))
+            .filter_map(|server| server.trim().parse::<IpAddr>().ok())
+            .map(|ip| SocketAddr::new(ip, 53))
+            .collect();
+
+        if upstreams.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::NotFound,
+                "No name servers configured",
+            ));
+        }
+        Ok(Self::new(upstreams))
+    }
+
+    /// Answers `host` with `addrs` instead of querying the upstream servers.
+    pub fn with_host(mut self, host: &str, addrs: Vec<IpAddr>) -> Self {
+        Arc::make_mut(&mut self.config)
+            .hosts
+            .insert(normalize_host(host), addrs);
+        self
+    }
+
+    /// Sets how long to wait for a response from a single upstream server, defaults to 5 seconds.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        Arc::make_mut(&mut self.config).timeout = timeout;
+        self
+    }
+
+    /// Sets how many times every upstream server is tried, defaults to 2.
+    pub fn with_attempts(mut self, attempts: usize) -> Self {
+        Arc::make_mut(&mut self.config).attempts = attempts.max(1);
+        self
+    }
+
+    /// Sets which record types are queried and how the addresses are ordered.
+    pub fn with_preference(mut self, preference: AddressPreference) -> Self {
+        Arc::make_mut(&mut self.config).preference = preference;
+        self
+    }
+
+    /// Sets the maximum number of cached answers, defaults to 4096. Zero disables caching.
+    pub fn with_cache_size(mut self, cache_size: usize) -> Self {
+        Arc::make_mut(&mut self.config).cache_size = cache_size;
+        self
+    }
+
+    /// Removes all cached answers.
+    pub fn clear_cache(&self) {
+        self.cache.lock().expect("Cache lock poisoned").clear();
+    }
+
+    async fn lookup(&self, host: &str, record_type: RecordType) -> io::Result<Vec<IpAddr>> {
+        let key = (host.to_string(), record_type);
+        if let Some(entry) = self.cache.lock().expect("Cache lock poisoned").get(&key) {
+            if entry.expires > Instant::now() {
+                debug!("Cache hit for {} {:?}", host, record_type);
+                return Ok(entry.addrs.clone());
+            }
+        }
+
+        let response = self.query(host, record_type).await?;
+        let ttl = response
+            .ttl
+            .map_or(NEGATIVE_TTL, |ttl| Duration::from_secs(ttl.into()));
+        self.insert(key, response.addrs.clone(), ttl);
+        Ok(response.addrs)
+    }
+
+    fn insert(&self, key: (String, RecordType), addrs: Vec<IpAddr>, ttl: Duration) {
+        if self.config.cache_size == 0 || ttl.is_zero() {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut cache = self.cache.lock().expect("Cache lock poisoned");
+        if cache.len() >= self.config.cache_size && !cache.contains_key(&key) {
+            cache.retain(|_, entry| entry.expires > now);
+            if cache.len() >= self.config.cache_size {
+                return;
+            }
+        }
+        cache.insert(
+            key,
+            CacheEntry {
+                addrs,
+                expires: now + ttl,
+            },
+        );
+    }
+
+    async fn query(&self, host: &str, record_type: RecordType) -> io::Result<Response> {
+        let query = Query::new(query_id(), host, record_type)?;
+        let mut last_err =
+            io::Error::new(ErrorKind::NotFound, "No upstream name servers configured");
+
+        for _ in 0..self.config.attempts {
+            for &upstream in &self.config.upstreams {
+                let res =
+                    tokio::time::timeout(self.config.timeout, exchange(upstream, &query)).await;
+                let response = match res {
+                    Ok(Ok(response)) => response,
+                    Ok(Err(err)) => {
+                        warn!("Query to {} failed: {}", upstream, err);
+                        last_err = err;
+                        continue;
+                    }
+                    Err(_) => {
+                        warn!("Query to {} timed out", upstream);
+                        last_err = io::Error::new(ErrorKind::TimedOut, "DNS query timed out");
+                        continue;
+                    }
+                };
+
+                match response.rcode {
+                    RCODE_NO_ERROR => return Ok(response),
+                    RCODE_NAME_ERROR => {
+                        return Err(io::Error::new(
+                            ErrorKind::NotFound,
+                            format!("Host not found: {}", host),
+                        ))
+                    }
+                    rcode => {
+                        warn!("Upstream {} answered with error code {}", upstream, rcode);
+                        last_err = io::Error::other(format!("DNS error code {}", rcode));
+                    }
+                }
+            }
+        }
+
+        Err(last_err)
+    }
+}
+
+impl Resolver for DnsResolver {
+    async fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(vec![SocketAddr::new(ip, port)]);
+        }
+
+        let host = normalize_host(host);
+        if let Some(addrs) = self.config.hosts.get(&host) {
+            return Ok(addrs.iter().map(|&ip| SocketAddr::new(ip, port)).collect());
+        }
+
+        let addrs = match self.config.preference {
+            AddressPreference::Ipv4Only => self.lookup(&host, RecordType::A).await?,
+            AddressPreference::Ipv6Only => self.lookup(&host, RecordType::Aaaa).await?,
+            preference => {
+                let (v4, v6) = tokio::join!(
+                    self.lookup(&host, RecordType::A),
+                    self.lookup(&host, RecordType::Aaaa)
+                );
+                let (first, second) = if preference == AddressPreference::Ipv4First {
+                    (v4, v6)
+                } else {
+                    (v6, v4)
+                };
+                match (first, second) {
+                    (Err(err), Err(_)) => return Err(err),
+                    (first, second) => first
+                        .unwrap_or_default()
+                        .into_iter()
+                        .chain(second.unwrap_or_default())
+                        .collect(),
+                }
+            }
+        };
+
+        if addrs.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::NotFound,
+                format!("No addresses found for {}", host),
+            ));
+        }
+        Ok(addrs
+            .into_iter()
+            .map(|ip| SocketAddr::new(ip, port))
+            .collect())
+    }
+}
+
+fn normalize_host(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// A random query id, keyed SipHash over nothing is as unpredictable as its random keys.
+fn query_id() -> u16 {
+    RandomState::new().build_hasher().finish() as u16
+}
+
+async fn exchange(upstream: SocketAddr, query: &Query) -> io::Result<Response> {
+    let local: SocketAddr = if upstream.is_ipv4() {
+        "0.0.0.0:0".parse().expect("Valid address")
+    } else {
+        "[::]:0".parse().expect("Valid address")
+    };
+    let socket = UdpSocket::bind(local).await?;
+    // A connected socket drops datagrams from anyone but the upstream
+    socket.connect(upstream).await?;
+    socket.send(query.as_bytes()).await?;
+
+    let mut buf = vec![0; MAX_UDP_RESPONSE];
+    loop {
+        let len = socket.recv(&mut buf).await?;
+        match Response::parse(query, &buf[..len]) {
+            Ok(response) if response.truncated => {
+                debug!("Response from {} truncated, retrying over TCP", upstream);
+                return exchange_tcp(upstream, query).await;
+            }
+            Ok(response) => return Ok(response),
+            Err(err) => debug!("Ignoring invalid response from {}: {}", upstream, err),
+        }
+    }
+}
+
+async fn exchange_tcp(upstream: SocketAddr, query: &Query) -> io::Result<Response> {
+    let mut stream = TcpStream::connect(upstream).await?;
+    let bytes = query.as_bytes();
+    let mut message = Vec::with_capacity(bytes.len() + 2);
+    message.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+    message.extend_from_slice(bytes);
+    stream.write_all(&message).await?;
+
+    let len = stream.read_u16().await?;
+    let mut buf = vec![0; len as usize];
+    stream.read_exact(&mut buf).await?;
+    Response::parse(query, &buf)
+}
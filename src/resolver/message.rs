@@ -0,0 +1,411 @@
+use std::{
+    io::{self, ErrorKind},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+const HEADER_LEN: usize = 12;
+const CLASS_IN: u16 = 1;
+/// Recursion desired
+const FLAG_RD: u16 = 0x0100;
+const FLAG_QR: u16 = 0x8000;
+const FLAG_TC: u16 = 0x0200;
+const TYPE_CNAME: u16 = 5;
+/// The longest name on the wire, RFC 1035 section 2.3.4
+const MAX_NAME_LEN: usize = 255;
+/// The most aliases followed from the queried name
+const MAX_CNAME_CHAIN: usize = 8;
+
+/// Response codes, RFC 1035 section 4.1.1
+pub(crate) const RCODE_NO_ERROR: u8 = 0;
+pub(crate) const RCODE_NAME_ERROR: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum RecordType {
+    A = 1,
+    Aaaa = 28,
+}
+
+/// A DNS query for a single name and record type.
+pub(crate) struct Query {
+    pub id: u16,
+    pub record_type: RecordType,
+    /// The queried name, lowercase and without the trailing dot.
+    name: Vec<u8>,
+    bytes: Vec<u8>,
+}
+
+impl Query {
+    pub fn new(id: u16, name: &str, record_type: RecordType) -> io::Result<Self> {
+        let invalid = || io::Error::new(ErrorKind::InvalidInput, format!("Invalid name: {}", name));
+
+        let name = name.trim_end_matches('.');
+        if name.is_empty() || name.len() > 253 {
+            return Err(invalid());
+        }
+
+        let mut bytes = Vec::with_capacity(HEADER_LEN + name.len() + 6);
+        bytes.extend_from_slice(&id.to_be_bytes());
+        bytes.extend_from_slice(&FLAG_RD.to_be_bytes());
+        // One question, no answer, authority or additional records
+        bytes.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+
+        for label in name.split('.') {
+            if label.is_empty() || label.len() > 63 {
+                return Err(invalid());
+            }
+            bytes.push(label.len() as u8);
+            bytes.extend_from_slice(label.as_bytes());
+        }
+        bytes.push(0);
+        bytes.extend_from_slice(&(record_type as u16).to_be_bytes());
+        bytes.extend_from_slice(&CLASS_IN.to_be_bytes());
+
+        Ok(Self {
+            id,
+            record_type,
+            name: name.to_ascii_lowercase().into_bytes(),
+            bytes,
+        })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    fn question(&self) -> &[u8] {
+        &self.bytes[HEADER_LEN..]
+    }
+}
+
+/// The parts of a DNS response the resolver cares about.
+#[derive(Debug)]
+pub(crate) struct Response {
+    pub rcode: u8,
+    pub truncated: bool,
+    pub addrs: Vec<IpAddr>,
+    /// The lowest TTL of the returned records, `None` if there are none.
+    pub ttl: Option<u32>,
+}
+
+impl Response {
+    /// Parses the response to `query`, failing if it isn't a response to it.
+    pub fn parse(query: &Query, buf: &[u8]) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(ErrorKind::InvalidData, msg.to_string());
+        let mut reader = Reader { buf, pos: 0 };
+
+        let id = reader.u16()?;
+        let flags = reader.u16()?;
+        let question_count = reader.u16()?;
+        let answer_count = reader.u16()?;
+        // Authority and additional records are skipped
+        reader.skip(4)?;
+
+        if id != query.id || flags & FLAG_QR == 0 {
+            return Err(invalid("Not a response to the query"));
+        }
+        let truncated = flags & FLAG_TC != 0;
+        let rcode = (flags & 0x000F) as u8;
+
+        if question_count != 1 {
+            return Err(invalid("Unexpected question count"));
+        }
+        let question = reader.take(query.question().len())?;
+        if !question.eq_ignore_ascii_case(query.question()) {
+            return Err(invalid("Question doesn't match the query"));
+        }
+
+        let mut records = Vec::new();
+        let mut aliases = Vec::new();
+        for _ in 0..answer_count {
+            let owner = reader.name()?;
+            let record_type = reader.u16()?;
+            let class = reader.u16()?;
+            let record_ttl = reader.u32()?;
+            let len = reader.u16()? as usize;
+            let data_pos = reader.pos;
+            let data = reader.take(len)?;
+
+            if class != CLASS_IN {
+                continue;
+            }
+            if record_type == TYPE_CNAME {
+                let target = Reader { buf, pos: data_pos }.name()?;
+                aliases.push((owner, target, record_ttl));
+            } else if record_type == query.record_type as u16 {
+                records.push((owner, data, record_ttl));
+            }
+        }
+
+        // Only the records of the queried name, or of the names it's an alias of, answer the
+        // query, any other record could poison the cache
+        let mut names = vec![query.name.clone()];
+        let mut alias_ttl: Option<u32> = None;
+        while names.len() <= MAX_CNAME_CHAIN {
+            let name = names.last().expect("Starts with the queried name");
+            let Some((_, target, ttl)) = aliases.iter().find(|(owner, ..)| owner == name) else {
+                break;
+            };
+            if names.contains(target) {
+                return Err(invalid("CNAME loop"));
+            }
+            names.push(target.clone());
+            alias_ttl = Some(alias_ttl.map_or(*ttl, |alias_ttl| alias_ttl.min(*ttl)));
+        }
+
+        let mut addrs = Vec::new();
+        // The answer lives as long as its shortest lived alias
+        let mut ttl = alias_ttl;
+        for (owner, data, record_ttl) in records {
+            if !names.contains(&owner) {
+                continue;
+            }
+            let addr = match (query.record_type, data.len()) {
+                (RecordType::A, 4) => IpAddr::V4(Ipv4Addr::from(
+                    <[u8; 4]>::try_from(data).expect("Length checked"),
+                )),
+                (RecordType::Aaaa, 16) => IpAddr::V6(Ipv6Addr::from(
+                    <[u8; 16]>::try_from(data).expect("Length checked"),
+                )),
+                _ => return Err(invalid("Invalid address record")),
+            };
+            addrs.push(addr);
+            ttl = Some(ttl.map_or(record_ttl, |ttl| ttl.min(record_ttl)));
+        }
+        if addrs.is_empty() {
+            ttl = None;
+        }
+
+        Ok(Self {
+            rcode,
+            truncated,
+            addrs,
+            ttl,
+        })
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.buf.len())
+            .ok_or_else(|| io::Error::new(ErrorKind::UnexpectedEof, "Truncated DNS message"))?;
+        let res = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(res)
+    }
+
+    fn skip(&mut self, len: usize) -> io::Result<()> {
+        self.take(len).map(|_| ())
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Reads a possibly compressed domain name, lowercase and without the trailing dot.
+    fn name(&mut self) -> io::Result<Vec<u8>> {
+        let invalid = |msg: &str| io::Error::new(ErrorKind::InvalidData, msg.to_string());
+
+        let mut name = Vec::new();
+        let mut wire_len = 0;
+        // Reading continues from a pointer's target, the name ends after the first pointer
+        let mut cursor = Reader {
+            buf: self.buf,
+            pos: self.pos,
+        };
+        let mut end = None;
+        loop {
+            let label_pos = cursor.pos;
+            let len = cursor.take(1)?[0];
+            match len {
+                0 => break,
+                len if len & 0xC0 == 0xC0 => {
+                    let offset = usize::from(len & 0x3F) << 8 | usize::from(cursor.take(1)?[0]);
+                    // Pointers only go backwards, together with the length limit this rules out
+                    // loops
+                    if offset >= label_pos {
+                        return Err(invalid("Invalid compression pointer in DNS name"));
+                    }
+                    end.get_or_insert(cursor.pos);
+                    cursor.pos = offset;
+                }
+                len if len & 0xC0 == 0 => {
+                    wire_len += 1 + len as usize;
+                    if wire_len + 1 > MAX_NAME_LEN {
+                        return Err(invalid("DNS name is too long"));
+                    }
+                    if !name.is_empty() {
+                        name.push(b'.');
+                    }
+                    name.extend(cursor.take(len as usize)?.to_ascii_lowercase());
+                }
+                _ => return Err(invalid("Invalid label in DNS name")),
+            }
+        }
+
+        self.pos = end.unwrap_or(cursor.pos);
+        Ok(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A pointer to the queried name, right after the header.
+    const QNAME: [u8; 2] = [0xC0, 0x0C];
+
+    fn response(query: &Query, answers: &[&[u8]]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&query.id.to_be_bytes());
+        buf.extend_from_slice(&(FLAG_QR | FLAG_RD).to_be_bytes());
+        buf.extend_from_slice(&[0, 1]);
+        buf.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+        buf.extend_from_slice(&[0, 0, 0, 0]);
+        buf.extend_from_slice(query.question());
+        for answer in answers {
+            buf.extend_from_slice(answer);
+        }
+        buf
+    }
+
+    fn record(owner: &[u8], record_type: u16, ttl: u32, data: &[u8]) -> Vec<u8> {
+        let mut record = owner.to_vec();
+        record.extend_from_slice(&record_type.to_be_bytes());
+        record.extend_from_slice(&CLASS_IN.to_be_bytes());
+        record.extend_from_slice(&ttl.to_be_bytes());
+        record.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        record.extend_from_slice(data);
+        record
+    }
+
+    fn query() -> Query {
+        Query::new(0x1234, "Example.com.", RecordType::A).unwrap()
+    }
+
+    #[test]
+    fn encodes_query() {
+        let query = query();
+        assert_eq!(
+            query.question(),
+            b"\x07Example\x03com\x00\x00\x01\x00\x01".as_slice()
+        );
+        assert!(Query::new(1, "a..b", RecordType::A).is_err());
+        assert!(Query::new(1, &"a".repeat(64), RecordType::A).is_err());
+    }
+
+    #[test]
+    fn parses_answer_of_the_queried_name() {
+        let query = query();
+        let answer = record(&QNAME, RecordType::A as u16, 300, &[192, 0, 2, 1]);
+        let parsed = Response::parse(&query, &response(&query, &[&answer])).unwrap();
+
+        assert_eq!(parsed.rcode, RCODE_NO_ERROR);
+        assert_eq!(parsed.addrs, [IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))]);
+        assert_eq!(parsed.ttl, Some(300));
+    }
+
+    #[test]
+    fn follows_cname_chain() {
+        let query = query();
+        // example.com CNAME www.example.com, www.example.com A 192.0.2.2
+        let alias = record(&QNAME, TYPE_CNAME, 60, b"\x03www\xC0\x0C");
+        let target_offset = (HEADER_LEN + query.question().len() + alias.len() - 6) as u8;
+        let answer = record(
+            &[0xC0, target_offset],
+            RecordType::A as u16,
+            300,
+            &[192, 0, 2, 2],
+        );
+        let parsed = Response::parse(&query, &response(&query, &[&alias, &answer])).unwrap();
+
+        assert_eq!(parsed.addrs, [IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2))]);
+        assert_eq!(parsed.ttl, Some(60));
+    }
+
+    #[test]
+    fn ignores_records_of_other_names() {
+        let query = query();
+        let other = record(
+            b"\x04evil\x03com\x00",
+            RecordType::A as u16,
+            300,
+            &[203, 0, 113, 1],
+        );
+        let parsed = Response::parse(&query, &response(&query, &[&other])).unwrap();
+
+        assert!(parsed.addrs.is_empty());
+        assert_eq!(parsed.ttl, None);
+    }
+
+    #[test]
+    fn rejects_cname_loop() {
+        let query = query();
+        let alias = record(&QNAME, TYPE_CNAME, 60, &QNAME);
+        assert!(Response::parse(&query, &response(&query, &[&alias])).is_err());
+    }
+
+    #[test]
+    fn rejects_other_responses() {
+        let query = query();
+        let mut buf = response(&query, &[]);
+        buf[0] ^= 0xFF;
+        assert!(Response::parse(&query, &buf).is_err());
+
+        let other = Query::new(0x1234, "example.org", RecordType::A).unwrap();
+        assert!(Response::parse(&query, &response(&other, &[])).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_names() {
+        let query = query();
+        let address = [192, 0, 2, 1];
+
+        // Forward and self pointers could loop
+        let forward = record(&[0xC0, 0xFF], RecordType::A as u16, 300, &address);
+        assert!(Response::parse(&query, &response(&query, &[&forward])).is_err());
+        let offset = (HEADER_LEN + query.question().len()) as u8;
+        let to_itself = record(&[0xC0, offset], RecordType::A as u16, 300, &address);
+        assert!(Response::parse(&query, &response(&query, &[&to_itself])).is_err());
+
+        // Reserved label type
+        let reserved = record(&[0x40, 0], RecordType::A as u16, 300, &address);
+        assert!(Response::parse(&query, &response(&query, &[&reserved])).is_err());
+
+        // Longer than 255 bytes
+        let mut long = Vec::new();
+        for _ in 0..4 {
+            long.push(63);
+            long.extend_from_slice(&[b'a'; 63]);
+        }
+        long.push(0);
+        let too_long = record(&long, RecordType::A as u16, 300, &address);
+        assert!(Response::parse(&query, &response(&query, &[&too_long])).is_err());
+
+        // Truncated in the middle of a label
+        let mut truncated = response(&query, &[]);
+        truncated[7] = 1;
+        truncated.extend_from_slice(b"\x07exam");
+        assert!(Response::parse(&query, &truncated).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_address_record() {
+        let query = query();
+        let answer = record(&QNAME, RecordType::A as u16, 300, &[192, 0, 2]);
+        assert!(Response::parse(&query, &response(&query, &[&answer])).is_err());
+    }
+}
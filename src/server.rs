@@ -0,0 +1,400 @@
+use std::{future::Future, io, net::SocketAddr, sync::Arc, time::Duration};
+
+use tokio::{
+    net::{TcpListener, TcpStream},
+    select,
+    sync::watch,
+    task::JoinSet,
+    time::Instant,
+};
+use tracing::{debug, info, span, warn, Instrument, Level};
+
+use crate::{
+    auth::Authenticator,
+    method_handlers::{Associate, Bind, Connect},
+    metrics::Metrics,
+    observer::SessionObserver,
+    socks5_socket::DEFAULT_UDP_BUFFER_SIZE,
+    FragmentPolicy, Socks5Socket, Timeouts,
+};
+
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The delay before accepting again after a failed accept, doubled on every consecutive failure up
+/// to `MAX_ACCEPT_BACKOFF`.
+const MIN_ACCEPT_BACKOFF: Duration = Duration::from_millis(5);
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Paces an accept loop after failed accepts, e.g. while the process is out of file descriptors,
+/// instead of retrying right away.
+#[derive(Debug, Default)]
+pub(crate) struct AcceptBackoff {
+    delay: Option<Duration>,
+    /// When accepting may resume, while paused after a failed accept.
+    paused_until: Option<Instant>,
+}
+
+impl AcceptBackoff {
+    /// Called after a successful accept.
+    pub(crate) fn reset(&mut self) {
+        self.delay = None;
+    }
+
+    /// Called after a failed accept, pauses accepting, longer on every consecutive failure.
+    pub(crate) fn failed(&mut self) {
+        let delay = self.delay.map_or(MIN_ACCEPT_BACKOFF, |delay| {
+            (delay * 2).min(MAX_ACCEPT_BACKOFF)
+        });
+        self.delay = Some(delay);
+        self.paused_until = Some(Instant::now() + delay);
+    }
+
+    /// Returns whether accepting is paused, until `resumed` resolves.
+    pub(crate) fn is_paused(&self) -> bool {
+        self.paused_until.is_some()
+    }
+
+    /// Resolves once the pause after a failed accept is over, never if accepting isn't paused. It
+    /// can be raced against other events, the pause isn't shortened by doing so.
+    pub(crate) async fn resumed(&mut self) {
+        match self.paused_until {
+            Some(until) => {
+                tokio::time::sleep_until(until).await;
+                self.paused_until = None;
+            }
+            None => std::future::pending().await,
+        }
+    }
+}
+
+/// The `Socks5Server` struct owns a `TcpListener` and runs a `Socks5Socket` for every accepted
+/// connection, each on its own task.
+///
+/// Since every session needs its own authenticator and method handlers, the server is built from
+/// factories (closures) that create a fresh instance for every accepted connection.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use gerevs::{
+///     auth::NoAuthAuthenticator,
+///     method_handlers::{TunnelAssociate, TunnelBind, TunnelConnect},
+///     Socks5Server,
+/// };
+/// use tokio::net::TcpListener;
+///
+/// # async fn run() -> gerevs::Result<()> {
+/// let listener = TcpListener::bind("0.0.0.0:1080").await?;
+/// let server = Socks5Server::new(
+///     listener,
+///     || NoAuthAuthenticator,
+///     TunnelConnect::new,
+///     TunnelBind::new,
+///     TunnelAssociate::new,
+/// );
+/// server.run_until(async { tokio::signal::ctrl_c().await.unwrap() }).await
+/// # }
+/// ```
+pub struct Socks5Server<Auth, C, B, A> {
+    listener: TcpListener,
+    authenticator: Auth,
+    connect_handler: C,
+    bind_handler: B,
+    associate_handler: A,
+    shutdown_timeout: Duration,
+    timeouts: Timeouts,
+    http_forwarding: bool,
+    fragment_policy: FragmentPolicy,
+    udp_buffer_size: usize,
+    metrics: Option<Arc<Metrics>>,
+    observer: Option<Arc<dyn SessionObserver>>,
+}
+
+impl<AuthF, CF, BF, AF, Auth, C, B, A> Socks5Server<AuthF, CF, BF, AF>
+where
+    AuthF: Fn() -> Auth,
+    CF: Fn() -> C,
+    BF: Fn() -> B,
+    AF: Fn() -> A,
+    Auth: Authenticator<TcpStream> + Send + Unpin + 'static,
+    Auth::Credentials: Sync + Send + 'static,
+    C: Connect<Auth::Credentials> + Send + Unpin + 'static,
+    C::ServerConnection: Send,
+    B: Bind<Auth::Credentials> + Send + Unpin + 'static,
+    B::Listener: Send,
+    B::Stream: Send,
+    A: Associate<Auth::Credentials> + Send + Unpin + 'static,
+    A::Connection: Send,
+{
+    /// Creates a new `Socks5Server` instance.
+    ///
+    /// - `listener`: The listener on which client connections are accepted.
+    /// - `authenticator`: A factory creating the authenticator of every session.
+    /// - `connect_handler`: A factory creating the handler for the CONNECT command.
+    /// - `bind_handler`: A factory creating the handler for the BIND command.
+    /// - `associate_handler`: A factory creating the handler for the UDP ASSOCIATE command.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `Socks5Server`.
+    pub fn new(
+        listener: TcpListener,
+        authenticator: AuthF,
+        connect_handler: CF,
+        bind_handler: BF,
+        associate_handler: AF,
+    ) -> Self {
+        Self {
+            listener,
+            authenticator,
+            connect_handler,
+            bind_handler,
+            associate_handler,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            timeouts: Timeouts::default(),
+            http_forwarding: false,
+            fragment_policy: FragmentPolicy::default(),
+            udp_buffer_size: DEFAULT_UDP_BUFFER_SIZE,
+            metrics: None,
+            observer: None,
+        }
+    }
+
+    /// Sets how long in-flight sessions are given to finish once shutdown was requested,
+    /// sessions still running after this deadline are aborted. Defaults to 30 seconds.
+    pub fn with_shutdown_timeout(mut self, shutdown_timeout: Duration) -> Self {
+        self.shutdown_timeout = shutdown_timeout;
+        self
+    }
+
+    /// Sets the deadlines every session enforces, see `Timeouts`.
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Sets whether every session forwards HTTP proxy requests with an absolute URI, see
+    /// `Socks5Socket::with_http_forwarding`.
+    pub fn with_http_forwarding(mut self, enabled: bool) -> Self {
+        self.http_forwarding = enabled;
+        self
+    }
+
+    /// Sets what the UDP associations of every session do with fragmented datagrams, see
+    /// `FragmentPolicy`.
+    pub fn with_fragment_policy(mut self, fragment_policy: FragmentPolicy) -> Self {
+        self.fragment_policy = fragment_policy;
+        self
+    }
+
+    /// Sets the size of the UDP receive buffer of every association, see
+    /// `Socks5Socket::with_udp_buffer_size`.
+    pub fn with_udp_buffer_size(mut self, size: usize) -> Self {
+        self.udp_buffer_size = size;
+        self
+    }
+
+    /// Records every session in `metrics`, see the `metrics` module.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Notifies `observer` of the events of every session, see the `observer` module.
+    pub fn with_observer(mut self, observer: Arc<dyn SessionObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Returns the local address the server is listening on.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts and serves connections forever, errors while accepting a connection are logged
+    /// and do not stop the server.
+    ///
+    /// # Returns
+    ///
+    /// A future that never resolves.
+    pub async fn run(self) -> crate::Result<()> {
+        self.run_until(std::future::pending()).await
+    }
+
+    /// Accepts and serves connections until `shutdown` resolves. Once it does the server stops
+    /// accepting new connections and waits for in-flight sessions to finish, aborting those that
+    /// are still running after the shutdown timeout.
+    ///
+    /// The sessions are told about the shutdown: those still negotiating and UDP associations are
+    /// closed right away, while CONNECT and BIND sessions get to finish their relay.
+    ///
+    /// - `shutdown`: The cancellation signal, e.g. `tokio::signal::ctrl_c()`.
+    ///
+    /// # Returns
+    ///
+    /// A future that resolves to `crate::Result<()>` once all sessions finished or were aborted.
+    pub async fn run_until<S>(self, shutdown: S) -> crate::Result<()>
+    where
+        S: Future<Output = ()>,
+    {
+        let Self {
+            listener,
+            authenticator,
+            connect_handler,
+            bind_handler,
+            associate_handler,
+            shutdown_timeout,
+            timeouts,
+            http_forwarding,
+            fragment_policy,
+            udp_buffer_size,
+            metrics,
+            observer,
+        } = self;
+
+        let mut sessions = JoinSet::new();
+        let mut backoff = AcceptBackoff::default();
+        let (shutdown_sender, shutdown_receiver) = watch::channel(false);
+        tokio::pin!(shutdown);
+
+        loop {
+            select! {
+                _ = &mut shutdown => {
+                    info!("Shutdown requested, no longer accepting connections");
+                    break;
+                }
+
+                _ = backoff.resumed(), if backoff.is_paused() => {}
+
+                accepted = listener.accept(), if !backoff.is_paused() => {
+                    let (client, addr) = match accepted {
+                        Ok(accepted) => {
+                            backoff.reset();
+                            accepted
+                        }
+                        Err(err) => {
+                            warn!("Failed accepting connection: {}", err);
+                            backoff.failed();
+                            continue;
+                        }
+                    };
+                    debug!("Received connection from: {}", addr);
+                    let local_addr = client.local_addr();
+
+                    let mut socket = Socks5Socket::new(
+                        client,
+                        authenticator(),
+                        connect_handler(),
+                        bind_handler(),
+                        associate_handler(),
+                    )
+                    .with_timeouts(timeouts)
+                    .with_http_forwarding(http_forwarding)
+                    .with_fragment_policy(fragment_policy)
+                    .with_udp_buffer_size(udp_buffer_size)
+                    .with_peer_addr(addr)
+                    .with_shutdown(shutdown_receiver.clone());
+                    if let Ok(local_addr) = local_addr {
+                        socket = socket.with_local_addr(local_addr);
+                    }
+                    if let Some(metrics) = &metrics {
+                        socket = socket.with_metrics(metrics.clone());
+                    }
+                    if let Some(observer) = &observer {
+                        socket = socket.with_observer(observer.clone());
+                    }
+                    let connection = span!(Level::INFO, "connection", %addr);
+                    sessions.spawn(
+                        async move {
+                            if let Err(err) = socket.run().await {
+                                warn!("Failed connection: {:?}", err);
+                            }
+                        }
+                        .instrument(connection),
+                    );
+                }
+
+                Some(finished) = sessions.join_next(), if !sessions.is_empty() => {
+                    if let Err(err) = finished {
+                        warn!("Session task failed: {}", err);
+                    }
+                }
+            }
+        }
+        drop(listener);
+        shutdown_sender.send_replace(true);
+
+        info!("Waiting for {} sessions to finish", sessions.len());
+        let drain = async { while sessions.join_next().await.is_some() {} };
+        if tokio::time::timeout(shutdown_timeout, drain).await.is_err() {
+            warn!(
+                "Shutdown timeout reached, aborting {} sessions",
+                sessions.len()
+            );
+            sessions.shutdown().await;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{net::TcpStream, sync::oneshot};
+
+    use super::*;
+    use crate::{
+        auth::NoAuthAuthenticator,
+        method_handlers::{TunnelAssociate, TunnelBind, TunnelConnect},
+    };
+
+    #[tokio::test]
+    async fn shutdown_closes_negotiating_sessions() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Socks5Server::new(
+            listener,
+            || NoAuthAuthenticator,
+            TunnelConnect::new,
+            TunnelBind::new,
+            TunnelAssociate::new,
+        );
+        let (stop, stopped) = oneshot::channel();
+        let server = tokio::spawn(server.run_until(async {
+            let _ = stopped.await;
+        }));
+
+        // A client that never sends its greeting
+        let _client = TcpStream::connect(addr).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        stop.send(()).unwrap();
+
+        // Well before the 30 seconds shutdown timeout
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("Sessions weren't told about the shutdown")
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn backoff_doubles_until_reset() {
+        let mut backoff = AcceptBackoff::default();
+        assert!(!backoff.is_paused());
+
+        for expected in [5, 10, 20] {
+            let started = Instant::now();
+            backoff.failed();
+            assert!(backoff.is_paused());
+            backoff.resumed().await;
+            assert_eq!(started.elapsed(), Duration::from_millis(expected));
+            assert!(!backoff.is_paused());
+        }
+
+        backoff.reset();
+        let started = Instant::now();
+        backoff.failed();
+        backoff.resumed().await;
+        assert_eq!(started.elapsed(), MIN_ACCEPT_BACKOFF);
+    }
+}
@@ -1,28 +1,91 @@
-use std::io::{self, ErrorKind};
+use std::{
+    io::{self, ErrorKind},
+    net::SocketAddr,
+    sync::Arc,
+};
 
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::{select, sync::watch};
 use tracing::{debug, info, instrument};
 
-use crate::auth::Authenticator;
+use crate::auth::{Authenticator, SessionStream};
+use crate::context::SessionContext;
 
 use crate::method_handlers::{Associate, Bind, Connect};
-use crate::protocol::{AuthMethod, Command, Reply, SocksSocketAddr, RESERVED, VERSION};
+use crate::metrics::Metrics;
+use crate::observer::{SessionId, SessionObserver};
+use crate::protocol::{
+    http::{is_http_method_start, RequestBody},
+    AuthMethod, Command, Reply, SocksSocketAddr, RESERVED, SOCKS4_VERSION, VERSION,
+};
+
+pub use self::fragments::FragmentPolicy;
+use self::timeouts::timeout;
+pub use self::timeouts::Timeouts;
+
+/// The default size of the UDP receive buffer, large enough for any UDP datagram.
+pub(crate) const DEFAULT_UDP_BUFFER_SIZE: usize = u16::MAX as usize;
 
 /// The `Socks5Socket` struct represents a SOCKS5 protocol handler that manages the connection
 /// between a client and a server. It handles authentication, command parsing, and the execution
 /// of the CONNECT, BIND, and UDP ASSOCIATE commands.
+///
+/// SOCKS4 and SOCKS4a clients are served on the same connection type: their CONNECT and BIND
+/// requests are passed to the same handlers, after `Authenticator::authenticate_socks4` accepts
+/// the user id of the request.
 pub struct Socks5Socket<T, A, Connect, Bind, Associate> {
-    inner: T,
+    /// The connection, encapsulated by the authenticator once a SOCKS5 client authenticated.
+    inner: SessionStream<T>,
+    version: Version,
+    /// Bytes read from the client that belong to the relayed data, sent before reading `inner`.
+    prefix: Vec<u8>,
+    /// The rest of the body of a forwarded HTTP request, nothing is relayed after it.
+    request_body: Option<RequestBody>,
+    http_forwarding: bool,
+    fragment_policy: FragmentPolicy,
+    udp_buffer_size: usize,
+    metrics: Option<Arc<Metrics>>,
+    context: SessionContext,
+    observer: Option<Arc<dyn SessionObserver>>,
     authenticator: A,
     connect_handler: Connect,
     bind_handler: Bind,
     associate_handler: Associate,
+    timeouts: Timeouts,
+    /// Set to `true` by the server once it shuts down.
+    shutdown: Option<watch::Receiver<bool>>,
 }
 
 mod associate;
 mod bind;
 mod connect;
+mod fragments;
+mod http;
+mod relay;
+mod socks4;
+mod timeouts;
+
+/// Resolves once `shutdown` is set, or its server is gone. Never resolves without a signal.
+pub(crate) async fn shutdown_requested(shutdown: &mut Option<watch::Receiver<bool>>) {
+    match shutdown {
+        Some(shutdown) => {
+            let _ = shutdown.wait_for(|&shutdown| shutdown).await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// The protocol version spoken by the client, it decides the format of the replies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Version {
+    Socks4,
+    Socks5,
+    /// An HTTP proxy client tunneling with `CONNECT`.
+    HttpConnect,
+    /// An HTTP proxy client whose request is forwarded to the origin server.
+    HttpForward,
+}
 
 impl<T, Auth, C, B, A> Socks5Socket<T, Auth, C, B, A>
 where
@@ -49,16 +112,143 @@ where
         associate_handler: A,
     ) -> Self {
         Self {
-            inner,
+            inner: SessionStream::plain(inner),
+            version: Version::Socks5,
+            prefix: Vec::new(),
+            request_body: None,
+            http_forwarding: false,
+            fragment_policy: FragmentPolicy::default(),
+            udp_buffer_size: DEFAULT_UDP_BUFFER_SIZE,
+            metrics: None,
+            context: SessionContext::new(),
+            observer: None,
             authenticator,
             connect_handler,
             bind_handler,
             associate_handler,
+            timeouts: Timeouts::default(),
+            shutdown: None,
         }
     }
 
-    #[instrument(skip(self))]
+    /// Sets whether HTTP proxy requests with an absolute URI (`GET http://host/path`) are forwarded
+    /// to the origin server through the `Connect` handler. `CONNECT` requests are always served.
+    /// Disabled by default.
+    ///
+    /// Only one request is forwarded per connection: the origin server is asked to close the
+    /// connection after its response, and what the client sends after the request's body is
+    /// dropped.
+    pub fn with_http_forwarding(mut self, enabled: bool) -> Self {
+        self.http_forwarding = enabled;
+        self
+    }
+
+    /// Sets what a UDP association does with datagrams the client sent fragmented, see
+    /// `FragmentPolicy`. Fragments are reassembled by default.
+    pub fn with_fragment_policy(mut self, fragment_policy: FragmentPolicy) -> Self {
+        self.fragment_policy = fragment_policy;
+        self
+    }
+
+    /// Sets the size of the buffer UDP datagrams are received into, in both directions.
+    /// Datagrams from the client include the SOCKS5 UDP header. Larger datagrams are dropped and
+    /// counted instead of being relayed truncated. Defaults to 65,535 bytes, which fits any
+    /// datagram, so it only needs to be set to reduce the memory used by every association.
+    /// Datagrams for the client that don't fit in a datagram once the header is added are dropped
+    /// and counted as well.
+    pub fn with_udp_buffer_size(mut self, size: usize) -> Self {
+        self.udp_buffer_size = size;
+        self
+    }
+
+    /// Records the session in `metrics`, see the `metrics` module.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Notifies `observer` of the events of the session, see the `observer` module.
+    pub fn with_observer(mut self, observer: Arc<dyn SessionObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Sets the address of the client, reported to the observer and in the `SessionContext`.
+    /// `Socks5Server` sets it for the sessions it accepts.
+    pub fn with_peer_addr(mut self, peer_addr: SocketAddr) -> Self {
+        self.context.set_peer_addr(peer_addr);
+        self
+    }
+
+    /// Sets the signal of the server's shutdown, once it's `true` the session stops if it's still
+    /// negotiating or relaying UDP datagrams.
+    pub(crate) fn with_shutdown(mut self, shutdown: watch::Receiver<bool>) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Sets the local address the client connected to, given in the `SessionContext`.
+    /// `Socks5Server` sets it for the sessions it accepts.
+    pub fn with_local_addr(mut self, local_addr: SocketAddr) -> Self {
+        self.context.set_local_addr(local_addr);
+        self
+    }
+
+    /// Returns the id of the session, carried by the events reported to the observer.
+    pub fn session_id(&self) -> SessionId {
+        self.context.session_id()
+    }
+
+    /// Returns the context of the session, see `SessionContext`.
+    pub fn context(&self) -> &SessionContext {
+        &self.context
+    }
+
+    /// Sets the deadlines enforced on the different phases of the session, see `Timeouts`.
+    /// By default no timeouts are enforced.
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
     async fn socks_request(&mut self) -> io::Result<(Command, SocksSocketAddr, Auth::Credentials)> {
+        let handshake_timeout = self.timeouts.handshake;
+        let request = timeout(handshake_timeout, self.socks_request_inner())
+            .await
+            .unwrap_or_else(|| Err(io::Error::new(ErrorKind::TimedOut, "Handshake timed out")));
+
+        if let Some(metrics) = &self.metrics {
+            match &request {
+                Ok((command, _, _)) => metrics.command(*command),
+                Err(err) => metrics.handshake_failed(err),
+            }
+        }
+        if let Ok((command, addr, _)) = &request {
+            self.notify(|observer, session| observer.request_parsed(session, *command, addr));
+        }
+        request
+    }
+
+    #[instrument(skip(self))]
+    async fn socks_request_inner(
+        &mut self,
+    ) -> io::Result<(Command, SocksSocketAddr, Auth::Credentials)> {
+        let version = self.inner.read_u8().await?;
+        match version {
+            VERSION => {}
+            SOCKS4_VERSION => {
+                self.version = Version::Socks4;
+                return self.socks4_request().await;
+            }
+            byte if is_http_method_start(byte) => return self.http_request(byte).await,
+            _ => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "Unexpected protocol version",
+                ))
+            }
+        }
+
         let credentials = self.authenticate().await?;
 
         let command = self.parse_request().await?;
@@ -73,15 +263,33 @@ where
         let methods = self.parse_methods().await?;
         debug!("Received methods: {:?}", methods);
 
-        let method = self.authenticator.select_method(&methods);
+        let method = self.authenticator.select_method(&methods, &self.context);
+        self.context.set_method(method);
         debug!("Selected method: {:?}", method);
+        if let Some(metrics) = &self.metrics {
+            metrics.auth_method_selected(method);
+        }
+        self.notify(|observer, session| observer.method_selected(session, method));
         self.write_auth_method(method).await?;
 
-        let credentials = match self
-            .authenticator
-            .authenticate(&mut self.inner, method)
-            .await
-        {
+        let conn = self
+            .inner
+            .plain_mut()
+            .ok_or_else(|| io::Error::other("Session is already encapsulated"))?;
+        let authentication = timeout(
+            self.timeouts.auth,
+            self.authenticator
+                .authenticate(conn, method, &mut self.context),
+        )
+        .await
+        .unwrap_or_else(|| {
+            Err(io::Error::new(
+                ErrorKind::TimedOut,
+                "Authentication timed out",
+            ))
+        });
+        let authentication = self.auth_outcome(authentication);
+        let credentials = match authentication {
             Ok(Some(credentials)) => credentials,
 
             Ok(None) => {
@@ -95,8 +303,43 @@ where
             }
         };
         debug!("Authentication success");
+
+        if let Some(conn) = self.inner.detach() {
+            self.inner = self.authenticator.encapsulate(conn)?;
+            if self.inner.is_encapsulated() {
+                debug!("Session is encapsulated");
+            }
+        }
         Ok(credentials)
     }
+
+    /// Notifies the observer of the outcome of an authentication, and returns it.
+    fn auth_outcome<Cr>(&self, authentication: io::Result<Option<Cr>>) -> io::Result<Option<Cr>> {
+        match &authentication {
+            Ok(Some(_)) => {
+                let user = self.authenticator.user_name();
+                self.notify(|observer, session| observer.auth_succeeded(session, user));
+            }
+            Ok(None) => {
+                let err = io::Error::new(ErrorKind::PermissionDenied, "Authentication failed");
+                self.notify(|observer, session| observer.auth_failed(session, &err));
+            }
+            Err(err) => self.notify(|observer, session| observer.auth_failed(session, err)),
+        }
+        authentication
+    }
+}
+
+impl<T, Auth, C, B, A> Socks5Socket<T, Auth, C, B, A> {
+    /// Calls `event` with the observer and the session id, if the session has an observer.
+    fn notify<F>(&self, event: F)
+    where
+        F: FnOnce(&dyn SessionObserver, SessionId),
+    {
+        if let Some(observer) = &self.observer {
+            event(observer.as_ref(), self.context.session_id());
+        }
+    }
 }
 
 impl<T, Auth, C, B, A> Socks5Socket<T, Auth, C, B, A>
@@ -116,13 +359,64 @@ where
     ///
     /// A future that resolves to `crate::Result<()>` indicating the success or failure of the operation.
     pub async fn run(mut self) -> crate::Result<()> {
-        let (command, addr, credentials) = self.socks_request().await?;
-        match command {
-            Command::Connect => self.connect(addr, credentials).await?,
-            Command::Bind => self.bind(addr, credentials).await?,
-            Command::UdpAssociate => self.associate(addr, credentials).await?,
+        let _active = self
+            .metrics
+            .as_ref()
+            .map(|metrics| metrics.session_started());
+        let peer_addr = self.context.peer_addr();
+        self.notify(|observer, session| observer.connection_accepted(session, peer_addr));
+        // Closes the session for the observer even if this future is dropped or panics
+        let closed = ClosedGuard {
+            observer: self.observer.clone(),
+            session: self.session_id(),
         };
-        Ok(())
+
+        let mut shutdown = self.shutdown.clone();
+        let res = async move {
+            let (command, addr, credentials) = select! {
+                request = self.socks_request() => request?,
+                _ = shutdown_requested(&mut shutdown) => {
+                    debug!("Server is shutting down, closing the session during the handshake");
+                    return Err(io::Error::new(
+                        ErrorKind::ConnectionAborted,
+                        "Server is shutting down",
+                    ))?;
+                }
+            };
+            match command {
+                Command::Connect => self.connect(addr, credentials).await,
+                Command::Bind => self.bind(addr, credentials).await,
+                Command::UdpAssociate => self.associate(addr, credentials).await,
+            }
+        }
+        .await;
+
+        closed.close(res.as_ref().err());
+        res
+    }
+}
+
+/// Notifies the observer that a session closed, exactly once: with the result of the session, or
+/// when dropped before, e.g. when the task of the session is aborted or panics.
+struct ClosedGuard {
+    observer: Option<Arc<dyn SessionObserver>>,
+    session: SessionId,
+}
+
+impl ClosedGuard {
+    fn close(mut self, error: Option<&crate::Socks5Error>) {
+        if let Some(observer) = self.observer.take() {
+            observer.connection_closed(self.session, error);
+        }
+    }
+}
+
+impl Drop for ClosedGuard {
+    fn drop(&mut self) {
+        if let Some(observer) = self.observer.take() {
+            let err = io::Error::new(ErrorKind::ConnectionAborted, "Session was aborted").into();
+            observer.connection_closed(self.session, Some(&err));
+        }
     }
 }
 
@@ -136,6 +430,17 @@ where
         reply: Reply,
         bnd_address: SocksSocketAddr,
     ) -> io::Result<()> {
+        if let Some(metrics) = &self.metrics {
+            metrics.reply_sent(reply);
+        }
+        self.notify(|observer, session| observer.reply_sent(session, reply, &bnd_address));
+
+        match self.version {
+            Version::Socks4 => return self.socks4_reply(reply.into(), bnd_address).await,
+            Version::HttpConnect | Version::HttpForward => return self.http_reply(reply).await,
+            Version::Socks5 => {}
+        }
+
         self.inner.write_u8(VERSION).await?;
 
         self.inner.write_u8(reply.to_u8()).await?;
@@ -157,14 +462,6 @@ where
     }
 
     async fn parse_methods(&mut self) -> io::Result<Vec<AuthMethod>> {
-        let version = self.inner.read_u8().await?;
-        if version != VERSION {
-            return Err(io::Error::new(
-                ErrorKind::InvalidData,
-                "Unexpected protocol version",
-            ));
-        }
-
         let nmethods = self.inner.read_u8().await?;
         if nmethods < 1 {
             return Err(io::Error::new(
@@ -216,3 +513,89 @@ where
         SocksSocketAddr::read(&mut self.inner).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Mutex, time::Duration};
+
+    use tokio::io::{duplex, DuplexStream};
+
+    use crate::{
+        auth::NoAuthAuthenticator,
+        method_handlers::{TunnelAssociate, TunnelBind, TunnelConnect},
+    };
+
+    use super::*;
+
+    /// Records the sessions that were accepted and closed.
+    #[derive(Default)]
+    struct Sessions {
+        accepted: Mutex<Vec<SessionId>>,
+        closed: Mutex<Vec<(SessionId, Option<ErrorKind>)>>,
+    }
+
+    impl SessionObserver for Sessions {
+        fn connection_accepted(&self, session: SessionId, _peer_addr: Option<SocketAddr>) {
+            self.accepted.lock().unwrap().push(session);
+        }
+
+        fn connection_closed(&self, session: SessionId, error: Option<&crate::Socks5Error>) {
+            let kind = error.map(|err| match err {
+                crate::Socks5Error::IoError(err) => err.kind(),
+                crate::Socks5Error::Socks5Error(_) => ErrorKind::Other,
+            });
+            self.closed.lock().unwrap().push((session, kind));
+        }
+    }
+
+    type TestSocket =
+        Socks5Socket<DuplexStream, NoAuthAuthenticator, TunnelConnect, TunnelBind, TunnelAssociate>;
+
+    /// Returns a session observed by `observer`, and the client end of its connection.
+    fn session(observer: &Arc<Sessions>) -> (TestSocket, DuplexStream) {
+        let (server, client) = duplex(1024);
+        let socket = Socks5Socket::new(
+            server,
+            NoAuthAuthenticator,
+            TunnelConnect::new(),
+            TunnelBind::new(),
+            TunnelAssociate::new(),
+        )
+        .with_observer(observer.clone());
+        (socket, client)
+    }
+
+    #[tokio::test]
+    async fn closes_finished_sessions_once() {
+        let observer = Arc::new(Sessions::default());
+        let (socket, client) = session(&observer);
+        let session = socket.session_id();
+
+        drop(client);
+        assert!(socket.run().await.is_err());
+        assert_eq!(*observer.accepted.lock().unwrap(), [session]);
+        assert_eq!(
+            *observer.closed.lock().unwrap(),
+            [(session, Some(ErrorKind::UnexpectedEof))]
+        );
+    }
+
+    #[tokio::test]
+    async fn closes_aborted_sessions_once() {
+        let observer = Arc::new(Sessions::default());
+        let (socket, _client) = session(&observer);
+        let session = socket.session_id();
+
+        let task = tokio::spawn(socket.run());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(*observer.accepted.lock().unwrap(), [session]);
+        assert!(observer.closed.lock().unwrap().is_empty());
+
+        task.abort();
+        assert!(task.await.unwrap_err().is_cancelled());
+        assert_eq!(
+            *observer.closed.lock().unwrap(),
+            [(session, Some(ErrorKind::ConnectionAborted))]
+        );
+    }
+}
@@ -6,21 +6,35 @@ use std::{
 use tokio::{
     io::{AsyncRead, AsyncReadExt, AsyncWrite},
     select,
+    time::{sleep_until, Instant},
 };
 use tracing::{debug, error, info, instrument, trace, warn};
 
 use crate::{
     auth::Authenticator,
     method_handlers::Associate,
-    protocol::{Reply, SocksSocketAddr},
+    observer::{Direction, RelayStats},
+    protocol::{Reply, SocksSocketAddr, UdpMessage},
     Socks5Error,
 };
 
-use self::udp_message::UdpMessage;
+use super::{
+    fragments::{FragmentPolicy, ReassemblyQueue},
+    shutdown_requested, Socks5Socket,
+};
+
+/// The largest UDP payload over IPv4, larger datagrams can't be sent.
+const MAX_UDP_PAYLOAD: usize = 65_507;
 
-use super::Socks5Socket;
+/// The length of the UDP request header wrapping a datagram from `source` for the client.
+fn udp_header_len(source: &SocketAddr) -> usize {
+    // RSV, FRAG and ATYP followed by the address and the port
+    4 + match source {
+        SocketAddr::V4(_) => 4,
+        SocketAddr::V6(_) => 16,
+    } + 2
+}
 
-mod udp_message;
 async fn addrs_match(client_addrs: &[SocketAddr], udp_addr: &SocketAddr) -> bool {
     for sa in client_addrs.iter() {
         if sa.port() == 0 {
@@ -28,19 +42,17 @@ async fn addrs_match(client_addrs: &[SocketAddr], udp_addr: &SocketAddr) -> bool
         }
 
         match (sa, udp_addr) {
-            (SocketAddr::V4(sa_v4), SocketAddr::V4(udp_v4)) => {
+            (SocketAddr::V4(sa_v4), SocketAddr::V4(udp_v4))
                 if (*sa_v4.ip() == Ipv4Addr::UNSPECIFIED || sa_v4.ip() == udp_v4.ip())
-                    && sa_v4.port() == udp_v4.port()
-                {
-                    return true;
-                }
+                    && sa_v4.port() == udp_v4.port() =>
+            {
+                return true;
             }
-            (SocketAddr::V6(sa_v6), SocketAddr::V6(udp_v6)) => {
+            (SocketAddr::V6(sa_v6), SocketAddr::V6(udp_v6))
                 if (*sa_v6.ip() == Ipv6Addr::UNSPECIFIED || sa_v6.ip() == udp_v6.ip())
-                    && sa_v6.port() == udp_v6.port()
-                {
-                    return true;
-                }
+                    && sa_v6.port() == udp_v6.port() =>
+            {
+                return true;
             }
             _ => {}
         }
@@ -62,7 +74,7 @@ where
     ) -> crate::Result<A::Connection> {
         let (localaddr, conn) = self
             .associate_handler
-            .bind(credentials)
+            .bind(credentials, &self.context)
             .await
             .map_err(|err| Socks5Error::Socks5Error(err.into()))?;
 
@@ -80,16 +92,30 @@ where
         credentials: &Auth::Credentials,
     ) -> crate::Result<()> {
         let mut verified_client_addr = None;
-        let mut buf = [0; 4096];
+        let mut reassembly = ReassemblyQueue::default();
+        // One extra byte tells datagrams that filled the buffer apart from truncated ones
+        let mut buf = vec![0; self.udp_buffer_size + 1];
+        let mut truncated = 0u64;
         let mut tcp_buf = [0; 1];
-        loop {
+        let udp_idle = self.timeouts.udp_idle;
+        let mut idle_deadline = udp_idle.map(|idle| Instant::now() + idle);
+        let started = Instant::now();
+        let mut stats = RelayStats::default();
+        let res = loop {
             let (n, source) = select! {
                 result = self.associate_handler.recv_from(&mut conn,&mut buf, credentials) => {
-                    let Ok((n, source)) = result else {
-                        continue;
-                    };
-
-                    (n,source)
+                    match result {
+                        Ok((n, source)) => (n, source),
+                        Err(Socks5Error::Socks5Error(reply)) => {
+                            info!("Associate handler closed the association: {:?}", reply);
+                            break Err(io::Error::new(
+                                io::ErrorKind::ConnectionAborted,
+                                "Udp association closed by the handler",
+                            )
+                            .into());
+                        }
+                        Err(_) => continue,
+                    }
                 }
 
 
@@ -112,8 +138,30 @@ where
                         }
                     };
                 }
+
+                _ = shutdown_requested(&mut self.shutdown) => {
+                    info!("Server is shutting down, closing the udp association");
+                    break Ok(());
+                }
+
+                _ = sleep_until(idle_deadline.unwrap_or_else(Instant::now)), if idle_deadline.is_some() => {
+                    info!("Udp association was idle for too long, closing");
+                    break Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "Udp association was idle for too long",
+                    )
+                    .into());
+                }
             };
             debug!("Received {} bytes from: {}", n, source);
+            if n > self.udp_buffer_size {
+                self.count_truncated(&mut truncated);
+                warn!(
+                    "Dropped datagram from {} larger than the {} bytes buffer ({} so far)",
+                    source, self.udp_buffer_size, truncated
+                );
+                continue;
+            }
             trace!("Bytes: {:?}", &buf[..n]);
 
             if verified_client_addr.is_none() && addrs_match(client_addrs, &source).await {
@@ -129,40 +177,106 @@ where
                 continue;
             };
 
-            let res = if verified_client_addr == source {
-                self.forward_to_server(&mut conn, &buf[..n], credentials)
+            if verified_client_addr == source {
+                let forwarded = match self
+                    .forward_to_server(&mut conn, &buf[..n], &mut reassembly, credentials)
                     .await
+                {
+                    Ok(forwarded) => forwarded,
+                    Err(err) => {
+                        debug!("Failed forwarding datagram of the client: {}", err);
+                        continue;
+                    }
+                };
+                stats.bytes_up += forwarded as u64;
             } else {
-                self.forward_to_client(
-                    &mut conn,
-                    &buf[..n],
-                    source,
-                    verified_client_addr,
-                    credentials,
-                )
-                .await
-            };
-            if res.is_err() {
-                continue;
+                // The datagram is wrapped in the UDP request header, which has to fit as well
+                if n + udp_header_len(&source) > MAX_UDP_PAYLOAD {
+                    self.count_truncated(&mut truncated);
+                    warn!(
+                        "Dropped datagram of {} bytes from {}, too large for the client once \
+                         wrapped ({} so far)",
+                        n, source, truncated
+                    );
+                    continue;
+                }
+                let forwarded = match self
+                    .forward_to_client(
+                        &mut conn,
+                        &buf[..n],
+                        source,
+                        verified_client_addr,
+                        credentials,
+                    )
+                    .await
+                {
+                    Ok(forwarded) => forwarded,
+                    Err(err) => {
+                        debug!("Failed forwarding datagram from {}: {}", source, err);
+                        continue;
+                    }
+                };
+                stats.bytes_down += forwarded as u64;
             }
+            idle_deadline = udp_idle.map(|idle| Instant::now() + idle);
+        };
+
+        if truncated > 0 {
+            info!("Udp association dropped {} truncated datagrams", truncated);
         }
+        stats.duration = started.elapsed();
+        self.notify(|observer, session| {
+            observer.relay_finished(session, &stats, res.as_ref().err())
+        });
+        res
     }
 
+    /// Counts a datagram dropped because it doesn't fit in the buffer or in a datagram.
+    fn count_truncated(&self, truncated: &mut u64) {
+        *truncated += 1;
+        if let Some(metrics) = &self.metrics {
+            metrics.udp_truncated();
+        }
+    }
+
+    /// Forwards a datagram of the client, returns the size of the payload forwarded.
     async fn forward_to_server(
         &mut self,
         conn: &mut A::Connection,
         buf: &[u8],
+        reassembly: &mut ReassemblyQueue,
         credentials: &Auth::Credentials,
     ) -> crate::Result<usize> {
         let udp_message = UdpMessage::parse(buf).await?;
-        let dst = &*udp_message.dst.to_socket_addr().await?;
-        debug!("Sending {} bytes to: {:?}", udp_message.data.len(), dst);
+        let (dst, data) = match self.fragment_policy {
+            FragmentPolicy::Drop if udp_message.fragment_number != 0 => {
+                debug!(
+                    "Dropped fragment {:#04x} for: {}",
+                    udp_message.fragment_number, udp_message.dst
+                );
+                return Ok(0);
+            }
+            FragmentPolicy::Drop => (udp_message.dst, udp_message.data.into()),
+            FragmentPolicy::Reassemble => match reassembly.push(udp_message) {
+                Some(datagram) => datagram,
+                None => return Ok(0),
+            },
+        };
+        debug!("Sending {} bytes to: {}", data.len(), dst);
 
         self.associate_handler
-            .send_to(conn, udp_message.data, dst, credentials)
-            .await
+            .send_to(conn, &data, dst.clone(), credentials)
+            .await?;
+        if let Some(metrics) = &self.metrics {
+            metrics.relayed_up(data.len());
+        }
+        self.notify(|observer, session| {
+            observer.udp_datagram_forwarded(session, Direction::ToDestination, &dst, data.len())
+        });
+        Ok(data.len())
     }
 
+    /// Forwards a datagram to the client, returns the size of the payload forwarded.
     async fn forward_to_client(
         &mut self,
         conn: &mut A::Connection,
@@ -184,8 +298,15 @@ where
             client
         );
         self.associate_handler
-            .send_to(conn, response, client, credentials)
-            .await
+            .send_to_client(conn, response, client, credentials)
+            .await?;
+        if let Some(metrics) = &self.metrics {
+            metrics.relayed_down(buf.len());
+        }
+        self.notify(|observer, session| {
+            observer.udp_datagram_forwarded(session, Direction::ToClient, &source.into(), buf.len())
+        });
+        Ok(buf.len())
     }
 
     #[instrument(skip_all)]
@@ -198,8 +319,9 @@ where
             let credentials = credentials;
             let client_addrs = addr;
 
-            let client_addrs = &*client_addrs
-                .to_socket_addr()
+            let client_addrs = &*self
+                .associate_handler
+                .resolve(&client_addrs)
                 .await
                 .map_err(Socks5Error::from)?;
 
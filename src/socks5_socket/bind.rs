@@ -8,7 +8,7 @@ use crate::{
     Socks5Error,
 };
 
-use super::Socks5Socket;
+use super::{relay::relay, timeouts::timeout, Socks5Socket};
 
 impl<T, Auth, C, B, A> Socks5Socket<T, Auth, C, B, A>
 where
@@ -26,7 +26,7 @@ where
         let bind_inner = || async {
             let (localaddr, server) = self
                 .bind_handler
-                .bind(addr, &credentials)
+                .bind(addr, &credentials, &self.context)
                 .await
                 .map_err(|err| Socks5Error::Socks5Error(err.into()))?;
 
@@ -34,11 +34,13 @@ where
 
             self.reply(Reply::Success, localaddr.into()).await?;
 
-            let (client, client_addr) = self
-                .bind_handler
-                .accept(server, &credentials)
-                .await
-                .map_err(|err| crate::Socks5Error::Socks5Error(err.into()))?;
+            let (client, client_addr) = timeout(
+                self.timeouts.connect,
+                self.bind_handler.accept(server, &credentials),
+            )
+            .await
+            .ok_or(Socks5Error::Socks5Error(Reply::TTLExpired))?
+            .map_err(|err| crate::Socks5Error::Socks5Error(err.into()))?;
 
             debug!("Accepted client {}, starting to listen", client_addr);
 
@@ -59,8 +61,14 @@ where
             Ok(conn) => conn,
         };
 
-        self.bind_handler
-            .start_listening(self.inner, conn, credentials)
-            .await
+        let bind_handler = self.bind_handler;
+        let (res, stats) = relay(self.inner, self.timeouts.idle, self.metrics, |server| {
+            bind_handler.start_listening(server, conn, credentials)
+        })
+        .await;
+        if let Some(observer) = &self.observer {
+            observer.relay_finished(self.context.session_id(), &stats, res.as_ref().err());
+        }
+        res
     }
 }
@@ -1,4 +1,7 @@
-use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    time::Instant,
+};
 use tracing::{debug, info, instrument};
 
 use crate::{
@@ -8,7 +11,11 @@ use crate::{
     Socks5Error,
 };
 
-use super::Socks5Socket;
+use super::{
+    relay::{relay, Prefixed},
+    timeouts::timeout,
+    Socks5Socket,
+};
 impl<T, Auth, C, B, A> Socks5Socket<T, Auth, C, B, A>
 where
     Self: Unpin + Send,
@@ -23,14 +30,28 @@ where
         credentials: Auth::Credentials,
     ) -> crate::Result<()> {
         let connect_inner = || async {
-            let conn = self
-                .connect_handler
-                .establish_connection(addr.clone(), credentials)
-                .await
-                .map_err(|err| Socks5Error::Socks5Error(err.into()))?;
+            let started = Instant::now();
+            let conn = timeout(
+                self.timeouts.connect,
+                self.connect_handler
+                    .establish_connection(addr.clone(), credentials, &self.context),
+            )
+            .await
+            .ok_or(Socks5Error::Socks5Error(Reply::TTLExpired))?
+            .map_err(|err| Socks5Error::Socks5Error(err.into()))?;
 
             debug!("Connection established with: {}", addr);
-            self.reply(Reply::Success, addr.clone()).await?;
+            if let Some(metrics) = &self.metrics {
+                metrics.connected(started.elapsed());
+            }
+            let connected_addr = self.connect_handler.reply_address(&conn);
+            if let Some(connected_addr) = connected_addr {
+                self.notify(|observer, session| {
+                    observer.destination_connected(session, connected_addr)
+                });
+            }
+            let bound_addr = connected_addr.map_or_else(|| addr.clone(), SocksSocketAddr::from);
+            self.reply(Reply::Success, bound_addr).await?;
 
             info!("Connection with {} closed succefully", addr);
 
@@ -49,9 +70,17 @@ where
             Ok(conn) => conn,
         };
 
-        self.connect_handler
-            .start_listening(self.inner, conn)
-            .await?;
-        Ok(())
+        let connect_handler = self.connect_handler;
+        let (res, stats) = relay(
+            Prefixed::new(self.inner, self.prefix).with_body(self.request_body),
+            self.timeouts.idle,
+            self.metrics,
+            |client| connect_handler.start_listening(client, conn),
+        )
+        .await;
+        if let Some(observer) = &self.observer {
+            observer.relay_finished(self.context.session_id(), &stats, res.as_ref().err());
+        }
+        res
     }
 }
@@ -0,0 +1,221 @@
+use std::{borrow::Cow, time::Duration};
+
+use tokio::time::Instant;
+use tracing::debug;
+
+use crate::protocol::{SocksSocketAddr, UdpMessage};
+
+/// How long the fragments of a sequence are kept, RFC 1928 requires at least 5 seconds.
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(5);
+/// The high-order bit of the FRAG field, set on the last fragment of a sequence.
+const END_OF_SEQUENCE: u8 = 0x80;
+/// The largest datagram a sequence may be reassembled into.
+const MAX_REASSEMBLED_LEN: usize = u16::MAX as usize;
+
+/// The `FragmentPolicy` enum decides what a UDP association does with datagrams the client sent
+/// fragmented, i.e. with a non-zero FRAG field.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FragmentPolicy {
+    /// Reassembles fragments as described in RFC 1928 and relays the whole datagram once the last
+    /// fragment arrived. Sequences that time out or arrive out of order are abandoned.
+    #[default]
+    Reassemble,
+
+    /// Drops every fragmented datagram, only standalone datagrams are relayed.
+    Drop,
+}
+
+/// A sequence of fragments being reassembled.
+struct Sequence {
+    dst: SocksSocketAddr,
+    data: Vec<u8>,
+    position: u8,
+    deadline: Instant,
+}
+
+/// The reassembly queue of a UDP association.
+#[derive(Default)]
+pub(crate) struct ReassemblyQueue {
+    sequence: Option<Sequence>,
+}
+
+impl ReassemblyQueue {
+    /// Adds a datagram received from the client, returns the destination and payload to relay
+    /// once a whole datagram is available.
+    pub(crate) fn push<'a>(
+        &mut self,
+        message: UdpMessage<'a>,
+    ) -> Option<(SocksSocketAddr, Cow<'a, [u8]>)> {
+        if self
+            .sequence
+            .as_ref()
+            .is_some_and(|sequence| sequence.deadline <= Instant::now())
+        {
+            debug!("Reassembly timer expired, abandoning fragments");
+            self.sequence = None;
+        }
+
+        if message.fragment_number == 0 {
+            self.abandon("Standalone datagram received");
+            return Some((message.dst, Cow::Borrowed(message.data)));
+        }
+
+        let position = message.fragment_number & !END_OF_SEQUENCE;
+        let end = message.fragment_number & END_OF_SEQUENCE != 0;
+        let expected = self
+            .sequence
+            .as_ref()
+            .map_or(1, |sequence| sequence.position.saturating_add(1));
+
+        match position {
+            1 => {
+                self.abandon("New fragment sequence started");
+                if end {
+                    return Some((message.dst, Cow::Borrowed(message.data)));
+                }
+                self.sequence = Some(Sequence {
+                    dst: message.dst,
+                    data: message.data.to_vec(),
+                    position,
+                    deadline: Instant::now() + REASSEMBLY_TIMEOUT,
+                });
+                None
+            }
+            position if position == expected => {
+                let sequence = self.sequence.as_mut()?;
+                if sequence.dst != message.dst {
+                    self.abandon("Fragment destination changed");
+                    return None;
+                }
+                if sequence.data.len() + message.data.len() > MAX_REASSEMBLED_LEN {
+                    self.abandon("Reassembled datagram too large");
+                    return None;
+                }
+                sequence.data.extend_from_slice(message.data);
+                sequence.position = position;
+
+                if !end {
+                    return None;
+                }
+                let sequence = self.sequence.take()?;
+                debug!(
+                    "Reassembled {} fragments into {} bytes",
+                    sequence.position,
+                    sequence.data.len()
+                );
+                Some((sequence.dst, Cow::Owned(sequence.data)))
+            }
+            _ => {
+                self.abandon("Fragment received out of order");
+                None
+            }
+        }
+    }
+
+    fn abandon(&mut self, reason: &str) {
+        if let Some(sequence) = self.sequence.take() {
+            debug!("{}, abandoning {} fragments", reason, sequence.position);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use super::*;
+
+    fn dst(port: u16) -> SocksSocketAddr {
+        SocketAddr::from((Ipv4Addr::new(192, 0, 2, 1), port)).into()
+    }
+
+    fn fragment(fragment_number: u8, data: &[u8]) -> UdpMessage<'_> {
+        UdpMessage {
+            fragment_number,
+            dst: dst(53),
+            data,
+        }
+    }
+
+    #[tokio::test]
+    async fn relays_standalone_datagrams() {
+        let mut queue = ReassemblyQueue::default();
+        let (addr, data) = queue.push(fragment(0, b"whole")).unwrap();
+        assert_eq!(addr, dst(53));
+        assert_eq!(&data[..], b"whole");
+    }
+
+    #[tokio::test]
+    async fn reassembles_fragments_in_order() {
+        let mut queue = ReassemblyQueue::default();
+        assert!(queue.push(fragment(1, b"ab")).is_none());
+        assert!(queue.push(fragment(2, b"cd")).is_none());
+        let (addr, data) = queue.push(fragment(3 | END_OF_SEQUENCE, b"ef")).unwrap();
+        assert_eq!(addr, dst(53));
+        assert_eq!(&data[..], b"abcdef");
+
+        // A single fragment sequence
+        let (_, data) = queue.push(fragment(1 | END_OF_SEQUENCE, b"gh")).unwrap();
+        assert_eq!(&data[..], b"gh");
+    }
+
+    #[tokio::test]
+    async fn abandons_fragments_out_of_order() {
+        let mut queue = ReassemblyQueue::default();
+        assert!(queue.push(fragment(1, b"ab")).is_none());
+        assert!(queue.push(fragment(3, b"ef")).is_none());
+        assert!(queue.push(fragment(2 | END_OF_SEQUENCE, b"cd")).is_none());
+
+        // Without a first fragment
+        assert!(queue.push(fragment(2 | END_OF_SEQUENCE, b"cd")).is_none());
+    }
+
+    #[tokio::test]
+    async fn sequence_ends_with_end_of_sequence() {
+        let mut queue = ReassemblyQueue::default();
+        assert!(queue.push(fragment(1, b"ab")).is_none());
+        assert!(queue.push(fragment(2 | END_OF_SEQUENCE, b"cd")).is_some());
+        // Fragments after the end belong to no sequence
+        assert!(queue.push(fragment(3 | END_OF_SEQUENCE, b"ef")).is_none());
+    }
+
+    #[tokio::test]
+    async fn standalone_datagram_abandons_sequence() {
+        let mut queue = ReassemblyQueue::default();
+        assert!(queue.push(fragment(1, b"ab")).is_none());
+        assert!(queue.push(fragment(0, b"whole")).is_some());
+        assert!(queue.push(fragment(2 | END_OF_SEQUENCE, b"cd")).is_none());
+    }
+
+    #[tokio::test]
+    async fn abandons_sequence_with_changed_destination() {
+        let mut queue = ReassemblyQueue::default();
+        assert!(queue.push(fragment(1, b"ab")).is_none());
+        let other = UdpMessage {
+            fragment_number: 2 | END_OF_SEQUENCE,
+            dst: dst(54),
+            data: b"cd",
+        };
+        assert!(queue.push(other).is_none());
+    }
+
+    #[tokio::test]
+    async fn abandons_sequence_too_large() {
+        let mut queue = ReassemblyQueue::default();
+        let data = vec![0; MAX_REASSEMBLED_LEN / 2 + 1];
+        assert!(queue.push(fragment(1, &data)).is_none());
+        assert!(queue.push(fragment(2 | END_OF_SEQUENCE, &data)).is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn abandons_sequence_after_timeout() {
+        let mut queue = ReassemblyQueue::default();
+        assert!(queue.push(fragment(1, b"ab")).is_none());
+        tokio::time::advance(REASSEMBLY_TIMEOUT - Duration::from_millis(1)).await;
+        assert!(queue.push(fragment(2 | END_OF_SEQUENCE, b"cd")).is_some());
+
+        assert!(queue.push(fragment(1, b"ab")).is_none());
+        tokio::time::advance(REASSEMBLY_TIMEOUT).await;
+        assert!(queue.push(fragment(2 | END_OF_SEQUENCE, b"cd")).is_none());
+    }
+}
@@ -0,0 +1,505 @@
+use std::io::{self, ErrorKind};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tracing::{debug, info, instrument};
+use zeroize::Zeroizing;
+
+use crate::{
+    auth::{username_password_authenticator::User, Authenticator},
+    protocol::{
+        http::{base64_decode, http_status, parse_authority, RequestBody, MAX_HTTP_HEADER},
+        Command, Reply, SocksSocketAddr,
+    },
+};
+
+use super::{timeouts::timeout, Socks5Socket, Version};
+
+/// Hop-by-hop headers that are dropped when forwarding a request to the origin server.
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "proxy-authorization",
+    "proxy-connection",
+    "connection",
+    "keep-alive",
+];
+
+/// A parsed HTTP request header.
+struct HttpRequest<'a> {
+    method: &'a str,
+    target: &'a str,
+    version: &'a str,
+    headers: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> HttpRequest<'a> {
+    fn parse(header: &'a [u8]) -> io::Result<Self> {
+        let invalid = || io::Error::new(ErrorKind::InvalidData, "Invalid HTTP request");
+
+        let header = std::str::from_utf8(header).map_err(|_| invalid())?;
+        let mut lines = header.split("\r\n");
+
+        let mut request_line = lines.next().ok_or_else(invalid)?.split(' ');
+        let (Some(method), Some(target), Some(version), None) = (
+            request_line.next(),
+            request_line.next(),
+            request_line.next(),
+            request_line.next(),
+        ) else {
+            return Err(invalid());
+        };
+        if !version.starts_with("HTTP/1.") {
+            return Err(invalid());
+        }
+
+        let headers = lines
+            .take_while(|line| !line.is_empty())
+            .map(|line| {
+                let (name, value) = line.split_once(':').ok_or_else(invalid)?;
+                Ok((name.trim(), value.trim()))
+            })
+            .collect::<io::Result<_>>()?;
+
+        Ok(Self {
+            method,
+            target,
+            version,
+            headers,
+        })
+    }
+
+    fn header(&self, name: &str) -> Option<&'a str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|&(_, value)| value)
+    }
+
+    /// The values of every `name` header, comma separated lists are split.
+    fn header_values<'b>(&'b self, name: &'b str) -> impl Iterator<Item = &'a str> + 'b {
+        self.headers
+            .iter()
+            .filter(move |(header, _)| header.eq_ignore_ascii_case(name))
+            .flat_map(|&(_, value)| value.split(','))
+            .map(str::trim)
+    }
+
+    /// Where the body of the request ends, RFC 7230 section 3.3.3. Requests whose body length is
+    /// ambiguous are rejected, as the origin server could frame them differently.
+    fn body(&self) -> io::Result<RequestBody> {
+        let invalid = |message: &str| io::Error::new(ErrorKind::InvalidData, message.to_string());
+
+        let transfer_encoding = self.header_values("Transfer-Encoding").last();
+        let mut lengths = self.header_values("Content-Length");
+        match (transfer_encoding, lengths.next()) {
+            (Some(_), Some(_)) => Err(invalid(
+                "Request has both a Transfer-Encoding and a Content-Length",
+            )),
+            (Some(coding), None) if coding.eq_ignore_ascii_case("chunked") => {
+                Ok(RequestBody::chunked())
+            }
+            (Some(_), None) => Err(invalid("Request body isn't chunked")),
+            (None, Some(length)) => {
+                if lengths.any(|other| other != length) {
+                    return Err(invalid("Conflicting Content-Length headers"));
+                }
+                length
+                    .parse()
+                    .map(RequestBody::Length)
+                    .map_err(|_| invalid("Invalid Content-Length"))
+            }
+            (None, None) => Ok(RequestBody::Length(0)),
+        }
+    }
+
+    /// The user of a basic `Proxy-Authorization` header.
+    fn proxy_user(&self) -> Option<User> {
+        let credentials = self
+            .header("Proxy-Authorization")?
+            .strip_prefix("Basic ")
+            .and_then(|credentials| base64_decode(credentials.trim()))
+            .map(Zeroizing::new)?;
+        let (username, password) = std::str::from_utf8(&credentials).ok()?.split_once(':')?;
+
+        Some(User {
+            username: username.to_string(),
+            password: password.into(),
+        })
+    }
+
+    /// Splits an absolute `http://` URI into the destination, its authority and the origin-form
+    /// request target.
+    fn absolute_target(&self) -> io::Result<(SocksSocketAddr, &'a str, String)> {
+        let target = self.target;
+        let rest = target
+            .get(..7)
+            .filter(|scheme| scheme.eq_ignore_ascii_case("http://"))
+            .map(|_| &target[7..])
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "Expected an absolute URI"))?;
+
+        let authority_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+        let (authority, path) = rest.split_at(authority_end);
+        // Credentials in the URI are never forwarded
+        let authority = authority.rsplit('@').next().unwrap_or(authority);
+        let destination = parse_authority(authority, Some(80))?;
+
+        let path = match path {
+            "" => "/".to_string(),
+            path if path.starts_with('/') => path.to_string(),
+            path => format!("/{}", path),
+        };
+        Ok((destination, authority, path))
+    }
+
+    /// Rewrites the request for the origin server. The `Host` header is replaced with the
+    /// authority of the URI, RFC 7230 section 5.4, so it names the destination that was checked.
+    fn to_origin_form(&self, path: &str, authority: &str) -> Vec<u8> {
+        let mut request = format!("{} {} {}\r\n", self.method, path, self.version);
+        for (name, value) in &self.headers {
+            if name.eq_ignore_ascii_case("Host")
+                || HOP_BY_HOP_HEADERS
+                    .iter()
+                    .any(|header| name.eq_ignore_ascii_case(header))
+            {
+                continue;
+            }
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        request.push_str(&format!("Host: {}\r\n", authority));
+        request.push_str("Connection: close\r\n\r\n");
+        request.into_bytes()
+    }
+}
+
+impl<T, Auth, C, B, A> Socks5Socket<T, Auth, C, B, A>
+where
+    Self: Unpin + Send,
+    T: AsyncRead + AsyncWrite + Unpin + Send,
+    Auth: Authenticator<T>,
+{
+    /// Handles an HTTP proxy request whose first byte was already read. `CONNECT` requests are
+    /// tunneled, requests with an absolute URI are forwarded if enabled.
+    #[instrument(skip(self))]
+    pub(crate) async fn http_request(
+        &mut self,
+        first: u8,
+    ) -> io::Result<(Command, SocksSocketAddr, Auth::Credentials)> {
+        self.version = Version::HttpConnect;
+        let (header, read_ahead) = self.read_http_header(first).await?;
+        // The header, and pipelined requests read ahead, may hold the credentials of the client
+        let header = Zeroizing::new(header);
+        let mut read_ahead = Zeroizing::new(read_ahead);
+
+        let request = match HttpRequest::parse(&header) {
+            Ok(request) => request,
+            Err(err) => {
+                self.write_http_response(400, "Bad Request", "").await?;
+                return Err(err);
+            }
+        };
+
+        let destination = if request.method.eq_ignore_ascii_case("CONNECT") {
+            self.prefix = std::mem::take(&mut *read_ahead);
+            parse_authority(request.target, None)
+        } else if self.http_forwarding {
+            self.version = Version::HttpForward;
+            // Only this request is forwarded, the client's later requests would skip the
+            // authentication and the checks of their destination
+            request
+                .absolute_target()
+                .and_then(|(destination, authority, path)| {
+                    let mut body = request.body()?;
+                    let body_len = body.consume(&read_ahead);
+                    if body_len < read_ahead.len() {
+                        debug!(
+                            "Dropped {} bytes sent after the forwarded request",
+                            read_ahead.len() - body_len
+                        );
+                    }
+                    self.prefix = request.to_origin_form(&path, authority);
+                    self.prefix.extend_from_slice(&read_ahead[..body_len]);
+                    self.request_body = Some(body);
+                    Ok(destination)
+                })
+        } else {
+            self.http_reply(Reply::CommandNotSupported).await?;
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Unsupported HTTP method: {}", request.method),
+            ));
+        };
+        let destination = match destination {
+            Ok(destination) => destination,
+            Err(err) => {
+                self.write_http_response(400, "Bad Request", "").await?;
+                return Err(err);
+            }
+        };
+        info!("HTTP {} to {}", request.method, destination);
+
+        let user = request.proxy_user();
+        let authentication = timeout(
+            self.timeouts.auth,
+            self.authenticator
+                .authenticate_http(user, &mut self.context),
+        )
+        .await
+        .unwrap_or_else(|| {
+            Err(io::Error::new(
+                ErrorKind::TimedOut,
+                "Authentication timed out",
+            ))
+        });
+        let authentication = self.auth_outcome(authentication)?;
+        let Some(credentials) = authentication else {
+            self.write_http_response(
+                407,
+                "Proxy Authentication Required",
+                "Proxy-Authenticate: Basic realm=\"gerevs\"\r\n",
+            )
+            .await?;
+            return Err(io::Error::new(
+                ErrorKind::PermissionDenied,
+                "Authentication failed",
+            ));
+        };
+        debug!("Authentication success");
+
+        Ok((Command::Connect, destination, credentials))
+    }
+
+    /// Reads the request header, returning it together with the bytes read after it.
+    async fn read_http_header(&mut self, first: u8) -> io::Result<(Vec<u8>, Vec<u8>)> {
+        let mut buf = vec![first];
+        let mut chunk = [0; 1024];
+        let mut searched = 0;
+        loop {
+            if let Some(end) = buf[searched..]
+                .windows(4)
+                .position(|window| window == b"\r\n\r\n")
+            {
+                let read_ahead = buf.split_off(searched + end + 4);
+                return Ok((buf, read_ahead));
+            }
+            // The end of the header may span the previous and the next read
+            searched = buf.len().saturating_sub(3);
+
+            if buf.len() > MAX_HTTP_HEADER {
+                self.write_http_response(431, "Request Header Fields Too Large", "")
+                    .await?;
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "HTTP request header too long",
+                ));
+            }
+
+            let n = self.inner.read(&mut chunk).await?;
+            if n == 0 {
+                return Err(io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "Connection closed during HTTP request",
+                ));
+            }
+            buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+}
+
+impl<T, Auth, C, B, A> Socks5Socket<T, Auth, C, B, A>
+where
+    Self: Unpin + Send,
+    T: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    /// Answers an HTTP request, a successful forwarded request is answered by the origin server.
+    pub(crate) async fn http_reply(&mut self, reply: Reply) -> io::Result<()> {
+        match (reply, self.version) {
+            (Reply::Success, Version::HttpForward) => Ok(()),
+            (Reply::Success, _) => {
+                let (status, reason) = http_status(reply);
+                self.inner
+                    .write_all(format!("HTTP/1.1 {} {}\r\n\r\n", status, reason).as_bytes())
+                    .await?;
+                self.inner.flush().await
+            }
+            (reply, _) => {
+                let (status, reason) = http_status(reply);
+                self.write_http_response(status, reason, "").await
+            }
+        }
+    }
+
+    async fn write_http_response(
+        &mut self,
+        status: u16,
+        reason: &str,
+        headers: &str,
+    ) -> io::Result<()> {
+        let response = format!(
+            "HTTP/1.1 {} {}\r\n{}Content-Length: 0\r\nConnection: close\r\n\r\n",
+            status, reason, headers
+        );
+        self.inner.write_all(response.as_bytes()).await?;
+        self.inner.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{duplex, AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use crate::{
+        auth::NoAuthAuthenticator,
+        method_handlers::{TunnelAssociate, TunnelBind, TunnelConnect},
+        protocol::Addr,
+    };
+
+    use super::*;
+
+    fn parse(header: &str) -> HttpRequest<'_> {
+        HttpRequest::parse(header.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn parses_request() {
+        let request =
+            parse("GET http://example.com/a HTTP/1.1\r\nHost: example.com\r\nX-A:  b \r\n\r\n");
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.target, "http://example.com/a");
+        assert_eq!(request.header("x-a"), Some("b"));
+
+        for invalid in [
+            "GET http://example.com/ HTTP/2\r\n\r\n",
+            "GET  http://example.com/ HTTP/1.1\r\n\r\n",
+            "GET http://example.com/ HTTP/1.1\r\nNo colon\r\n\r\n",
+        ] {
+            assert!(HttpRequest::parse(invalid.as_bytes()).is_err());
+        }
+    }
+
+    #[test]
+    fn decodes_basic_proxy_authorization() {
+        let request = parse("GET / HTTP/1.1\r\nProxy-Authorization: Basic YWxpY2U6czpl\r\n\r\n");
+        let user = request.proxy_user().unwrap();
+        assert_eq!(user.username, "alice");
+        assert_eq!(user.password, "s:e");
+
+        for invalid in ["Bearer YWxpY2U6czpl", "Basic !!!!", "Basic YWxpY2U="] {
+            let header = format!("GET / HTTP/1.1\r\nProxy-Authorization: {}\r\n\r\n", invalid);
+            assert!(parse(&header).proxy_user().is_none());
+        }
+    }
+
+    #[test]
+    fn rewrites_request_to_origin_form() {
+        let request = parse(
+            "POST http://user@example.com:8080?q HTTP/1.1\r\nHost: internal.example\r\n\
+             Proxy-Authorization: Basic YTpi\r\nConnection: keep-alive\r\nAccept: */*\r\n\r\n",
+        );
+        let (destination, authority, path) = request.absolute_target().unwrap();
+        assert_eq!(destination.addr, Addr::Domain("example.com".to_string()));
+        assert_eq!(destination.port, 8080);
+        assert_eq!(authority, "example.com:8080");
+        assert_eq!(path, "/?q");
+
+        assert_eq!(
+            request.to_origin_form(&path, authority),
+            b"POST /?q HTTP/1.1\r\nAccept: */*\r\nHost: example.com:8080\r\nConnection: close\r\n\r\n"
+        );
+        assert!(parse("GET https://example.com/ HTTP/1.1\r\n\r\n")
+            .absolute_target()
+            .is_err());
+    }
+
+    #[test]
+    fn rejects_ambiguous_bodies() {
+        let body = |headers: &str| parse(&format!("POST / HTTP/1.1\r\n{}\r\n", headers)).body();
+
+        assert_eq!(body("").unwrap(), RequestBody::Length(0));
+        assert_eq!(
+            body("Content-Length: 4\r\nContent-Length: 4\r\n").unwrap(),
+            RequestBody::Length(4)
+        );
+        assert_eq!(
+            body("Transfer-Encoding: gzip, chunked\r\n").unwrap(),
+            RequestBody::chunked()
+        );
+        assert!(body("Content-Length: 4\r\nContent-Length: 5\r\n").is_err());
+        assert!(body("Content-Length: 4\r\nTransfer-Encoding: chunked\r\n").is_err());
+        assert!(body("Transfer-Encoding: chunked, gzip\r\n").is_err());
+        assert!(body("Content-Length: -1\r\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_header_too_long() {
+        let (mut client, server) = duplex(64 * 1024);
+        let mut socket = Socks5Socket::new(
+            server,
+            NoAuthAuthenticator,
+            TunnelConnect::new(),
+            TunnelBind::new(),
+            TunnelAssociate::new(),
+        );
+
+        let mut request = b"GET http://example.com/ HTTP/1.1\r\n".to_vec();
+        request.extend(std::iter::repeat_n(b'a', MAX_HTTP_HEADER));
+        client.write_all(&request).await.unwrap();
+
+        let err = socket.read_http_header(b'G').await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        drop(socket);
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 431 "));
+    }
+
+    #[tokio::test]
+    async fn forwards_a_single_request() {
+        let origin = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let origin_addr = origin.local_addr().unwrap();
+        let (mut client, server) = duplex(64 * 1024);
+        let socket = Socks5Socket::new(
+            server,
+            NoAuthAuthenticator,
+            TunnelConnect::new(),
+            TunnelBind::new(),
+            TunnelAssociate::new(),
+        )
+        .with_http_forwarding(true);
+        let session = tokio::spawn(socket.run());
+
+        // A body, followed by a pipelined request that must not reach the origin
+        let request = format!(
+            "POST http://{}/upload HTTP/1.1\r\nHost: internal.example\r\nContent-Length: 4\r\n\r\n\
+             body\
+             GET http://{}/secret HTTP/1.1\r\nProxy-Authorization: Basic YTpi\r\n\r\n",
+            origin_addr, origin_addr
+        );
+        client.write_all(request.as_bytes()).await.unwrap();
+
+        let (mut stream, _) = origin.accept().await.unwrap();
+        let expected = format!(
+            "POST /upload HTTP/1.1\r\nContent-Length: 4\r\nHost: {}\r\nConnection: close\r\n\r\nbody",
+            origin_addr
+        );
+        let mut received = vec![0; expected.len()];
+        stream.read_exact(&mut received).await.unwrap();
+        assert_eq!(String::from_utf8(received).unwrap(), expected);
+
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut rest = Vec::new();
+        stream.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+
+        stream
+            .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+            .await
+            .unwrap();
+        drop(stream);
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert_eq!(response, "HTTP/1.1 204 No Content\r\n\r\n");
+        session.await.unwrap().unwrap();
+    }
+}
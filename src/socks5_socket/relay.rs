@@ -0,0 +1,261 @@
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    select,
+    time::Instant,
+};
+
+use crate::{metrics::Metrics, observer::RelayStats, protocol::http::RequestBody};
+
+/// Tracks when data last flowed through a relayed stream.
+#[derive(Debug)]
+pub(crate) struct Activity {
+    started: Instant,
+    last_activity_ms: AtomicU64,
+    bytes_up: AtomicU64,
+    bytes_down: AtomicU64,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            last_activity_ms: AtomicU64::new(0),
+            bytes_up: AtomicU64::new(0),
+            bytes_down: AtomicU64::new(0),
+        }
+    }
+
+    fn stats(&self) -> RelayStats {
+        RelayStats {
+            bytes_up: self.bytes_up.load(Ordering::Relaxed),
+            bytes_down: self.bytes_down.load(Ordering::Relaxed),
+            duration: self.started.elapsed(),
+        }
+    }
+
+    fn touch(&self) {
+        let elapsed = self.started.elapsed().as_millis() as u64;
+        self.last_activity_ms.store(elapsed, Ordering::Relaxed);
+    }
+
+    fn last_activity(&self) -> Instant {
+        self.started + Duration::from_millis(self.last_activity_ms.load(Ordering::Relaxed))
+    }
+
+    /// Resolves once no data flowed for `idle`.
+    async fn idle(&self, idle: Duration) {
+        loop {
+            let deadline = self.last_activity() + idle;
+            if Instant::now() >= deadline {
+                return;
+            }
+            tokio::time::sleep_until(deadline).await;
+        }
+    }
+}
+
+/// The client stream handed to the method handlers while relaying, records activity in both
+/// directions.
+pub(crate) struct TrackedStream<T> {
+    inner: T,
+    activity: Arc<Activity>,
+    metrics: Option<Arc<Metrics>>,
+}
+
+impl<T> AsyncRead for TrackedStream<T>
+where
+    T: AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        let res = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if res.is_ready() {
+            this.activity.touch();
+        }
+        if let Poll::Ready(Ok(())) = res {
+            let read = buf.filled().len() - filled;
+            this.activity
+                .bytes_up
+                .fetch_add(read as u64, Ordering::Relaxed);
+            if let Some(metrics) = &this.metrics {
+                metrics.relayed_up(read);
+            }
+        }
+        res
+    }
+}
+
+impl<T> AsyncWrite for TrackedStream<T>
+where
+    T: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let res = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if res.is_ready() {
+            this.activity.touch();
+        }
+        if let Poll::Ready(Ok(written)) = res {
+            this.activity
+                .bytes_down
+                .fetch_add(written as u64, Ordering::Relaxed);
+            if let Some(metrics) = &this.metrics {
+                metrics.relayed_down(written);
+            }
+        }
+        res
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// A stream that yields `prefix` before reading from `inner`, used to relay bytes that were read
+/// ahead while parsing the request.
+///
+/// With a `body`, only the bytes of the body are read from `inner`, what the client sends after
+/// it is dropped until the client closes the connection.
+#[derive(Debug)]
+pub(crate) struct Prefixed<T> {
+    prefix: Vec<u8>,
+    pos: usize,
+    inner: T,
+    body: Option<RequestBody>,
+}
+
+impl<T> Prefixed<T> {
+    pub(crate) fn new(inner: T, prefix: Vec<u8>) -> Self {
+        Self {
+            prefix,
+            pos: 0,
+            inner,
+            body: None,
+        }
+    }
+
+    /// Limits the bytes read from `inner` to `body`.
+    pub(crate) fn with_body(mut self, body: Option<RequestBody>) -> Self {
+        self.body = body;
+        self
+    }
+}
+
+impl<T> AsyncRead for Prefixed<T>
+where
+    T: AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.pos < this.prefix.len() {
+            let len = buf.remaining().min(this.prefix.len() - this.pos);
+            buf.put_slice(&this.prefix[this.pos..this.pos + len]);
+            this.pos += len;
+            if this.pos == this.prefix.len() {
+                this.prefix = Vec::new();
+                this.pos = 0;
+            }
+            return Poll::Ready(Ok(()));
+        }
+
+        let Some(body) = &mut this.body else {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        };
+        loop {
+            let filled = buf.filled().len();
+            ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+            if buf.filled().len() == filled {
+                return Poll::Ready(Ok(()));
+            }
+
+            let body_len = body.consume(&buf.filled()[filled..]);
+            buf.set_filled(filled + body_len);
+            if body_len > 0 {
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+}
+
+impl<T> AsyncWrite for Prefixed<T>
+where
+    T: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Hands `inner` to `start_listening` wrapped in a `TrackedStream`, closing the relay once it was
+/// idle for longer than `idle`. The relayed bytes are recorded in `metrics`.
+///
+/// - Returns: The result of the relay together with the traffic relayed.
+pub(crate) async fn relay<T, F, Fut>(
+    inner: T,
+    idle: Option<Duration>,
+    metrics: Option<Arc<Metrics>>,
+    start_listening: F,
+) -> (crate::Result<()>, RelayStats)
+where
+    F: FnOnce(TrackedStream<T>) -> Fut,
+    Fut: Future<Output = crate::Result<()>>,
+{
+    let activity = Arc::new(Activity::new());
+    let relay = start_listening(TrackedStream {
+        inner,
+        activity: activity.clone(),
+        metrics,
+    });
+
+    let res = match idle {
+        None => relay.await,
+        Some(idle) => select! {
+            res = relay => res,
+            _ = activity.idle(idle) => {
+                Err(io::Error::new(io::ErrorKind::TimedOut, "Relay was idle for too long").into())
+            }
+        },
+    };
+    (res, activity.stats())
+}
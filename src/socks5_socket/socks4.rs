@@ -0,0 +1,126 @@
+use std::{
+    io::{self, ErrorKind},
+    net::Ipv4Addr,
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tracing::{debug, info, instrument};
+
+use crate::{
+    auth::Authenticator,
+    protocol::{
+        Addr, Command, Socks4Reply, SocksSocketAddr, SOCKS4_MAX_FIELD_LEN, SOCKS4_REPLY_VERSION,
+    },
+};
+
+use super::{timeouts::timeout, Socks5Socket};
+
+impl<T, Auth, C, B, A> Socks5Socket<T, Auth, C, B, A>
+where
+    Self: Unpin + Send,
+    T: AsyncRead + AsyncWrite + Unpin + Send,
+    Auth: Authenticator<T>,
+{
+    /// Parses a SOCKS4 or SOCKS4a request whose version byte was already read, and authenticates
+    /// the user id it carries.
+    #[instrument(skip(self))]
+    pub(crate) async fn socks4_request(
+        &mut self,
+    ) -> io::Result<(Command, SocksSocketAddr, Auth::Credentials)> {
+        let command = self.inner.read_u8().await?;
+        let port = self.inner.read_u16().await?;
+        let ip = Ipv4Addr::from(self.inner.read_u32().await?);
+        let user_id = self.read_null_terminated().await?;
+
+        let command = match Command::from_u8(command) {
+            Some(command @ (Command::Connect | Command::Bind)) => command,
+            _ => {
+                self.socks4_reply(Socks4Reply::RejectedOrFailed, Default::default())
+                    .await?;
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "Invalid SOCKS4 command value",
+                ));
+            }
+        };
+
+        // SOCKS4a marks a domain name following the user id with the address 0.0.0.x, x != 0
+        let addr = match ip.octets() {
+            [0, 0, 0, last] if last != 0 => Addr::Domain(self.read_null_terminated().await?),
+            _ => Addr::Ipv4(ip),
+        };
+        let addr = SocksSocketAddr { port, addr };
+        info!("SOCKS4 command: {:?}, dst: {}", command, addr);
+
+        let authentication = timeout(
+            self.timeouts.auth,
+            self.authenticator
+                .authenticate_socks4(&user_id, &mut self.context),
+        )
+        .await
+        .unwrap_or_else(|| {
+            Err(io::Error::new(
+                ErrorKind::TimedOut,
+                "Authentication timed out",
+            ))
+        });
+        let authentication = self.auth_outcome(authentication)?;
+        let Some(credentials) = authentication else {
+            self.socks4_reply(Socks4Reply::UserIdRejected, Default::default())
+                .await?;
+            return Err(io::Error::new(
+                ErrorKind::PermissionDenied,
+                "Authentication failed",
+            ));
+        };
+        debug!("Authentication success");
+
+        Ok((command, addr, credentials))
+    }
+
+    async fn read_null_terminated(&mut self) -> io::Result<String> {
+        let mut field = Vec::new();
+        loop {
+            match self.inner.read_u8().await? {
+                0 => break,
+                _ if field.len() == SOCKS4_MAX_FIELD_LEN => {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        "SOCKS4 field too long",
+                    ));
+                }
+                byte => field.push(byte),
+            }
+        }
+
+        String::from_utf8(field)
+            .map_err(|_| io::Error::new(ErrorKind::InvalidData, "Invalid UTF-8 in SOCKS4 field"))
+    }
+}
+
+impl<T, Auth, C, B, A> Socks5Socket<T, Auth, C, B, A>
+where
+    Self: Unpin + Send,
+    T: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    /// Writes a SOCKS4 reply, only IPv4 addresses can be reported so others are sent as 0.0.0.0.
+    pub(crate) async fn socks4_reply(
+        &mut self,
+        reply: Socks4Reply,
+        bnd_address: SocksSocketAddr,
+    ) -> io::Result<()> {
+        let ip = match bnd_address.addr {
+            Addr::Ipv4(ip) => ip,
+            Addr::Ipv6(ip) => ip.to_ipv4_mapped().unwrap_or(Ipv4Addr::UNSPECIFIED),
+            Addr::Domain(_) => Ipv4Addr::UNSPECIFIED,
+        };
+
+        self.inner.write_u8(SOCKS4_REPLY_VERSION).await?;
+        self.inner.write_u8(reply.to_u8()).await?;
+        self.inner.write_u16(bnd_address.port).await?;
+        self.inner.write_all(&ip.octets()).await?;
+        self.inner.flush().await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,33 @@
+use std::{future::Future, time::Duration};
+
+/// The `Timeouts` struct configures the deadlines a `Socks5Socket` enforces on the different
+/// phases of a session. Every timeout is optional, and `Timeouts::default()` disables all of them.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Deadline for the whole handshake: method negotiation, authentication and the request.
+    pub handshake: Option<Duration>,
+
+    /// Deadline for the authentication sub-negotiation performed by the `Authenticator`.
+    pub auth: Option<Duration>,
+
+    /// Deadline for establishing the CONNECT connection, and for the incoming connection of a
+    /// BIND request. Replies with `TTLExpired` when exceeded.
+    pub connect: Option<Duration>,
+
+    /// Time a CONNECT or BIND relay may go without data flowing in either direction before it's closed.
+    pub idle: Option<Duration>,
+
+    /// Time a UDP association may go without datagrams being relayed before it's closed.
+    pub udp_idle: Option<Duration>,
+}
+
+/// Awaits `future` for at most `duration`, returns `None` if the deadline was reached first.
+pub(crate) async fn timeout<F>(duration: Option<Duration>, future: F) -> Option<F::Output>
+where
+    F: Future,
+{
+    match duration {
+        Some(duration) => tokio::time::timeout(duration, future).await.ok(),
+        None => Some(future.await),
+    }
+}